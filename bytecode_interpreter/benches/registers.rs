@@ -0,0 +1,92 @@
+// Compares the stack interpreter with the register machine on loops, run by `cargo bench`
+use std::time::{Duration, Instant};
+
+use bytecode_interpreter::ByteCode;
+
+// sum of 1..=n
+const SUM: &str = r#"
+LOAD_VAL 100000
+WRITE_VAR n
+LOAD_VAL 0
+WRITE_VAR sum
+READ_VAR n
+LOAD_VAL 0
+LOAD_VAL 10
+JUMP_GREATER_THAN
+READ_VAR sum
+RETURN_VALUE
+READ_VAR sum
+READ_VAR n
+ADD
+WRITE_VAR sum
+READ_VAR n
+LOAD_VAL 1
+SUB
+WRITE_VAR n
+LOAD_VAL 4
+JUMP
+"#;
+
+// n-th Fibonacci number modulo 2^128, the next number is kept on the stack
+const FIBONACCI: &str = r#"
+LOAD_VAL 100000
+WRITE_VAR n
+LOAD_VAL 0
+WRITE_VAR a
+LOAD_VAL 1
+READ_VAR n
+LOAD_VAL 0
+LOAD_VAL 21
+JUMP_EQUAL
+WRITE_VAR b
+READ_VAR b
+READ_VAR a
+ADD_WRAP
+READ_VAR b
+WRITE_VAR a
+READ_VAR n
+LOAD_VAL 1
+SUB
+WRITE_VAR n
+LOAD_VAL 5
+JUMP
+READ_VAR a
+RETURN_VALUE
+"#;
+
+// The fastest of several runs
+fn measure(mut run: impl FnMut() -> u128) -> (Duration, u128) {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            let result = run();
+            (start.elapsed(), result)
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for (name, program) in [("sum", SUM), ("fibonacci", FIBONACCI)] {
+        let bytecode = ByteCode::from_bytecode_text(program).unwrap();
+        let registers = bytecode.to_registers().unwrap();
+        let (stack_time, stack_result) = measure(|| {
+            let mut bytecode = ByteCode::from_bytecode_text(program).unwrap();
+            bytecode.set_workers(1);
+            bytecode.interpret().unwrap();
+            *bytecode.ret().unwrap()
+        });
+        let (register_time, register_result) = measure(|| registers.run(&[]).unwrap());
+        assert_eq!(stack_result, register_result);
+        println!(
+            "{:<10} stack: {:>4} instructions {:>10.2?}, registers: {:>4} operations {:>10.2?}, \
+             {:.1}x",
+            name,
+            bytecode.instructions().len(),
+            stack_time,
+            registers.ops().len(),
+            register_time,
+            stack_time.as_secs_f64() / register_time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,558 @@
+// Control-flow graph of instructions. A jump has a constant target, if `LOAD_VAL` is right before
+// it in the same block, otherwise the jump is computed, and its target is unknown. Starts of
+// threads are found the same way for `SPAWN` and `SPAWN_IDS`, whose four operands are `LOAD_VAL`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use crate::{Data, IndexedInstruction, Instruction};
+
+// Instructions at `start..end`, which are always executed one after another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Successor {
+    Block(usize),
+    // The target of a computed jump or spawn
+    Unknown,
+    // A position after the instructions, whose thread fails
+    OutOfRange(Data),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    FallThrough,
+    Jump,
+    Taken,
+    NotTaken,
+    // The start of a thread, which is spawned by the block
+    Spawn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Successor,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub header: usize,
+    // Blocks, which jump back to the header
+    pub latches: Vec<usize>,
+    pub blocks: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+    // Blocks, where threads start, the root thread starts at the first one
+    pub entries: Vec<usize>,
+    // Source lines and positions of instructions for the export, lines are 1-based like in parse
+    // errors
+    lines: Vec<String>,
+}
+
+impl Cfg {
+    pub fn new(instructions: &[IndexedInstruction]) -> Self {
+        let len = instructions.len();
+        let constant = |position: usize| match instructions.get(position)?.instruction() {
+            Instruction::LoadVal(value) => Some(*value),
+            _ => None,
+        };
+        let target = |position: usize| usize::try_from(constant(position)?).ok();
+
+        let mut leaders = BTreeSet::new();
+        if len > 0 {
+            leaders.insert(0);
+        }
+        for (position, instruction) in instructions.iter().enumerate() {
+            let instruction = instruction.instruction();
+            if instruction.is_jump() || *instruction == Instruction::RetVal {
+                leaders.insert(position + 1);
+            }
+            if instruction.is_jump() && position > 0 {
+                leaders.extend(target(position - 1));
+            }
+            if instruction.is_spawn() && position > 2 {
+                leaders.extend(target(position - 1));
+                leaders.extend(target(position - 3));
+            }
+        }
+        leaders.retain(|leader| *leader < len);
+        let leaders: Vec<_> = leaders.into_iter().collect();
+        let blocks: Vec<Block> = leaders
+            .iter()
+            .zip(leaders.iter().skip(1).chain([&len]))
+            .map(|(start, end)| Block {
+                start: *start,
+                end: *end,
+            })
+            .collect();
+
+        let mut cfg = Self {
+            blocks,
+            edges: Vec::new(),
+            entries: Vec::new(),
+            lines: instructions
+                .iter()
+                .enumerate()
+                .map(|(position, instruction)| {
+                    format!(
+                        "{}:{}  {:>3}: {}",
+                        instruction.file().map_or("<input>", |file| file),
+                        instruction.index() + 1,
+                        position,
+                        instruction.instruction()
+                    )
+                })
+                .collect(),
+        };
+        if len > 0 {
+            cfg.entries.push(0);
+        }
+        for (id, block) in cfg.blocks.clone().into_iter().enumerate() {
+            // The operand at the position is constant, if it's pushed in the same block
+            let operand = |position: usize| {
+                (position >= block.start)
+                    .then(|| constant(position))
+                    .flatten()
+            };
+            let successor = |position: Option<Data>| match position {
+                Some(position) => cfg.successor(position, len),
+                None => Successor::Unknown,
+            };
+            let mut edges = Vec::new();
+            for (position, instruction) in instructions[block.start..block.end]
+                .iter()
+                .enumerate()
+                .map(|(i, instruction)| (block.start + i, instruction))
+            {
+                if !instruction.instruction().is_spawn() {
+                    continue;
+                }
+                let starts = match position.checked_sub(3) {
+                    Some(first) if (first..position).all(|p| operand(p).is_some()) => {
+                        [operand(first), operand(position - 1)]
+                    }
+                    _ => [None, operand(position.wrapping_sub(1))],
+                };
+                for start in starts {
+                    edges.push(Edge {
+                        from: id,
+                        to: successor(start),
+                        kind: EdgeKind::Spawn,
+                    });
+                }
+            }
+            let last = block.end - 1;
+            let jump_target = || successor(last.checked_sub(1).and_then(operand));
+            let next = cfg.successor(block.end as Data, len);
+            match instructions[last].instruction() {
+                Instruction::RetVal => {}
+                Instruction::Jump => edges.push(Edge {
+                    from: id,
+                    to: jump_target(),
+                    kind: EdgeKind::Jump,
+                }),
+                instruction if instruction.is_jump() => {
+                    edges.push(Edge {
+                        from: id,
+                        to: jump_target(),
+                        kind: EdgeKind::Taken,
+                    });
+                    edges.push(Edge {
+                        from: id,
+                        to: next,
+                        kind: EdgeKind::NotTaken,
+                    });
+                }
+                _ => edges.push(Edge {
+                    from: id,
+                    to: next,
+                    kind: EdgeKind::FallThrough,
+                }),
+            }
+            for edge in &edges {
+                if let (EdgeKind::Spawn, Successor::Block(start)) = (edge.kind, edge.to) {
+                    cfg.entries.push(start);
+                }
+            }
+            cfg.edges.extend(edges);
+        }
+        cfg.entries.sort_unstable();
+        cfg.entries.dedup();
+        cfg
+    }
+
+    fn successor(&self, position: Data, len: usize) -> Successor {
+        match usize::try_from(position) {
+            Ok(position) if position < len => Successor::Block(
+                self.block_of(position)
+                    .expect("Block of the position doesn't exist"),
+            ),
+            _ => Successor::OutOfRange(position),
+        }
+    }
+
+    pub fn block_of(&self, position: usize) -> Option<usize> {
+        let index = self.blocks.partition_point(|block| block.start <= position);
+        let id = index.checked_sub(1)?;
+        (position < self.blocks[id].end).then_some(id)
+    }
+
+    // Known blocks, which the block passes the control to, spawned threads aren't included
+    pub fn successors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.from == block && edge.kind != EdgeKind::Spawn)
+            .filter_map(|edge| match edge.to {
+                Successor::Block(to) => Some(to),
+                _ => None,
+            })
+    }
+
+    pub fn predecessors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.to == Successor::Block(block) && edge.kind != EdgeKind::Spawn)
+            .map(|edge| edge.from)
+    }
+
+    // Dominators of blocks, which are reachable from entries by known edges. Computed jumps may
+    // go anywhere, so the result is exact only without them.
+    pub fn dominators(&self) -> Dominators {
+        let len = self.blocks.len();
+        // The virtual root before all entries
+        let root = len;
+        let successors = |block: usize| -> Vec<usize> {
+            if block == root {
+                self.entries.clone()
+            } else {
+                self.successors(block).collect()
+            }
+        };
+
+        // Postorder by depth-first search
+        let mut order = vec![usize::MAX; len + 1];
+        let mut postorder = Vec::with_capacity(len + 1);
+        let mut visited = vec![false; len + 1];
+        let mut stack = vec![(root, successors(root), 0)];
+        visited[root] = true;
+        while let Some((block, next, i)) = stack.last_mut() {
+            if let Some(&successor) = next.get(*i) {
+                *i += 1;
+                if !visited[successor] {
+                    visited[successor] = true;
+                    stack.push((successor, successors(successor), 0));
+                }
+            } else {
+                order[*block] = postorder.len();
+                postorder.push(*block);
+                stack.pop();
+            }
+        }
+
+        let mut idom = vec![None; len + 1];
+        idom[root] = Some(root);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in postorder.iter().rev().skip(1) {
+                let mut predecessors: Vec<usize> = self.predecessors(block).collect();
+                if self.entries.contains(&block) {
+                    predecessors.push(root);
+                }
+                let mut new = None;
+                for predecessor in predecessors {
+                    if idom[predecessor].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => predecessor,
+                        Some(mut other) => {
+                            let mut finger = predecessor;
+                            while finger != other {
+                                while order[finger] < order[other] {
+                                    finger = idom[finger].expect("Dominator isn't computed");
+                                }
+                                while order[other] < order[finger] {
+                                    other = idom[other].expect("Dominator isn't computed");
+                                }
+                            }
+                            finger
+                        }
+                    });
+                }
+                if new.is_some() && idom[block] != new {
+                    idom[block] = new;
+                    changed = true;
+                }
+            }
+        }
+        Dominators {
+            immediate: idom[..len]
+                .iter()
+                .map(|idom| idom.filter(|idom| *idom != root))
+                .collect(),
+            reachable: visited[..len].to_vec(),
+        }
+    }
+
+    // Natural loops of back-edges, whose targets dominate their sources, by their headers
+    pub fn loops(&self) -> Vec<Loop> {
+        let dominators = self.dominators();
+        let mut loops: BTreeMap<usize, Loop> = BTreeMap::new();
+        for block in 0..self.blocks.len() {
+            for header in self.successors(block) {
+                if !dominators.dominates(header, block) {
+                    continue;
+                }
+                let looped = loops.entry(header).or_insert_with(|| Loop {
+                    header,
+                    latches: Vec::new(),
+                    blocks: BTreeSet::from([header]),
+                });
+                looped.latches.push(block);
+                let mut stack = vec![block];
+                while let Some(block) = stack.pop() {
+                    if looped.blocks.insert(block) {
+                        stack.extend(self.predecessors(block));
+                    }
+                }
+            }
+        }
+        loops.into_values().collect()
+    }
+
+    // Blocks with their source lines
+    pub fn to_dot(&self) -> String {
+        let mut output = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("block {}\\l", id);
+            for line in &self.lines[block.start..block.end] {
+                let _ = write!(label, "{}\\l", line);
+            }
+            let shape = if self.entries.contains(&id) {
+                ", peripheries=2"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                output,
+                "    b{} [label=\"{}\"{}];",
+                id,
+                label.replace('"', "\\\""),
+                shape
+            );
+        }
+        let mut outside = BTreeSet::new();
+        for edge in &self.edges {
+            let to = match edge.to {
+                Successor::Block(block) => format!("b{}", block),
+                Successor::Unknown => "unknown".to_string(),
+                Successor::OutOfRange(position) => format!("out{}", position),
+            };
+            if !matches!(edge.to, Successor::Block(_)) {
+                outside.insert(edge.to);
+            }
+            let style = match edge.kind {
+                EdgeKind::FallThrough => "",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Taken => " [label=\"taken\"]",
+                EdgeKind::NotTaken => " [label=\"not taken\", style=dashed]",
+                EdgeKind::Spawn => " [label=\"spawn\", style=dotted]",
+            };
+            let _ = writeln!(output, "    b{} -> {}{};", edge.from, to, style);
+        }
+        for successor in outside {
+            match successor {
+                Successor::Unknown => {
+                    output.push_str("    unknown [label=\"?\", shape=diamond];\n");
+                }
+                Successor::OutOfRange(position) => {
+                    let _ = writeln!(
+                        output,
+                        "    out{} [label=\"position {}\", shape=plaintext];",
+                        position, position
+                    );
+                }
+                Successor::Block(_) => {}
+            }
+        }
+        output.push_str("}\n");
+        output
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dominators {
+    // Entries and unreachable blocks don't have the immediate dominator
+    immediate: Vec<Option<usize>>,
+    reachable: Vec<bool>,
+}
+
+impl Dominators {
+    pub fn immediate(&self, block: usize) -> Option<usize> {
+        self.immediate.get(block).copied().flatten()
+    }
+
+    // Whether every path from entries to `b` goes through `a`
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        if !self.reachable.get(b).copied().unwrap_or_default() {
+            return false;
+        }
+        let mut block = Some(b);
+        while let Some(current) = block {
+            if current == a {
+                return true;
+            }
+            block = self.immediate(current);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{Block, Cfg, Edge, EdgeKind, Loop, Successor};
+    use crate::{ByteCode, IndexedInstruction};
+
+    // result = base ^ exponent
+    const POW: &str = "LOAD_VAL 1
+WRITE_VAR result
+READ_VAR exponent
+LOAD_VAL 0
+LOAD_VAL 8
+JUMP_GREATER_THAN
+READ_VAR result
+RETURN_VALUE
+READ_VAR result
+READ_VAR base
+MULTIPLY
+WRITE_VAR result
+READ_VAR exponent
+LOAD_VAL 1
+SUB
+WRITE_VAR exponent
+LOAD_VAL 2
+JUMP";
+
+    fn instructions(text: &str) -> Vec<IndexedInstruction> {
+        ByteCode::from_bytecode_text(text)
+            .unwrap()
+            .instructions()
+            .to_vec()
+    }
+
+    fn edge(from: usize, to: Successor, kind: EdgeKind) -> Edge {
+        Edge { from, to, kind }
+    }
+
+    #[test]
+    fn blocks_and_loops() {
+        let cfg = Cfg::new(&instructions(POW));
+        let blocks: Vec<_> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(blocks, vec![(0, 2), (2, 6), (6, 8), (8, 18)]);
+        assert_eq!(
+            cfg.edges,
+            vec![
+                edge(0, Successor::Block(1), EdgeKind::FallThrough),
+                edge(1, Successor::Block(3), EdgeKind::Taken),
+                edge(1, Successor::Block(2), EdgeKind::NotTaken),
+                edge(3, Successor::Block(1), EdgeKind::Jump),
+            ]
+        );
+        assert_eq!(cfg.entries, vec![0]);
+        assert_eq!(cfg.block_of(7), Some(2));
+        assert_eq!(cfg.block_of(18), None);
+        assert_eq!(cfg.predecessors(1).collect::<Vec<_>>(), vec![0, 3]);
+
+        let dominators = cfg.dominators();
+        let immediate: Vec<_> = (0..4).map(|b| dominators.immediate(b)).collect();
+        assert_eq!(immediate, vec![None, Some(0), Some(1), Some(1)]);
+        assert!(dominators.dominates(0, 3));
+        assert!(dominators.dominates(3, 3));
+        assert!(!dominators.dominates(2, 3));
+        assert_eq!(
+            cfg.loops(),
+            vec![Loop {
+                header: 1,
+                latches: vec![3],
+                blocks: BTreeSet::from([1, 3]),
+            }]
+        );
+    }
+
+    #[test]
+    fn unknown_targets_and_threads() {
+        let cfg = Cfg::new(&instructions(
+            "LOAD_VAL 2\nLOAD_VAL 2\nJUMP\nREAD_VAR x\nJUMP\nLOAD_VAL 99\nJUMP",
+        ));
+        assert_eq!(
+            cfg.edges,
+            vec![
+                edge(0, Successor::Block(1), EdgeKind::FallThrough),
+                // The target is pushed in another block
+                edge(1, Successor::Unknown, EdgeKind::Jump),
+                edge(2, Successor::Unknown, EdgeKind::Jump),
+                edge(3, Successor::OutOfRange(99), EdgeKind::Jump),
+            ]
+        );
+        let dominators = cfg.dominators();
+        assert_eq!(dominators.immediate(3), None);
+        assert!(!dominators.dominates(3, 3));
+        assert!(cfg.loops().is_empty());
+
+        let cfg = Cfg::new(&instructions(
+            "LOAD_VAL 0\nLOAD_VAL 6\nLOAD_VAL 0\nLOAD_VAL 8\nSPAWN\nRETURN_VALUE\n\
+             LOAD_VAL 1\nRETURN_VALUE\nREAD_VAR x\nREAD_VAR x\nLOAD_VAL 0\nSPAWN",
+        ));
+        assert_eq!(
+            cfg.blocks,
+            vec![
+                Block { start: 0, end: 6 },
+                Block { start: 6, end: 8 },
+                Block { start: 8, end: 12 },
+            ]
+        );
+        assert_eq!(
+            cfg.edges,
+            vec![
+                edge(0, Successor::Block(1), EdgeKind::Spawn),
+                edge(0, Successor::Block(2), EdgeKind::Spawn),
+                edge(2, Successor::Unknown, EdgeKind::Spawn),
+                edge(2, Successor::Block(0), EdgeKind::Spawn),
+                edge(2, Successor::OutOfRange(12), EdgeKind::FallThrough),
+            ]
+        );
+        assert_eq!(cfg.entries, vec![0, 1, 2]);
+        let dominators = cfg.dominators();
+        assert!(!dominators.dominates(0, 2));
+        assert!(dominators.dominates(2, 2));
+    }
+
+    #[test]
+    fn dot() {
+        let instructions = instructions("\nREAD_VAR x\nLOAD_VAL 0\nJUMP\nRETURN_VALUE");
+        let dot = Cfg::new(&instructions).to_dot();
+        assert_eq!(
+            dot,
+            "digraph cfg {\n    node [shape=box, fontname=monospace];\n    \
+             b0 [label=\"block 0\\l<input>:2    0: READ_VAR x\\l<input>:3    1: LOAD_VAL 0\\l\
+             <input>:4    2: JUMP\\l\", peripheries=2];\n    \
+             b1 [label=\"block 1\\l<input>:5    3: RETURN_VALUE\\l\"];\n    \
+             b0 -> b0 [label=\"jump\"];\n}\n"
+        );
+    }
+}
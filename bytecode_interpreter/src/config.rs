@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+
+use crate::{HostFunction, Observer};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    // A failure of a child is delivered to the parent, when the parent receives from the child or
+    // finishes
+    #[default]
+    Propagate,
+    // The first failure stops all threads of the tree
+    AbortTree,
+}
+
+// Handling of overflows by `ADD`, `SUB` and `MULTIPLY`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    // The thread fails
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // Maximum count of instructions, which all threads of the tree execute in one execution
+    pub gas: Option<u64>,
+    // Maximum count of live threads of the tree, including the root
+    pub max_threads: Option<usize>,
+    // Maximum depth of the operand stack of every thread
+    pub max_stack: Option<usize>,
+    // Maximum count of variables of every thread
+    pub max_vars: Option<usize>,
+    // Maximum count of bytes, which stacks and variables of all live threads of the tree take
+    pub max_heap: Option<usize>,
+    // Maximum count of ancestors of a thread, so children of the root have the depth 1
+    pub max_spawn_depth: Option<usize>,
+    // Maximum count of messages of the tree, which are sent, but not received yet
+    pub max_messages: Option<usize>,
+}
+
+// Resources, which are bounded by `Limits`, except the gas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    StackDepth,
+    Variables,
+    HeapBytes,
+    LiveThreads,
+    SpawnDepth,
+    MessagesInFlight,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Resource::StackDepth => "stack depth",
+            Resource::Variables => "variables",
+            Resource::HeapBytes => "heap bytes",
+            Resource::LiveThreads => "live threads",
+            Resource::SpawnDepth => "spawn depth",
+            Resource::MessagesInFlight => "messages in flight",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Stops the execution of all threads of the tree, when it is cancelled from any OS thread.
+// Clones share the same state.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Settings of a thread tree, children inherit them from the parent
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub failure_policy: FailurePolicy,
+    pub overflow: Overflow,
+    // Count of OS threads, which execute bytecodes of the tree
+    pub workers: usize,
+    pub limits: Limits,
+    // Whether interactions between threads are recorded for a replay
+    pub record: bool,
+    // Functions, which are called by `CALL_HOST`, by their names
+    pub host_functions: HashMap<String, HostFunction>,
+    // Receives executed instructions and logs instead of the standard output
+    pub observer: Option<Arc<dyn Observer>>,
+    pub cancellation: Option<CancellationToken>,
+    // Time, after which all threads of the tree fail
+    pub deadline: Option<Instant>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            failure_policy: FailurePolicy::default(),
+            overflow: Overflow::default(),
+            workers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            limits: Limits::default(),
+            record: false,
+            host_functions: HashMap::new(),
+            observer: None,
+            cancellation: None,
+            deadline: None,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{observer::Accumulator, Id, IndexedInstruction, Instruction, Observer, Step};
+
+// Executed instructions of all threads by their positions
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    pub hits: BTreeMap<usize, u64>,
+    // Counts of taken and not taken conditional jumps. A jump to the next position is counted as
+    // not taken, because both ways are the same.
+    pub branches: BTreeMap<usize, (u64, u64)>,
+}
+
+impl Coverage {
+    // Runs of the same program are merged into one report
+    pub fn merge(&mut self, other: &Coverage) {
+        for (position, hits) in &other.hits {
+            *self.hits.entry(*position).or_default() += hits;
+        }
+        for (position, (taken, not_taken)) in &other.branches {
+            let branch = self.branches.entry(*position).or_default();
+            branch.0 += taken;
+            branch.1 += not_taken;
+        }
+    }
+
+    // Instructions without a file are reported as `<input>`. Lines are 1-based, and a line of
+    // several instructions, e.g. of a macro, has hits of the most executed one.
+    pub fn to_lcov(&self, instructions: &[IndexedInstruction]) -> String {
+        let mut files: BTreeMap<&str, BTreeMap<usize, u64>> = BTreeMap::new();
+        let mut branches: BTreeMap<&str, Vec<(usize, usize)>> = BTreeMap::new();
+        for (position, instruction) in instructions.iter().enumerate() {
+            let file = instruction.file().map_or("<input>", |file| file);
+            let hits = self.hits.get(&position).copied().unwrap_or_default();
+            let line = files
+                .entry(file)
+                .or_default()
+                .entry(instruction.index() + 1)
+                .or_default();
+            *line = (*line).max(hits);
+            if is_conditional(instruction.instruction()) {
+                branches
+                    .entry(file)
+                    .or_default()
+                    .push((instruction.index() + 1, position));
+            }
+        }
+        let mut output = String::new();
+        for (file, lines) in files {
+            let _ = writeln!(output, "TN:\nSF:{}", file);
+            let (mut found, mut hit) = (0, 0);
+            for (line, position) in branches.get(file).into_iter().flatten() {
+                found += 2;
+                match self.branches.get(position) {
+                    Some(&(taken, not_taken)) => {
+                        hit += usize::from(taken > 0) + usize::from(not_taken > 0);
+                        let _ = writeln!(output, "BRDA:{},{},0,{}", line, position, taken);
+                        let _ = writeln!(output, "BRDA:{},{},1,{}", line, position, not_taken);
+                    }
+                    None => {
+                        let _ = writeln!(output, "BRDA:{},{},0,-", line, position);
+                        let _ = writeln!(output, "BRDA:{},{},1,-", line, position);
+                    }
+                }
+            }
+            if found > 0 {
+                let _ = writeln!(output, "BRF:{}\nBRH:{}", found, hit);
+            }
+            for (line, hits) in &lines {
+                let _ = writeln!(output, "DA:{},{}", line, hits);
+            }
+            let covered = lines.values().filter(|hits| **hits > 0).count();
+            let _ = writeln!(output, "LF:{}\nLH:{}\nend_of_record", lines.len(), covered);
+        }
+        output
+    }
+}
+
+fn is_conditional(instruction: &Instruction) -> bool {
+    instruction.is_jump() && *instruction != Instruction::Jump
+}
+
+// Records the coverage of all threads. Hits are gathered per slice of a thread and merged into the
+// coverage after the slice.
+#[derive(Debug, Default)]
+pub struct CoverageRecorder {
+    coverage: Accumulator<Coverage>,
+}
+
+impl CoverageRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coverage(&self) -> Coverage {
+        // Hits, which are executed by the caller outside of slices
+        self.coverage.flush(Coverage::merge);
+        self.coverage.lock().clone()
+    }
+}
+
+impl Observer for CoverageRecorder {
+    fn step(&self, step: &Step) {
+        self.coverage.update(|coverage| {
+            *coverage.hits.entry(step.position).or_default() += 1;
+            if is_conditional(step.instruction.instruction()) {
+                let branch = coverage.branches.entry(step.position).or_default();
+                if step.next == step.position + 1 {
+                    branch.1 += 1;
+                } else {
+                    branch.0 += 1;
+                }
+            }
+        });
+    }
+
+    fn flush(&self, _thread: Id) {
+        self.coverage.flush(Coverage::merge);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::VmBuilder;
+
+    // return the maximum of a and b, and count down from it
+    const MAX: &str = r#"
+READ_VAR a
+READ_VAR b
+LOAD_VAL 7
+JUMP_LESS_THAN
+READ_VAR a
+LOAD_VAL 8
+JUMP
+READ_VAR b
+WRITE_VAR max
+READ_VAR max
+LOAD_VAL 0
+LOAD_VAL 18
+JUMP_EQUAL
+READ_VAR max
+LOAD_VAL 1
+SUB
+LOAD_VAL 8
+JUMP
+READ_VAR max
+RETURN_VALUE
+"#;
+
+    #[test]
+    fn lines_and_branches() {
+        let run = |a, b| {
+            VmBuilder::from_bytecode_text(MAX)
+                .unwrap()
+                .var("a", a)
+                .var("b", b)
+                .coverage()
+                .run()
+                .unwrap()
+        };
+        let outcome = run(2, 1);
+        let instructions = crate::ByteCode::from_bytecode_text(MAX)
+            .unwrap()
+            .instructions()
+            .to_vec();
+        let coverage = outcome.coverage.unwrap();
+        assert_eq!(coverage.hits.get(&7), None);
+        assert_eq!(coverage.hits[&8], 3);
+        assert_eq!(coverage.branches[&3], (0, 1));
+        assert_eq!(coverage.branches[&12], (1, 2));
+        assert_eq!(
+            coverage.to_lcov(&instructions),
+            "TN:\nSF:<input>\n\
+             BRDA:5,3,0,0\nBRDA:5,3,1,1\nBRDA:14,12,0,1\nBRDA:14,12,1,2\nBRF:4\nBRH:3\n\
+             DA:2,1\nDA:3,1\nDA:4,1\nDA:5,1\nDA:6,1\nDA:7,1\nDA:8,1\nDA:9,0\nDA:10,3\n\
+             DA:11,3\nDA:12,3\nDA:13,3\nDA:14,3\nDA:15,2\nDA:16,2\nDA:17,2\nDA:18,2\nDA:19,2\n\
+             DA:20,1\nDA:21,1\nLF:20\nLH:19\nend_of_record\n"
+        );
+
+        let mut merged = coverage.clone();
+        merged.merge(&run(1, 2).coverage.unwrap());
+        assert_eq!(merged.hits[&7], 1);
+        assert_eq!(merged.branches[&3], (1, 1));
+        assert!(merged.to_lcov(&instructions).contains("BRH:4\n"));
+        assert!(merged.to_lcov(&instructions).contains("LH:20\n"));
+    }
+}
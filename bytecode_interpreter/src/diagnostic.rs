@@ -0,0 +1,125 @@
+// Errors of parsing, which point at the text in the source:
+//
+// main.bc:3:1: error: Unknown instruction `ADDD`
+//   |
+// 3 | ADDD 5
+//   | ^^^^ did you mean `ADD`?
+
+use std::fmt;
+
+// Characters of a line, columns are 0-based
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub(crate) column: usize,
+    pub(crate) width: usize,
+}
+
+impl Span {
+    // The place right after the span, where a missing word is expected
+    pub(crate) fn after(self) -> Self {
+        Self {
+            column: self.column + self.width + 1,
+            width: 1,
+        }
+    }
+}
+
+// Words of the line with their spans. Words after `//` are a comment.
+pub(crate) fn words(text: &str) -> impl Iterator<Item = (&str, Span)> {
+    text.split_ascii_whitespace()
+        .take_while(|word| !word.starts_with("//"))
+        .map(move |word| {
+            let start = word.as_ptr() as usize - text.as_ptr() as usize;
+            let span = Span {
+                column: text[..start].chars().count(),
+                width: word.chars().count(),
+            };
+            (word, span)
+        })
+}
+
+pub(crate) struct Diagnostic<'a> {
+    pub(crate) file: Option<&'a str>,
+    // 0-based like indices of instructions, but shown 1-based
+    pub(crate) line: usize,
+    pub(crate) text: &'a str,
+    pub(crate) span: Span,
+    pub(crate) message: &'a str,
+    pub(crate) help: Option<&'a str>,
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = (self.line + 1).to_string();
+        let gutter = " ".repeat(line.len());
+        writeln!(
+            f,
+            "{}:{}:{}: error: {}",
+            self.file.unwrap_or("<input>"),
+            line,
+            self.span.column + 1,
+            self.message
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line, self.text)?;
+        // Tabs are kept, so carets are under the text
+        let mut indent: String = self
+            .text
+            .chars()
+            .take(self.span.column)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let missing = self.span.column.saturating_sub(self.text.chars().count());
+        indent.push_str(&" ".repeat(missing));
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            indent,
+            "^".repeat(self.span.width.max(1))
+        )?;
+        if let Some(help) = self.help {
+            write!(f, " {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ByteCode;
+
+    fn errors(input: &str) -> Vec<String> {
+        ByteCode::from_bytecode_text(input).unwrap_err()
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(
+            errors("LOAD_VAL 1\nADDD\nLAOD_VAL 2\nread_var x\nPUSH 3\n"),
+            vec![
+                "<input>:2:1: error: Unknown instruction `ADDD`\n  |\n2 | ADDD\n  | ^^^^ did you mean `ADD`?",
+                "<input>:3:1: error: Unknown instruction `LAOD_VAL`\n  |\n3 | LAOD_VAL 2\n  | ^^^^^^^^ did you mean `LOAD_VAL`?",
+                "<input>:4:1: error: Unknown instruction `read_var`\n  |\n4 | read_var x\n  | ^^^^^^^^ did you mean `READ_VAR`?",
+                "<input>:5:1: error: Unknown instruction `PUSH`\n  |\n5 | PUSH 3\n  | ^^^^",
+            ]
+        );
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(
+            errors("ADD 5\n  LOAD_VAL 1 2 // two values\nLOAD_VAL -1\n\tWRITE_VAR\n"),
+            vec![
+                "<input>:1:5: error: Unexpected operand `5` of ADD\n  |\n1 | ADD 5\n  |     ^",
+                "<input>:2:14: error: Unexpected operand `2` of LOAD_VAL\n  |\n2 |   LOAD_VAL 1 2 // two values\n  |              ^",
+                "<input>:3:10: error: Invalid operand `-1` of LOAD_VAL\n  |\n3 | LOAD_VAL -1\n  |          ^^",
+                "<input>:4:12: error: Empty operand for WRITE_VAR\n  |\n4 | \tWRITE_VAR\n  | \t          ^",
+            ]
+        );
+        // Comments may follow instructions
+        let bytecode =
+            ByteCode::from_bytecode_text("  LOAD_VAL 1 // one\nRETURN_VALUE //\n").unwrap();
+        assert_eq!(bytecode.instructions().len(), 2);
+    }
+}
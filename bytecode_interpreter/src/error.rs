@@ -0,0 +1,107 @@
+use std::{fmt, sync::Arc};
+
+use crate::{Id, Resource};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Message(String),
+    Line {
+        file: Option<Arc<str>>,
+        line: usize,
+        source: Box<Error>,
+    },
+    Child {
+        id: Id,
+        source: Box<Error>,
+    },
+    Aborted,
+    Deadlock,
+    Cancelled,
+    TimedOut,
+    Exceeded {
+        resource: Resource,
+        maximum: usize,
+    },
+}
+
+impl Error {
+    // Whether the error is a consequence of aborting of the thread tree
+    pub fn is_aborted(&self) -> bool {
+        match self {
+            Error::Line { source, .. } | Error::Child { source, .. } => source.is_aborted(),
+            Error::Aborted => true,
+            _ => false,
+        }
+    }
+
+    // Whether the error is a consequence of cancelling or of the deadline
+    pub fn is_interrupted(&self) -> bool {
+        match self {
+            Error::Line { source, .. } | Error::Child { source, .. } => source.is_interrupted(),
+            Error::Cancelled | Error::TimedOut => true,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn cause(&self) -> &Error {
+        match self {
+            Error::Line { source, .. } | Error::Child { source, .. } => source.cause(),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Message(message) => write!(f, "{}", message),
+            Error::Line { file, line, source } => {
+                write!(f, "{}, error: {}", Location(file.as_deref(), *line), source)
+            }
+            Error::Child { id, source } => write!(f, "Thread {} failed: {}", id, source),
+            Error::Aborted => write!(f, "Aborted, because another thread failed"),
+            Error::Deadlock => write!(f, "Deadlock, all threads are waiting"),
+            Error::Cancelled => write!(f, "Cancelled by the host"),
+            Error::TimedOut => write!(f, "Timed out, the deadline has passed"),
+            Error::Exceeded { resource, maximum } => write!(
+                f,
+                "Limit of {} is exceeded (maximum is {})",
+                resource, maximum
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Line { source, .. } | Error::Child { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Message(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Message(message.into())
+    }
+}
+
+// Place in the source, which is written at the start of error messages. The line is kept as the
+// index, but it's shown counted from 1, like editors and diagnostics show it.
+pub(crate) struct Location<'a>(pub Option<&'a str>, pub usize);
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(file) => write!(f, "File: {}, line: {}", file, self.1 + 1),
+            None => write!(f, "Line: {}", self.1 + 1),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::{fmt, str::FromStr};
+
+use crate::{ByteCode, Data, Error, Id};
+
+// Threads, which are chosen one after another at scheduling points
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Schedule(pub Vec<Id>);
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids: Vec<_> = self.0.iter().map(Id::to_string).collect();
+        write!(f, "{}", ids.join(","))
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| format!("Invalid thread id `{}`", id))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    pub schedule: Schedule,
+    pub result: Result<Data, Error>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub executions: usize,
+    // Whether all schedules within the bounds have been explored
+    pub complete: bool,
+    // Result of the first execution, the rest ones are compared with it
+    pub expected: Result<Data, Error>,
+    // Executions, which have failed, deadlocked or returned another value
+    pub failures: Vec<Execution>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Explored schedules: {}", self.executions)?;
+        if !self.complete {
+            write!(f, " (incomplete)")?;
+        }
+        for execution in &self.failures {
+            match &execution.result {
+                Ok(ret) => write!(f, "\nSchedule {}: returned {}", execution.schedule, ret)?,
+                Err(e) => write!(f, "\nSchedule {}: {}", execution.schedule, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Systematic exploration of thread interleavings at scheduling points. Executions are repeated
+// from the start with a prefix of choices of the previous one, so the program is explored in
+// depth-first order.
+#[derive(Debug, Clone)]
+pub struct Explorer {
+    pub max_schedules: usize,
+    // Maximum count of switches from a thread, which could go on, per execution
+    pub max_preemptions: Option<usize>,
+    // Maximum count of instructions per execution, so hangs are detected
+    pub max_steps: u64,
+}
+
+impl Default for Explorer {
+    fn default() -> Self {
+        Self {
+            max_schedules: 10_000,
+            max_preemptions: Some(2),
+            max_steps: 100_000,
+        }
+    }
+}
+
+struct Choice {
+    // Runnable threads, the previous thread goes first, if it is runnable
+    options: Vec<Id>,
+    index: usize,
+    // Whether the previous thread could go on
+    preemptible: bool,
+    // Count of preemptions before the choice
+    preemptions: usize,
+}
+
+impl Choice {
+    fn is_preemption(&self, index: usize) -> bool {
+        self.preemptible && index > 0
+    }
+}
+
+impl Explorer {
+    pub fn explore(&self, bytecode: &ByteCode) -> Report {
+        let mut choices = Vec::new();
+        let first = self.execute(bytecode, &mut choices);
+        let mut report = Report {
+            executions: 1,
+            complete: false,
+            expected: first.result.clone(),
+            failures: Vec::new(),
+        };
+        let mut execution = first;
+        loop {
+            if execution.result.is_err() || execution.result != report.expected {
+                report.failures.push(execution);
+            }
+            if !self.backtrack(&mut choices) {
+                report.complete = true;
+                break;
+            }
+            if report.executions >= self.max_schedules {
+                break;
+            }
+            execution = self.execute(bytecode, &mut choices);
+            report.executions += 1;
+        }
+        report
+    }
+
+    fn execute(&self, bytecode: &ByteCode, choices: &mut Vec<Choice>) -> Execution {
+        let mut bytecode = bytecode.fresh();
+        let mut schedule = Vec::new();
+        let mut previous = None;
+        let mut preemptions = 0;
+        let result = bytecode.interpret_deterministic(
+            &mut |runnable| {
+                if schedule.len() == choices.len() {
+                    let mut options = runnable.to_vec();
+                    let previous = options.iter().position(|id| Some(*id) == previous);
+                    if let Some(index) = previous {
+                        options[..=index].rotate_right(1);
+                    }
+                    choices.push(Choice {
+                        options,
+                        index: 0,
+                        preemptible: previous.is_some(),
+                        preemptions,
+                    });
+                }
+                let choice = &choices[schedule.len()];
+                if choice.is_preemption(choice.index) {
+                    preemptions += 1;
+                }
+                let id = choice.options[choice.index];
+                previous = Some(id);
+                schedule.push(id);
+                Ok(id)
+            },
+            Some(self.max_steps),
+        );
+        Execution {
+            schedule: Schedule(schedule),
+            result: result.map(|()| bytecode.ret.unwrap_or_default()),
+        }
+    }
+
+    // Moves to the next unexplored schedule, returns false, if there are no more ones
+    fn backtrack(&self, choices: &mut Vec<Choice>) -> bool {
+        while let Some(mut choice) = choices.pop() {
+            let next = (choice.index + 1..choice.options.len()).find(|&index| {
+                self.max_preemptions.is_none_or(|max| {
+                    choice.preemptions + usize::from(choice.is_preemption(index)) <= max
+                })
+            });
+            if let Some(index) = next {
+                choice.index = index;
+                choices.push(choice);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ByteCode, Error, Explorer, Schedule};
+
+    #[test]
+    fn deterministic_program() {
+        let input = r#"
+// spawn(f_send, f_send)
+LOAD_VAL 0
+LOAD_VAL 11
+LOAD_VAL 0
+LOAD_VAL 11
+SPAWN_IDS
+
+// return recv(a) + recv(b)
+RECV_CHANNEL
+WRITE_VAR b
+RECV_CHANNEL
+READ_VAR b
+ADD
+RETURN_VALUE
+
+// send(self_id(), parent_id())
+SELF_ID
+PARENT_ID
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+        let bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        let report = Explorer::default().explore(&bytecode);
+        assert!(report.complete);
+        assert!(report.executions > 1);
+        assert_eq!(report.expected, Ok(3));
+        assert!(report.failures.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn live_threads_limit_race() {
+        let input = r#"
+// spawn(f_ret, f_spawn)
+LOAD_VAL 0
+LOAD_VAL 14
+LOAD_VAL 0
+LOAD_VAL 7
+SPAWN
+LOAD_VAL 0
+RETURN_VALUE
+
+// spawn(f_ret, f_ret), which fits into the limit only after the sibling has finished
+LOAD_VAL 0
+LOAD_VAL 14
+LOAD_VAL 0
+LOAD_VAL 14
+SPAWN
+LOAD_VAL 0
+RETURN_VALUE
+
+// return 0
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        bytecode.set_max_threads(4);
+        let report = Explorer::default().explore(&bytecode);
+        assert!(report.complete);
+        assert_eq!(report.expected, Ok(0));
+        let failure = report.failures.first().expect("Race isn't found");
+        let error = failure.result.clone().unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Limit of live threads is exceeded"));
+
+        let schedule: Schedule = failure.schedule.to_string().parse().unwrap();
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        bytecode.set_max_threads(4);
+        assert_eq!(bytecode.interpret_schedule(&schedule), Err(error));
+    }
+
+    #[test]
+    fn send_to_finished_receiver() {
+        let input = r#"
+// spawn(f_send, f_ret)
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 12
+SPAWN
+LOAD_VAL 0
+RETURN_VALUE
+
+// send(1, 2)
+LOAD_VAL 1
+LOAD_VAL 2
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+
+// return 0
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+        // The message is never received, so the sender fails in every schedule
+        let bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        let report = Explorer::default().explore(&bytecode);
+        assert!(report.complete);
+        assert_eq!(report.failures.len(), report.executions);
+        let error = report.expected.unwrap_err();
+        assert!(error.to_string().ends_with("Thread 2 has already finished"));
+    }
+
+    #[test]
+    fn deadlock_in_every_schedule() {
+        let input = r#"
+// spawn(f_recv, f_recv)
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 7
+SPAWN_IDS
+RECV_CHANNEL
+RETURN_VALUE
+
+// return recv(parent_id())
+PARENT_ID
+RECV_CHANNEL
+RETURN_VALUE
+"#;
+
+        let bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        let report = Explorer::default().explore(&bytecode);
+        assert_eq!(report.expected, Err(Error::Deadlock));
+        assert_eq!(report.failures.len(), report.executions);
+    }
+
+    #[test]
+    fn hang() {
+        let input = r#"
+// loop {}
+LOAD_VAL 0
+JUMP
+"#;
+
+        let bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        let explorer = Explorer {
+            max_steps: 1000,
+            ..Default::default()
+        };
+        let report = explorer.explore(&bytecode);
+        assert_eq!(
+            report.expected,
+            Err(Error::Message(
+                "Limit of steps is exceeded (maximum is 1000)".into()
+            ))
+        );
+    }
+}
@@ -0,0 +1,305 @@
+// Interpretation as a future, which executes all threads of the tree on the thread, that polls
+// it. The future yields, when it has executed its budget of instructions or a thread has to wait
+// for a channel, so other tasks of the executor go on. Only std `Future` and `Waker` are used, so
+// any executor can poll it.
+
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use crate::{runtime::Runtime, ByteCode, Error};
+
+// Count of instructions, which are executed in one poll by default
+const BUDGET: usize = 1024;
+
+impl ByteCode {
+    // Dropping of the unfinished future pauses the tree, so interpreting again resumes it
+    pub fn interpret_async(&mut self) -> Interpretation<'_> {
+        Interpretation {
+            bytecode: self,
+            runtime: None,
+            budget: BUDGET,
+            gas: None,
+            steps: 0,
+            finished: false,
+        }
+    }
+}
+
+pub struct Interpretation<'a> {
+    bytecode: &'a mut ByteCode,
+    // The runtime keeps the tree, while it is executed
+    runtime: Option<Arc<Runtime>>,
+    budget: usize,
+    gas: Option<u64>,
+    steps: u64,
+    finished: bool,
+}
+
+impl Interpretation<'_> {
+    pub fn budget(mut self, budget: usize) -> Self {
+        self.budget = budget.max(1);
+        self
+    }
+
+    fn finish(&mut self, result: Result<(), Error>) -> Poll<Result<(), Error>> {
+        if let Some(runtime) = self.runtime.take() {
+            *self.bytecode = runtime.take_root();
+        }
+        self.finished = true;
+        Poll::Ready(result.and_then(|()| self.bytecode.result()))
+    }
+}
+
+impl Future for Interpretation<'_> {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        assert!(
+            !this.finished,
+            "Interpretation is polled after its completion"
+        );
+        let runtime = match &this.runtime {
+            Some(runtime) => runtime.clone(),
+            None => {
+                let error = this.bytecode.verification_errors().next();
+                if let Some(e) = error {
+                    return this.finish(Err(e));
+                }
+                let runtime = this.bytecode.runtime.clone();
+                this.gas = this.bytecode.config.limits.gas;
+                runtime.start(std::mem::take(this.bytecode));
+                this.runtime = Some(runtime.clone());
+                runtime
+            }
+        };
+        let mut left = this.budget;
+        while left > 0 {
+            let quantum = match this.gas {
+                Some(gas) if this.steps >= gas => {
+                    let message = format!("Limit of steps is exceeded (maximum is {})", gas);
+                    return this.finish(Err(message.into()));
+                }
+                Some(gas) => left.min(usize::try_from(gas - this.steps).unwrap_or(usize::MAX)),
+                None => left,
+            };
+            let Some((steps, waiting)) = runtime.run_next(quantum) else {
+                return this.finish(Ok(()));
+            };
+            this.steps += steps;
+            left = left.saturating_sub(usize::try_from(steps).unwrap_or(usize::MAX).max(1));
+            if waiting {
+                break;
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl Drop for Interpretation<'_> {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            *self.bytecode = runtime.take_root();
+        }
+    }
+}
+
+// Polls the future on the current thread, which is parked, until the future is woken
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Arc::new(ThreadWaker {
+        woken: AtomicBool::new(true),
+        thread: thread::current(),
+    });
+    let waker_ref = Waker::from(waker.clone());
+    let mut context = Context::from_waker(&waker_ref);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        while !waker.woken.swap(false, Ordering::Acquire) {
+            thread::park();
+        }
+    }
+}
+
+struct ThreadWaker {
+    woken: AtomicBool,
+    thread: Thread,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.thread.unpark();
+    }
+}
+
+// Minimal single-threaded executor, which polls woken tasks in turns
+#[derive(Default)]
+pub struct LocalExecutor<'a> {
+    tasks: Vec<Pin<Box<dyn Future<Output = ()> + 'a>>>,
+}
+
+impl<'a> LocalExecutor<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + 'a) {
+        self.tasks.push(Box::pin(task));
+    }
+
+    // Executes all spawned tasks, until they have finished
+    pub fn run(&mut self) {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .drain(..)
+            .map(|task| {
+                let waker = Arc::new(ThreadWaker {
+                    woken: AtomicBool::new(true),
+                    thread: thread::current(),
+                });
+                (Some(task), waker)
+            })
+            .collect();
+        while tasks.iter().any(|(task, _)| task.is_some()) {
+            let mut polled = false;
+            for (task, waker) in &mut tasks {
+                let Some(future) = task else {
+                    continue;
+                };
+                if !waker.woken.swap(false, Ordering::Acquire) {
+                    continue;
+                }
+                polled = true;
+                let waker = Waker::from(waker.clone());
+                if future
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    *task = None;
+                }
+            }
+            if !polled {
+                thread::park();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::{block_on, LocalExecutor};
+    use crate::{ByteCode, Error};
+
+    // count down from 100 in a child, which sends the result to the root
+    const COUNTDOWN: &str = r#"
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 9
+SPAWN_IDS
+RECV_CHANNEL
+RETURN_VALUE
+LOAD_VAL 0
+RETURN_VALUE
+LOAD_VAL 100
+WRITE_VAR x
+READ_VAR x
+LOAD_VAL 1
+SUB
+WRITE_VAR x
+READ_VAR x
+LOAD_VAL 0
+LOAD_VAL 11
+JUMP_GREATER_THAN
+READ_VAR x
+PARENT_ID
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+    #[test]
+    fn yields() {
+        let mut bytecode = ByteCode::from_bytecode_text(COUNTDOWN).unwrap();
+        let mut future = Box::pin(bytecode.interpret_async().budget(50));
+        let mut context = Context::from_waker(Waker::noop());
+        let mut pending = 0;
+        while future.as_mut().poll(&mut context) == Poll::Pending {
+            pending += 1;
+        }
+        assert!(pending >= 100 * 8 / 50, "{}", pending);
+        drop(future);
+        assert_eq!(bytecode.ret(), Some(&0));
+        assert_eq!(bytecode.registry().live_threads(), 0);
+
+        let mut bytecode = ByteCode::from_bytecode_text(COUNTDOWN).unwrap();
+        assert_eq!(block_on(bytecode.interpret_async()), Ok(()));
+        assert_eq!(bytecode.ret(), Some(&0));
+    }
+
+    #[test]
+    fn interleaving() {
+        let order = RefCell::new(Vec::new());
+        let mut bytecodes: Vec<_> = (0..2)
+            .map(|_| ByteCode::from_bytecode_text(COUNTDOWN).unwrap())
+            .collect();
+        let mut executor = LocalExecutor::new();
+        for (i, bytecode) in bytecodes.iter_mut().enumerate() {
+            let order = &order;
+            let mut future = Box::pin(bytecode.interpret_async().budget(100));
+            executor.spawn(std::future::poll_fn(move |context| {
+                order.borrow_mut().push(i);
+                future.as_mut().poll(context).map(|result| result.unwrap())
+            }));
+        }
+        executor.run();
+        drop(executor);
+        let order = order.into_inner();
+        assert!(order.starts_with(&[0, 1, 0, 1]), "{:?}", order);
+        assert!(bytecodes.iter().all(|bytecode| bytecode.ret() == Some(&0)));
+    }
+
+    #[test]
+    fn pauses() {
+        let mut bytecode = ByteCode::from_bytecode_text(COUNTDOWN).unwrap();
+        let mut context = Context::from_waker(Waker::noop());
+        let mut future = bytecode.interpret_async().budget(10);
+        assert!(Pin::new(&mut future).poll(&mut context).is_pending());
+        drop(future);
+        // The dropped future has paused the tree
+        assert_eq!(bytecode.registry().live_threads(), 3);
+        assert_eq!(bytecode.interpret(), Ok(()));
+        assert_eq!(bytecode.ret(), Some(&0));
+
+        let mut bytecode = ByteCode::from_bytecode_text(COUNTDOWN).unwrap();
+        bytecode.set_gas(100);
+        assert_eq!(
+            block_on(bytecode.interpret_async()),
+            Err(Error::from("Limit of steps is exceeded (maximum is 100)"))
+        );
+    }
+}
@@ -0,0 +1,552 @@
+// Targets for fuzzing take arbitrary bytes, so any fuzzing engine can drive them. The fuzzer here
+// feeds them with generated and mutated programs and random bytes. A panic or a hang of a target
+// is a bug, errors of parsing and execution aren't.
+
+use std::{any::Any, panic, sync::mpsc, thread, time::Duration};
+
+use crate::{ByteCode, Data, IndexedInstruction, Instruction, Limits, Scheduling, VmBuilder};
+
+// Limits of every execution, so a target can't run for long
+const LIMITS: Limits = Limits {
+    gas: Some(10_000),
+    max_threads: Some(16),
+    max_stack: Some(4096),
+    max_vars: None,
+    max_heap: Some(1 << 20),
+    max_spawn_depth: None,
+    max_messages: Some(4096),
+};
+
+const VARIABLES: [&str; 4] = ["a", "b", "c", "d"];
+
+// `inc` is registered for every execution, `missing` isn't
+const HOST_FUNCTIONS: [&str; 2] = ["inc", "missing"];
+
+// Words, which are inserted into mutated programs
+const DICTIONARY: [&str; 16] = [
+    "\n",
+    " ",
+    "//",
+    ".macro m x\n",
+    ".endm\n",
+    "m 1\n",
+    "x",
+    "LOAD_VAL ",
+    "JUMP\n",
+    "SPAWN\n",
+    "RECV_CHANNEL\n",
+    "RETURN_VALUE\n",
+    "0",
+    "-1",
+    "18446744073709551616",
+    "340282366920938463463374607431768211455",
+];
+
+pub type Target = fn(&[u8]);
+
+pub const TARGETS: [(&str, Target); 2] =
+    [("text", parse_text), ("instructions", execute_instructions)];
+
+// Parses the input as a text program and executes it, if it's valid
+pub fn parse_text(data: &[u8]) {
+    let input = String::from_utf8_lossy(data);
+    // Includes read files, which may be anything, e.g. `/dev/zero`
+    if input.contains(".include") {
+        return;
+    }
+    if let Ok(bytecode) = ByteCode::from_bytecode_text(&*input) {
+        let _ = bytecode.verify();
+        execute(bytecode, data.len().is_multiple_of(2));
+    }
+}
+
+// Decodes the input into instructions with `decode` and executes them
+pub fn execute_instructions(data: &[u8]) {
+    let Some((&mode, data)) = data.split_first() else {
+        return;
+    };
+    execute(ByteCode::new(decode(data)), mode.is_multiple_of(2));
+}
+
+fn execute(bytecode: ByteCode, parallel: bool) {
+    let scheduling = if parallel {
+        Scheduling::Parallel
+    } else {
+        Scheduling::Sequential
+    };
+    let _ = VmBuilder::new(bytecode)
+        .limits(LIMITS)
+        .workers(2)
+        .scheduling(scheduling)
+        .host_function(HOST_FUNCTIONS[0], 1, |args| {
+            Ok(args[0].checked_add(1).ok_or("Addition overflow occurred")?)
+        })
+        .run();
+}
+
+// Every instruction is an opcode byte and operands. A value is a byte below 0xf0 or 16 bytes of a
+// little-endian number after the marker, variables and host functions are indices into the lists
+// above. Opcodes are fixed, so saved inputs keep decoding to the same instructions: new
+// instructions get the next free opcode, and bytes without an instruction are unknown ones.
+pub fn decode(mut data: &[u8]) -> Vec<IndexedInstruction> {
+    let mut instructions = Vec::new();
+    while let Some((&opcode, rest)) = data.split_first() {
+        data = rest;
+        let mut byte = || match data.split_first() {
+            Some((&byte, rest)) => {
+                data = rest;
+                byte
+            }
+            None => 0,
+        };
+        let instruction = match opcode {
+            0 => {
+                let value = byte();
+                Instruction::LoadVal(if value < 0xf0 {
+                    value.into()
+                } else {
+                    let mut bytes = [0; 16];
+                    bytes.iter_mut().for_each(|b| *b = byte());
+                    Data::from_le_bytes(bytes)
+                })
+            }
+            1 => Instruction::WriteVar(VARIABLES[usize::from(byte()) % VARIABLES.len()].into()),
+            2 => Instruction::ReadVar(VARIABLES[usize::from(byte()) % VARIABLES.len()].into()),
+            3 => Instruction::Add,
+            4 => Instruction::Sub,
+            5 => Instruction::Mul,
+            6 => Instruction::RetVal,
+            7 => Instruction::Jump,
+            8 => Instruction::JumpLessThan,
+            9 => Instruction::JumpGreaterThan,
+            10 => Instruction::JumpEqual,
+            11 => Instruction::Spawn,
+            12 => Instruction::SendChannel,
+            13 => Instruction::RecvChannel,
+            14 => Instruction::SelfId,
+            15 => Instruction::ParentId,
+            16 => Instruction::Log,
+            17 => Instruction::CallHost(
+                HOST_FUNCTIONS[usize::from(byte()) % HOST_FUNCTIONS.len()].into(),
+            ),
+            18 => Instruction::Unk,
+            19 => Instruction::AddWrap,
+            20 => Instruction::SubWrap,
+            21 => Instruction::MulWrap,
+            22 => Instruction::AddSat,
+            23 => Instruction::SubSat,
+            24 => Instruction::MulSat,
+            25 => Instruction::AddCarry,
+            26 => Instruction::SubCarry,
+            27 => Instruction::MulCarry,
+            28 => Instruction::LessThan,
+            29 => Instruction::GreaterThan,
+            30 => Instruction::Equal,
+            31 => Instruction::NotEqual,
+            32 => Instruction::LessEqual,
+            33 => Instruction::GreaterEqual,
+            34 => Instruction::LogicalAnd,
+            35 => Instruction::LogicalOr,
+            36 => Instruction::LogicalNot,
+            37 => Instruction::JumpIf,
+            38 => Instruction::JumpIfNot,
+            39 => Instruction::SpawnIds,
+            _ => Instruction::Unk,
+        };
+        instructions.push(IndexedInstruction::new(instructions.len(), instruction));
+    }
+    instructions
+}
+
+// The inverse of `decode` for instructions, which the generator makes
+pub fn encode(instructions: &[Instruction]) -> Vec<u8> {
+    let index = |names: &[&str], name: &str| {
+        let index = names.iter().position(|n| *n == name).unwrap_or_default();
+        u8::try_from(index).unwrap_or_default()
+    };
+    let mut data = Vec::new();
+    for instruction in instructions {
+        match instruction {
+            Instruction::LoadVal(value) => {
+                data.push(0);
+                match u8::try_from(*value) {
+                    Ok(value) if value < 0xf0 => data.push(value),
+                    _ => {
+                        data.push(0xf0);
+                        data.extend(value.to_le_bytes());
+                    }
+                }
+            }
+            Instruction::WriteVar(name) => data.extend([1, index(&VARIABLES, name)]),
+            Instruction::ReadVar(name) => data.extend([2, index(&VARIABLES, name)]),
+            Instruction::Add => data.push(3),
+            Instruction::Sub => data.push(4),
+            Instruction::Mul => data.push(5),
+            Instruction::RetVal => data.push(6),
+            Instruction::Jump => data.push(7),
+            Instruction::JumpLessThan => data.push(8),
+            Instruction::JumpGreaterThan => data.push(9),
+            Instruction::JumpEqual => data.push(10),
+            Instruction::Spawn => data.push(11),
+            Instruction::SendChannel => data.push(12),
+            Instruction::RecvChannel => data.push(13),
+            Instruction::SelfId => data.push(14),
+            Instruction::ParentId => data.push(15),
+            Instruction::Log => data.push(16),
+            Instruction::CallHost(name) => data.extend([17, index(&HOST_FUNCTIONS, name)]),
+            Instruction::Unk => data.push(18),
+            Instruction::AddWrap => data.push(19),
+            Instruction::SubWrap => data.push(20),
+            Instruction::MulWrap => data.push(21),
+            Instruction::AddSat => data.push(22),
+            Instruction::SubSat => data.push(23),
+            Instruction::MulSat => data.push(24),
+            Instruction::AddCarry => data.push(25),
+            Instruction::SubCarry => data.push(26),
+            Instruction::MulCarry => data.push(27),
+            Instruction::LessThan => data.push(28),
+            Instruction::GreaterThan => data.push(29),
+            Instruction::Equal => data.push(30),
+            Instruction::NotEqual => data.push(31),
+            Instruction::LessEqual => data.push(32),
+            Instruction::GreaterEqual => data.push(33),
+            Instruction::LogicalAnd => data.push(34),
+            Instruction::LogicalOr => data.push(35),
+            Instruction::LogicalNot => data.push(36),
+            Instruction::JumpIf => data.push(37),
+            Instruction::JumpIfNot => data.push(38),
+            Instruction::SpawnIds => data.push(39),
+        }
+    }
+    data
+}
+
+// SplitMix64, so runs are reproducible by the seed
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number in `0..n`
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Makes programs of threads, which jump inside the program, spawn, send and receive, so they get
+// further than random bytes
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: Rng,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn program(&mut self) -> Vec<Instruction> {
+        let len = self.rng.below(48) + 1;
+        let mut program = Vec::new();
+        while program.len() < len {
+            let rng = &mut self.rng;
+            let value = |rng: &mut Rng| Instruction::LoadVal(rng.below(8) as Data);
+            let position = |rng: &mut Rng| Instruction::LoadVal(rng.below(len + 1) as Data);
+            let variable = |rng: &mut Rng| VARIABLES[rng.below(VARIABLES.len())].to_string();
+            match rng.below(10) {
+                0..=2 => program.push(value(rng)),
+                3 => program.push(match rng.below(2) {
+                    0 => Instruction::WriteVar(variable(rng)),
+                    _ => Instruction::ReadVar(variable(rng)),
+                }),
+                4 => program.push(match rng.below(21) {
+                    0 => Instruction::Add,
+                    1 => Instruction::Sub,
+                    2 => Instruction::Mul,
+                    3 => Instruction::AddWrap,
+                    4 => Instruction::SubWrap,
+                    5 => Instruction::MulWrap,
+                    6 => Instruction::AddSat,
+                    7 => Instruction::SubSat,
+                    8 => Instruction::MulSat,
+                    9 => Instruction::AddCarry,
+                    10 => Instruction::SubCarry,
+                    11 => Instruction::MulCarry,
+                    12 => Instruction::LessThan,
+                    13 => Instruction::GreaterThan,
+                    14 => Instruction::Equal,
+                    15 => Instruction::NotEqual,
+                    16 => Instruction::LessEqual,
+                    17 => Instruction::GreaterEqual,
+                    18 => Instruction::LogicalAnd,
+                    19 => Instruction::LogicalOr,
+                    _ => Instruction::LogicalNot,
+                }),
+                5 => {
+                    program.push(position(rng));
+                    program.push(match rng.below(6) {
+                        0 => Instruction::Jump,
+                        1 => Instruction::JumpLessThan,
+                        2 => Instruction::JumpGreaterThan,
+                        3 => Instruction::JumpEqual,
+                        4 => Instruction::JumpIf,
+                        _ => Instruction::JumpIfNot,
+                    });
+                }
+                6 => {
+                    for _ in 0..2 {
+                        program.push(Instruction::LoadVal(0));
+                        program.push(position(rng));
+                    }
+                    program.push(match rng.below(2) {
+                        0 => Instruction::Spawn,
+                        _ => Instruction::SpawnIds,
+                    });
+                }
+                7 => {
+                    program.push(match rng.below(2) {
+                        0 => Instruction::SelfId,
+                        _ => Instruction::ParentId,
+                    });
+                    program.push(match rng.below(2) {
+                        0 => Instruction::SendChannel,
+                        _ => Instruction::RecvChannel,
+                    });
+                }
+                8 => program.push(match rng.below(3) {
+                    0 => Instruction::Log,
+                    _ => Instruction::CallHost(HOST_FUNCTIONS[rng.below(2)].into()),
+                }),
+                _ => {
+                    program.push(value(rng));
+                    program.push(Instruction::RetVal);
+                }
+            }
+        }
+        program
+    }
+
+    pub fn text(program: &[Instruction]) -> String {
+        program.iter().map(|i| format!("{}\n", i)).collect()
+    }
+
+    // Deletes, inserts or duplicates bytes of the input
+    pub fn mutate(&mut self, data: &mut Vec<u8>) {
+        for _ in 0..=self.rng.below(4) {
+            let at = self.rng.below(data.len() + 1);
+            match self.rng.below(4) {
+                0 if at < data.len() => {
+                    let end = (at + self.rng.below(8) + 1).min(data.len());
+                    data.drain(at..end);
+                }
+                1 if at < data.len() => {
+                    let end = (at + self.rng.below(16) + 1).min(data.len());
+                    let copy = data[at..end].to_vec();
+                    let to = self.rng.below(data.len() + 1);
+                    data.splice(to..to, copy);
+                }
+                2 if at < data.len() => data[at] = self.rng.next() as u8,
+                _ => {
+                    let word = DICTIONARY[self.rng.below(DICTIONARY.len())];
+                    data.splice(at..at, word.bytes());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    Panic(String),
+    // The target hasn't finished in time
+    Hang,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub target: &'static str,
+    pub input: Vec<u8>,
+    pub finding: Finding,
+}
+
+// Executes the target on another thread, which is left behind, if it hangs
+pub fn check(target: Target, input: &[u8], timeout: Duration) -> Result<(), Finding> {
+    let (sender, receiver) = mpsc::channel();
+    let input = input.to_vec();
+    thread::spawn(move || {
+        let result = panic::catch_unwind(|| target(&input));
+        let _ = sender.send(result.map_err(message));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result.map_err(Finding::Panic),
+        Err(_) => Err(Finding::Hang),
+    }
+}
+
+fn message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or("Panic without a message".into(), |message| {
+                message.to_string()
+            }),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Fuzzer {
+    pub seed: u64,
+    // Count of inputs of every target
+    pub runs: u64,
+    pub timeout: Duration,
+}
+
+impl Default for Fuzzer {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            runs: 1000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Fuzzer {
+    // Stops at the first failure
+    pub fn run(&self) -> Result<(), Failure> {
+        let mut generator = Generator::new(self.seed);
+        let mut rng = Rng::new(!self.seed);
+        for _ in 0..self.runs {
+            let program = generator.program();
+            let mut text = Generator::text(&program).into_bytes();
+            if rng.below(2) == 0 {
+                generator.mutate(&mut text);
+            }
+            let mut instructions = vec![rng.next() as u8];
+            match rng.below(3) {
+                0 => instructions.extend((0..rng.below(256)).map(|_| rng.next() as u8)),
+                1 => {
+                    instructions.extend(encode(&program));
+                    generator.mutate(&mut instructions);
+                }
+                _ => instructions.extend(encode(&program)),
+            }
+            for ((target, function), input) in TARGETS.iter().zip([text, instructions]) {
+                check(*function, &input, self.timeout).map_err(|finding| Failure {
+                    target,
+                    input,
+                    finding,
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{check, decode, encode, Finding, Fuzzer, Generator};
+    use crate::{ByteCode, Data, Instruction};
+
+    #[test]
+    fn generated_programs() {
+        let mut generator = Generator::new(7);
+        for _ in 0..100 {
+            let program = generator.program();
+            let text = Generator::text(&program);
+            let parsed = ByteCode::from_bytecode_text(&text).unwrap();
+            let decoded = decode(&encode(&program));
+            assert_eq!(parsed.instructions(), decoded);
+        }
+    }
+
+    #[test]
+    fn decoded_edge_cases() {
+        let mut bytecode = ByteCode::new(decode(&[18]));
+        assert_eq!(
+            bytecode.interpret().unwrap_err().to_string(),
+            "Line: 1, error: Unknown instruction"
+        );
+
+        // Opcodes don't change, when instructions are added
+        let decoded: Vec<_> = decode(&[11, 27, 38, 40, 0xff])
+            .iter()
+            .map(|i| i.instruction().clone())
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                Instruction::Spawn,
+                Instruction::MulCarry,
+                Instruction::JumpIfNot,
+                Instruction::Unk,
+                Instruction::Unk
+            ]
+        );
+
+        // The position isn't truncated to a valid one
+        let position = (1 << 64) + 1;
+        let program = [Instruction::LoadVal(position), Instruction::Jump];
+        let mut bytecode = ByteCode::new(decode(&encode(&program)));
+        assert_eq!(
+            bytecode.interpret().unwrap_err().to_string(),
+            "Instruction doesn't exist at 18446744073709551617 position"
+        );
+        assert_eq!(bytecode.position(), usize::MAX);
+
+        let truncated = decode(&[0, 0xf0, 1, 2]);
+        assert_eq!(
+            truncated[0].instruction(),
+            &Instruction::LoadVal(Data::from_le_bytes([
+                1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            ]))
+        );
+    }
+
+    #[test]
+    fn findings() {
+        let timeout = Duration::from_millis(200);
+        assert_eq!(check(|_| {}, b"", timeout), Ok(()));
+        assert_eq!(
+            check(|data| assert!(data.is_empty(), "Not empty"), b"x", timeout),
+            Err(Finding::Panic("Not empty".into()))
+        );
+        assert_eq!(
+            check(|_| std::thread::sleep(Duration::from_secs(5)), b"", timeout),
+            Err(Finding::Hang)
+        );
+    }
+
+    #[test]
+    fn fuzz() {
+        let fuzzer = Fuzzer {
+            seed: 1,
+            runs: 300,
+            ..Default::default()
+        };
+        if let Err(failure) = fuzzer.run() {
+            panic!(
+                "{:?} of `{}` target on {:?}",
+                failure.finding,
+                failure.target,
+                String::from_utf8_lossy(&failure.input)
+            );
+        }
+    }
+}
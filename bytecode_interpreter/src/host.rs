@@ -0,0 +1,152 @@
+use std::{fmt, sync::Arc};
+
+use crate::{Data, Error};
+
+type Function = dyn Fn(&[Data]) -> Result<Data, Error> + Send + Sync;
+
+// Function of the embedder, which is called by `CALL_HOST name`. The instruction pops `arity`
+// arguments, so the first argument is the deepest one, and pushes the result.
+#[derive(Clone)]
+pub struct HostFunction {
+    arity: usize,
+    function: Arc<Function>,
+}
+
+impl HostFunction {
+    pub fn new(
+        arity: usize,
+        function: impl Fn(&[Data]) -> Result<Data, Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            arity,
+            function: Arc::new(function),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn call(&self, arguments: &[Data]) -> Result<Data, Error> {
+        (self.function)(arguments)
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ByteCode, Error};
+
+    const MAX_OF_DOUBLED: &str = r#"
+// return max(double(3), 5) + max(8, double(2))
+LOAD_VAL 3
+CALL_HOST double
+LOAD_VAL 5
+CALL_HOST max
+LOAD_VAL 8
+LOAD_VAL 2
+CALL_HOST double
+CALL_HOST max
+ADD
+RETURN_VALUE
+"#;
+
+    fn register(bytecode: &mut ByteCode) {
+        bytecode.register_host_function("double", 1, |args| Ok(args[0] * 2));
+        bytecode.register_host_function("max", 2, |args| Ok(args[0].max(args[1])));
+        bytecode.register_host_function("checked_div", 2, |args| {
+            args[0]
+                .checked_div(args[1])
+                .ok_or_else(|| Error::from("Division by zero"))
+        });
+    }
+
+    #[test]
+    fn call_host() {
+        let mut bytecode = ByteCode::from_bytecode_text(MAX_OF_DOUBLED).unwrap();
+        register(&mut bytecode);
+        bytecode.interpret().unwrap();
+        assert_eq!(bytecode.ret(), Some(&14));
+    }
+
+    #[test]
+    fn arguments_order() {
+        let input = "LOAD_VAL 12\nLOAD_VAL 4\nCALL_HOST checked_div\nRETURN_VALUE\n";
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        register(&mut bytecode);
+        bytecode.interpret().unwrap();
+        assert_eq!(bytecode.ret(), Some(&3));
+    }
+
+    #[test]
+    fn unknown_host_function() {
+        let mut bytecode = ByteCode::from_bytecode_text(MAX_OF_DOUBLED).unwrap();
+        bytecode.register_host_function("double", 1, |args| Ok(args[0] * 2));
+        assert_eq!(
+            bytecode.verify(),
+            Err(vec![
+                "Line: 6, error: Host function `max` isn't registered".to_string(),
+                "Line: 10, error: Host function `max` isn't registered".to_string(),
+            ])
+        );
+        // Nothing is executed
+        assert_eq!(
+            bytecode.interpret().unwrap_err().to_string(),
+            "Line: 6, error: Host function `max` isn't registered"
+        );
+        assert_eq!(bytecode.registry().threads(), vec![]);
+    }
+
+    #[test]
+    fn host_function_failure() {
+        let input = "LOAD_VAL 1\nLOAD_VAL 0\nCALL_HOST checked_div\nRETURN_VALUE\n";
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        register(&mut bytecode);
+        assert_eq!(
+            bytecode.interpret().unwrap_err().to_string(),
+            "Line: 3, error: Division by zero"
+        );
+
+        let mut bytecode = ByteCode::from_bytecode_text("LOAD_VAL 1\nCALL_HOST max\n").unwrap();
+        register(&mut bytecode);
+        assert_eq!(
+            bytecode.interpret().unwrap_err().to_string(),
+            "Line: 2, error: Stack is empty"
+        );
+    }
+
+    #[test]
+    fn children_inherit_host_functions() {
+        let input = r#"
+LOAD_VAL 4
+LOAD_VAL 5
+LOAD_VAL 1
+LOAD_VAL 13
+LOAD_VAL 1
+LOAD_VAL 13
+SPAWN_IDS
+RECV_CHANNEL
+WRITE_VAR b
+RECV_CHANNEL
+READ_VAR b
+ADD
+RETURN_VALUE
+CALL_HOST double
+PARENT_ID
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        register(&mut bytecode);
+        bytecode.interpret().unwrap();
+        assert_eq!(bytecode.ret(), Some(&18));
+    }
+}
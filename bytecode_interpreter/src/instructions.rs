@@ -1,10 +1,10 @@
-use std::{sync::atomic::Ordering, sync::mpsc, thread};
+use std::{fmt, sync::Arc};
 
-use crate::{ByteCode, Data};
+use crate::{runtime::Wait, ByteCode, Data, Error, Id, Overflow, Resource};
 
 pub type Ident = String;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Default, PartialEq, Clone)]
 pub enum Instruction {
     LoadVal(Data),
     WriteVar(Ident),
@@ -12,67 +12,333 @@ pub enum Instruction {
     Add,
     Sub,
     Mul,
+    // Arithmetic, which handles overflows in its own way instead of the one of the config
+    AddWrap,
+    SubWrap,
+    MulWrap,
+    AddSat,
+    SubSat,
+    MulSat,
+    // Push the wrapped result and then 1, if the operation has overflowed, otherwise 0
+    AddCarry,
+    SubCarry,
+    MulCarry,
+    // Comparisons and logical operations push 1, if they hold, otherwise 0
+    LessThan,
+    GreaterThan,
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+    LogicalAnd,
+    LogicalOr,
+    LogicalNot,
     RetVal,
     Jump,
     JumpLessThan,
     JumpGreaterThan,
     JumpEqual,
+    // Jump to the target on the top, if the condition below it isn't 0 or is 0 respectively
+    JumpIf,
+    JumpIfNot,
     Spawn,
+    // Spawns like `SPAWN` and pushes ids of both children, so the parent can address them
+    SpawnIds,
     SendChannel,
     RecvChannel,
+    SelfId,
+    ParentId,
     Log,
+    CallHost(Ident),
+    #[default]
     Unk,
 }
 
-impl Default for Instruction {
-    fn default() -> Self {
-        Self::Unk
-    }
-}
-
 // FIXME: Ugly `TryFrom` trait with a wrapper, because Rust doesn't have the specialization
 //
 // https://github.com/rust-lang/rust/issues/50133
 pub struct IteratorWrapper<'a, T: std::iter::Iterator<Item = &'a str>>(pub T);
 
+// Mnemonics, which misspelled instructions are compared with
+const MNEMONICS: [&str; 39] = [
+    "LOAD_VAL",
+    "WRITE_VAR",
+    "READ_VAR",
+    "ADD",
+    "SUB",
+    "MULTIPLY",
+    "ADD_WRAP",
+    "SUB_WRAP",
+    "MULTIPLY_WRAP",
+    "ADD_SAT",
+    "SUB_SAT",
+    "MULTIPLY_SAT",
+    "ADD_CARRY",
+    "SUB_CARRY",
+    "MULTIPLY_CARRY",
+    "LT",
+    "GT",
+    "EQ",
+    "NE",
+    "LE",
+    "GE",
+    "LOGICAL_AND",
+    "LOGICAL_OR",
+    "LOGICAL_NOT",
+    "RETURN_VALUE",
+    "JUMP",
+    "JUMP_LESS_THAN",
+    "JUMP_GREATER_THAN",
+    "JUMP_EQUAL",
+    "JUMP_IF",
+    "JUMP_IF_NOT",
+    "SPAWN",
+    "SPAWN_IDS",
+    "SEND_CHANNEL",
+    "RECV_CHANNEL",
+    "SELF_ID",
+    "PARENT_ID",
+    "LOG",
+    "CALL_HOST",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    // The index of the wrong word, it's the count of words, if a word is missing
+    pub word: usize,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl ParseError {
+    fn new(word: usize, message: impl Into<String>) -> Self {
+        Self {
+            word,
+            message: message.into(),
+            help: None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.help {
+            Some(help) => write!(f, "{}, {}", self.message, help),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
 impl<'a, T> TryFrom<IteratorWrapper<'a, T>> for Instruction
 where
     T: std::iter::Iterator<Item = &'a str>,
 {
-    type Error = &'static str;
+    type Error = ParseError;
 
     fn try_from(iter_w: IteratorWrapper<'a, T>) -> Result<Self, Self::Error> {
         let mut iter = iter_w.0;
-        let instruction = iter.next().ok_or("Empty instruction")?;
-        let instruction = match instruction {
-            "LOAD_VAL" => Self::LoadVal(
-                iter.next()
-                    .ok_or("Empty operand for LOAD_VAL")?
-                    .parse()
-                    .map_err(|_| "Parsing of operand of LOAD_VAL")?,
-            ),
-            "WRITE_VAR" => Self::WriteVar(iter.next().ok_or("Empty operand for WRITE_VAR")?.into()),
-            "READ_VAR" => Self::ReadVar(iter.next().ok_or("Empty operand for READ_VAR")?.into()),
+        let mnemonic = iter
+            .next()
+            .ok_or_else(|| ParseError::new(0, "Empty instruction"))?;
+        let mut operand = || {
+            iter.next()
+                .ok_or_else(|| ParseError::new(1, format!("Empty operand for {}", mnemonic)))
+        };
+        let instruction = match mnemonic {
+            "LOAD_VAL" => {
+                let value = operand()?;
+                Self::LoadVal(value.parse().map_err(|_| {
+                    ParseError::new(1, format!("Invalid operand `{}` of LOAD_VAL", value))
+                })?)
+            }
+            "WRITE_VAR" => Self::WriteVar(operand()?.into()),
+            "READ_VAR" => Self::ReadVar(operand()?.into()),
             "ADD" => Self::Add,
             "SUB" => Self::Sub,
             "MULTIPLY" => Self::Mul,
+            "ADD_WRAP" => Self::AddWrap,
+            "SUB_WRAP" => Self::SubWrap,
+            "MULTIPLY_WRAP" => Self::MulWrap,
+            "ADD_SAT" => Self::AddSat,
+            "SUB_SAT" => Self::SubSat,
+            "MULTIPLY_SAT" => Self::MulSat,
+            "ADD_CARRY" => Self::AddCarry,
+            "SUB_CARRY" => Self::SubCarry,
+            "MULTIPLY_CARRY" => Self::MulCarry,
+            "LT" => Self::LessThan,
+            "GT" => Self::GreaterThan,
+            "EQ" => Self::Equal,
+            "NE" => Self::NotEqual,
+            "LE" => Self::LessEqual,
+            "GE" => Self::GreaterEqual,
+            "LOGICAL_AND" => Self::LogicalAnd,
+            "LOGICAL_OR" => Self::LogicalOr,
+            "LOGICAL_NOT" => Self::LogicalNot,
             "RETURN_VALUE" => Self::RetVal,
             "JUMP" => Self::Jump,
             "JUMP_LESS_THAN" => Self::JumpLessThan,
             "JUMP_GREATER_THAN" => Self::JumpGreaterThan,
             "JUMP_EQUAL" => Self::JumpEqual,
+            "JUMP_IF" => Self::JumpIf,
+            "JUMP_IF_NOT" => Self::JumpIfNot,
             "SPAWN" => Self::Spawn,
+            "SPAWN_IDS" => Self::SpawnIds,
             "SEND_CHANNEL" => Self::SendChannel,
             "RECV_CHANNEL" => Self::RecvChannel,
+            "SELF_ID" => Self::SelfId,
+            "PARENT_ID" => Self::ParentId,
             "LOG" => Self::Log,
-            _ => return Err("Unknown instruction"),
+            "CALL_HOST" => Self::CallHost(operand()?.into()),
+            _ => {
+                return Err(ParseError {
+                    help: suggestion(mnemonic).map(|s| format!("did you mean `{}`?", s)),
+                    ..ParseError::new(0, format!("Unknown instruction `{}`", mnemonic))
+                })
+            }
         };
+        let operands = match instruction {
+            Self::LoadVal(_) | Self::WriteVar(_) | Self::ReadVar(_) | Self::CallHost(_) => 1,
+            _ => 0,
+        };
+        if let Some(word) = iter.next() {
+            return Err(ParseError::new(
+                operands + 1,
+                format!("Unexpected operand `{}` of {}", word, mnemonic),
+            ));
+        }
         Ok(instruction)
     }
 }
 
+// The closest mnemonic, if the word is probably a misspelling of it
+fn suggestion(word: &str) -> Option<&'static str> {
+    let upper = word.to_ascii_uppercase();
+    MNEMONICS
+        .iter()
+        .map(|mnemonic| (distance(&upper, mnemonic), *mnemonic))
+        .filter(|(distance, _)| *distance <= (word.len() / 3).max(1))
+        .min()
+        .map(|(_, mnemonic)| mnemonic)
+}
+
+// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// The text, which is parsed into the same instruction. `UNKNOWN` of `Unk` is the exception, it
+// isn't parsed from programs or their JSON export, only snapshots restore it.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::LoadVal(value) => write!(f, "{} {}", self.mnemonic(), value),
+            Instruction::WriteVar(ident)
+            | Instruction::ReadVar(ident)
+            | Instruction::CallHost(ident) => write!(f, "{} {}", self.mnemonic(), ident),
+            _ => write!(f, "{}", self.mnemonic()),
+        }
+    }
+}
+
 impl Instruction {
-    pub fn interpret(&self, bytecode: &mut ByteCode) -> Result<(), String> {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::LoadVal(_) => "LOAD_VAL",
+            Instruction::WriteVar(_) => "WRITE_VAR",
+            Instruction::ReadVar(_) => "READ_VAR",
+            Instruction::Add => "ADD",
+            Instruction::Sub => "SUB",
+            Instruction::Mul => "MULTIPLY",
+            Instruction::AddWrap => "ADD_WRAP",
+            Instruction::SubWrap => "SUB_WRAP",
+            Instruction::MulWrap => "MULTIPLY_WRAP",
+            Instruction::AddSat => "ADD_SAT",
+            Instruction::SubSat => "SUB_SAT",
+            Instruction::MulSat => "MULTIPLY_SAT",
+            Instruction::AddCarry => "ADD_CARRY",
+            Instruction::SubCarry => "SUB_CARRY",
+            Instruction::MulCarry => "MULTIPLY_CARRY",
+            Instruction::LessThan => "LT",
+            Instruction::GreaterThan => "GT",
+            Instruction::Equal => "EQ",
+            Instruction::NotEqual => "NE",
+            Instruction::LessEqual => "LE",
+            Instruction::GreaterEqual => "GE",
+            Instruction::LogicalAnd => "LOGICAL_AND",
+            Instruction::LogicalOr => "LOGICAL_OR",
+            Instruction::LogicalNot => "LOGICAL_NOT",
+            Instruction::RetVal => "RETURN_VALUE",
+            Instruction::Jump => "JUMP",
+            Instruction::JumpLessThan => "JUMP_LESS_THAN",
+            Instruction::JumpGreaterThan => "JUMP_GREATER_THAN",
+            Instruction::JumpEqual => "JUMP_EQUAL",
+            Instruction::JumpIf => "JUMP_IF",
+            Instruction::JumpIfNot => "JUMP_IF_NOT",
+            Instruction::Spawn => "SPAWN",
+            Instruction::SpawnIds => "SPAWN_IDS",
+            Instruction::SendChannel => "SEND_CHANNEL",
+            Instruction::RecvChannel => "RECV_CHANNEL",
+            Instruction::SelfId => "SELF_ID",
+            Instruction::ParentId => "PARENT_ID",
+            Instruction::Log => "LOG",
+            Instruction::CallHost(_) => "CALL_HOST",
+            Instruction::Unk => "UNKNOWN",
+        }
+    }
+
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Jump
+                | Instruction::JumpLessThan
+                | Instruction::JumpGreaterThan
+                | Instruction::JumpEqual
+                | Instruction::JumpIf
+                | Instruction::JumpIfNot
+        )
+    }
+
+    // The comparison of an instruction, which pushes its result
+    pub(crate) fn comparison(&self) -> Option<Comparison> {
+        match self {
+            Instruction::LessThan => Some(Comparison::Less),
+            Instruction::GreaterThan => Some(Comparison::Greater),
+            Instruction::Equal => Some(Comparison::Equal),
+            Instruction::NotEqual => Some(Comparison::NotEqual),
+            Instruction::LessEqual => Some(Comparison::LessEqual),
+            Instruction::GreaterEqual => Some(Comparison::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    pub fn is_spawn(&self) -> bool {
+        matches!(self, Instruction::Spawn | Instruction::SpawnIds)
+    }
+
+    // Whether the instruction interacts with other threads, so the order of its execution matters
+    pub fn is_scheduling_point(&self) -> bool {
+        matches!(
+            self,
+            Instruction::RetVal
+                | Instruction::Spawn
+                | Instruction::SpawnIds
+                | Instruction::SendChannel
+                | Instruction::RecvChannel
+        )
+    }
+
+    pub fn interpret(&self, bytecode: &mut ByteCode) -> Result<(), Error> {
         match self {
             Instruction::LoadVal(value) => {
                 bytecode.stack.push(*value);
@@ -91,36 +357,47 @@ impl Instruction {
                 bytecode.stack.push(*value);
                 bytecode.position += 1;
             }
-            Instruction::Add => {
-                let lhs = bytecode.stack_pop()?;
+            Instruction::Add => arithmetic(bytecode, Operation::Add, Handling::Config)?,
+            Instruction::Sub => arithmetic(bytecode, Operation::Sub, Handling::Config)?,
+            Instruction::Mul => arithmetic(bytecode, Operation::Mul, Handling::Config)?,
+            Instruction::AddWrap => arithmetic(bytecode, Operation::Add, WRAPPING)?,
+            Instruction::SubWrap => arithmetic(bytecode, Operation::Sub, WRAPPING)?,
+            Instruction::MulWrap => arithmetic(bytecode, Operation::Mul, WRAPPING)?,
+            Instruction::AddSat => arithmetic(bytecode, Operation::Add, SATURATING)?,
+            Instruction::SubSat => arithmetic(bytecode, Operation::Sub, SATURATING)?,
+            Instruction::MulSat => arithmetic(bytecode, Operation::Mul, SATURATING)?,
+            Instruction::AddCarry => arithmetic(bytecode, Operation::Add, Handling::Carry)?,
+            Instruction::SubCarry => arithmetic(bytecode, Operation::Sub, Handling::Carry)?,
+            Instruction::MulCarry => arithmetic(bytecode, Operation::Mul, Handling::Carry)?,
+            Instruction::LessThan
+            | Instruction::GreaterThan
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::LessEqual
+            | Instruction::GreaterEqual => {
                 let rhs = bytecode.stack_pop()?;
-                bytecode.stack.push(
-                    lhs.checked_add(rhs)
-                        .ok_or(format!("Addition overflow occurred ({} + {})", lhs, rhs))?,
-                );
+                let lhs = bytecode.stack_pop()?;
+                let comparison = self.comparison().expect("Instruction is a comparison");
+                bytecode.stack.push(comparison.holds(lhs, rhs).into());
                 bytecode.position += 1;
             }
-            Instruction::Sub => {
+            Instruction::LogicalAnd | Instruction::LogicalOr => {
                 let rhs = bytecode.stack_pop()?;
                 let lhs = bytecode.stack_pop()?;
-                bytecode.stack.push(lhs.checked_sub(rhs).ok_or(format!(
-                    "Substraction overflow occurred ({} - {})",
-                    lhs, rhs
-                ))?);
+                let logic = match self {
+                    Instruction::LogicalAnd => Logic::And,
+                    _ => Logic::Or,
+                };
+                bytecode.stack.push(logic.holds(lhs, rhs).into());
                 bytecode.position += 1;
             }
-            Instruction::Mul => {
-                let lhs = bytecode.stack_pop()?;
-                let rhs = bytecode.stack_pop()?;
-                bytecode.stack.push(lhs.checked_mul(rhs).ok_or(format!(
-                    "Multiplication overflow occurred ({} * {})",
-                    lhs, rhs
-                ))?);
+            Instruction::LogicalNot => {
+                let value = bytecode.stack_pop()?;
+                bytecode.stack.push((value == 0).into());
                 bytecode.position += 1;
             }
             Instruction::RetVal => {
                 bytecode.ret = Some(bytecode.stack_pop()?);
-                bytecode.count_of_threads.fetch_sub(1, Ordering::Relaxed);
             }
             Instruction::Jump => {
                 bytecode.position = bytecode.stack_pop()?;
@@ -155,27 +432,33 @@ impl Instruction {
                     bytecode.position + 1
                 };
             }
-            Instruction::Spawn => {
+            Instruction::JumpIf | Instruction::JumpIfNot => {
+                let position = bytecode.stack_pop()?;
+                let condition = bytecode.stack_pop()?;
+                bytecode.position = if (condition != 0) == (*self == Instruction::JumpIf) {
+                    position
+                } else {
+                    bytecode.position + 1
+                };
+            }
+            Instruction::Spawn | Instruction::SpawnIds => {
                 let start_b = bytecode.stack_pop()?;
                 let arguments_b = bytecode.stack_pop()?;
 
                 let start_a = bytecode.stack_pop()?;
                 let arguments_a = bytecode.stack_pop()?;
+                match bytecode.config.limits.max_spawn_depth {
+                    Some(max) if bytecode.depth >= max => {
+                        return Err(Error::Exceeded {
+                            resource: Resource::SpawnDepth,
+                            maximum: max,
+                        })
+                    }
+                    _ => {}
+                }
 
-                let mut bytecode_a = ByteCode::new(bytecode.instructions().to_vec());
-                bytecode_a.count_of_threads = bytecode.count_of_threads.clone();
-                bytecode_a.id = bytecode.count_of_threads.fetch_add(1, Ordering::Relaxed) + 1;
-                bytecode_a.position = start_a;
-
-                let (tx, rx) = mpsc::sync_channel(0);
-                bytecode.receivers.insert(bytecode_a.id, rx);
-                bytecode_a.senders.insert(bytecode.id, tx);
-
-                let mut bytecode_b = ByteCode::new(bytecode.instructions().to_vec());
-                bytecode_b.count_of_threads = bytecode.count_of_threads.clone();
-                bytecode_b.id = bytecode.count_of_threads.fetch_add(1, Ordering::Relaxed) + 1;
-                bytecode_b.position = start_b;
-
+                let mut bytecode_a = bytecode.spawn_child(start_a);
+                let mut bytecode_b = bytecode.spawn_child(start_b);
                 for _ in 0..arguments_b {
                     bytecode_b.stack.push(bytecode.stack_pop()?);
                 }
@@ -183,71 +466,255 @@ impl Instruction {
                     bytecode_a.stack.push(bytecode.stack_pop()?);
                 }
 
-                let (tx, rx) = mpsc::sync_channel(0);
-                bytecode.receivers.insert(bytecode_b.id, rx);
-                bytecode_b.senders.insert(bytecode.id, tx);
-
-                thread::Builder::new()
-                    .name(format!("{}", bytecode_a.id))
-                    .spawn(move || {
-                        bytecode_a.interpret().unwrap();
-                    })
-                    .unwrap();
-                thread::Builder::new()
-                    .name(format!("{}", bytecode_b.id))
-                    .spawn(move || {
-                        bytecode_b.interpret().unwrap();
-                    })
-                    .unwrap();
+                if *self == Instruction::SpawnIds {
+                    bytecode.stack.push(bytecode_a.id as Data);
+                    bytecode.stack.push(bytecode_b.id as Data);
+                }
+                bytecode
+                    .runtime
+                    .spawn([bytecode_a, bytecode_b], bytecode.config.limits.max_threads)?;
                 bytecode.position += 1;
             }
             Instruction::SendChannel => {
-                let channel = bytecode.stack_pop()?;
-                let data = bytecode.stack_pop()?;
-                bytecode
-                    .senders
-                    .get(&(channel as usize))
-                    .ok_or(format!("Sender {} doesn't exist", channel))?
-                    .send(data)
-                    .map_err(|e| format!("Sender ({}) failed: {}", channel, e))?;
-                bytecode.position += 1;
+                // The data and the channel stay on the stack, until the receiver takes the data
+                let [data, channel] = match bytecode.stack.as_slice() {
+                    [.., data, channel] => [*data, *channel],
+                    _ => return Err("Stack is empty".into()),
+                };
+                let to = Id::try_from(channel)
+                    .map_err(|_| format!("Sender {} doesn't exist", channel))?;
+                let received = bytecode.runtime.send(
+                    bytecode.id,
+                    to,
+                    data,
+                    bytecode.config.limits.max_messages,
+                )?;
+                if received {
+                    bytecode.stack_pop()?;
+                    bytecode.stack_pop()?;
+                    bytecode.position += 1;
+                } else {
+                    bytecode.waiting = Some(Wait::Send(to));
+                }
             }
             Instruction::RecvChannel => {
-                let channel = bytecode.stack_pop()?;
-                let data = bytecode
-                    .receivers
-                    .get(&(channel as usize))
-                    .ok_or(format!("Receiver {} doesn't exist", channel))?
-                    .recv()
-                    .map_err(|e| format!("Receiver failed: {}", e))?;
-                bytecode.stack.push(data);
+                // The channel stays on the stack, until the data is received
+                let channel = *bytecode.stack.last().ok_or("Stack is empty")?;
+                let from = Id::try_from(channel)
+                    .map_err(|_| format!("Receiver {} doesn't exist", channel))?;
+                match bytecode.runtime.try_recv(from, bytecode.id)? {
+                    Some(data) => {
+                        bytecode.stack_pop()?;
+                        bytecode.stack.push(data);
+                        bytecode.position += 1;
+                    }
+                    None => bytecode.waiting = Some(Wait::Recv(from)),
+                }
+            }
+            Instruction::SelfId => {
+                bytecode.stack.push(bytecode.id as Data);
+                bytecode.position += 1;
+            }
+            Instruction::ParentId => {
+                let parent = bytecode.parent.ok_or("Thread doesn't have a parent")?;
+                bytecode.stack.push(parent as Data);
                 bytecode.position += 1;
             }
             Instruction::Log => {
-                println!("\x1b[31mLOG: {}\x1b[0m", bytecode.stack_pop()?);
+                let data = bytecode.stack_pop()?;
+                match &bytecode.config.observer {
+                    Some(observer) => observer.log(bytecode.id, data),
+                    None => println!("\x1b[31mLOG: {}\x1b[0m", data),
+                }
                 bytecode.position += 1;
             }
-            Instruction::Unk => unreachable!(),
+            Instruction::CallHost(name) => {
+                let function = bytecode
+                    .config
+                    .host_functions
+                    .get(name)
+                    .ok_or(format!("Host function `{}` isn't registered", name))?
+                    .clone();
+                let mut arguments = Vec::with_capacity(function.arity());
+                for _ in 0..function.arity() {
+                    arguments.push(bytecode.stack_pop()?);
+                }
+                arguments.reverse();
+                let result = function.call(&arguments)?;
+                bytecode.stack.push(result);
+                bytecode.position += 1;
+            }
+            Instruction::Unk => return Err("Unknown instruction".into()),
         }
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Add,
+    Sub,
+    Mul,
+}
+
+impl Operation {
+    pub(crate) fn overflowing(self, lhs: Data, rhs: Data) -> (Data, bool) {
+        match self {
+            Operation::Add => lhs.overflowing_add(rhs),
+            Operation::Sub => lhs.overflowing_sub(rhs),
+            Operation::Mul => lhs.overflowing_mul(rhs),
+        }
+    }
+
+    pub(crate) fn apply(self, lhs: Data, rhs: Data, overflow: Overflow) -> Result<Data, Error> {
+        let (wrapped, overflowed) = self.overflowing(lhs, rhs);
+        let result = match overflow {
+            _ if !overflowed => wrapped,
+            Overflow::Wrapping => wrapped,
+            // Only the subtraction overflows below zero
+            Overflow::Saturating => match self {
+                Operation::Sub => Data::MIN,
+                Operation::Add | Operation::Mul => Data::MAX,
+            },
+            Overflow::Checked => {
+                let message = match self {
+                    Operation::Add => format!("Addition overflow occurred ({} + {})", lhs, rhs),
+                    Operation::Sub => {
+                        format!("Substraction overflow occurred ({} - {})", lhs, rhs)
+                    }
+                    Operation::Mul => {
+                        format!("Multiplication overflow occurred ({} * {})", lhs, rhs)
+                    }
+                };
+                return Err(message.into());
+            }
+        };
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    Greater,
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+}
+
+impl Comparison {
+    pub(crate) fn holds(self, lhs: Data, rhs: Data) -> bool {
+        match self {
+            Comparison::Less => lhs < rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::LessEqual => lhs <= rhs,
+            Comparison::GreaterEqual => lhs >= rhs,
+        }
+    }
+
+    pub(crate) fn operator(self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::Greater => ">",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::LessEqual => "<=",
+            Comparison::GreaterEqual => ">=",
+        }
+    }
+}
+
+// Operands are true, if they aren't 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Logic {
+    And,
+    Or,
+}
+
+impl Logic {
+    pub(crate) fn holds(self, lhs: Data, rhs: Data) -> bool {
+        match self {
+            Logic::And => lhs != 0 && rhs != 0,
+            Logic::Or => lhs != 0 || rhs != 0,
+        }
+    }
+
+    pub(crate) fn operator(self) -> &'static str {
+        match self {
+            Logic::And => "&&",
+            Logic::Or => "||",
+        }
+    }
+}
+
+// How an arithmetic instruction handles overflows
+#[derive(Debug, Clone, Copy)]
+enum Handling {
+    Config,
+    Overflow(Overflow),
+    Carry,
+}
+
+const WRAPPING: Handling = Handling::Overflow(Overflow::Wrapping);
+const SATURATING: Handling = Handling::Overflow(Overflow::Saturating);
+
+// The left operand is below the right one on the stack
+fn arithmetic(
+    bytecode: &mut ByteCode,
+    operation: Operation,
+    handling: Handling,
+) -> Result<(), Error> {
+    let rhs = bytecode.stack_pop()?;
+    let lhs = bytecode.stack_pop()?;
+    let overflow = match handling {
+        Handling::Config => bytecode.config.overflow,
+        Handling::Overflow(overflow) => overflow,
+        Handling::Carry => {
+            let (wrapped, overflowed) = operation.overflowing(lhs, rhs);
+            bytecode.stack.push(wrapped);
+            bytecode.stack.push(overflowed.into());
+            bytecode.position += 1;
+            return Ok(());
+        }
+    };
+    bytecode.stack.push(operation.apply(lhs, rhs, overflow)?);
+    bytecode.position += 1;
+    Ok(())
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct IndexedInstruction {
     index: usize,
     instruction: Instruction,
+    // The file, which the instruction is written in, if it's known
+    file: Option<Arc<str>>,
 }
 
 impl IndexedInstruction {
     pub fn new(index: usize, instruction: Instruction) -> Self {
-        Self { index, instruction }
+        Self {
+            index,
+            instruction,
+            file: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: Option<Arc<str>>) -> Self {
+        self.file = file;
+        self
     }
 
     pub fn index(&self) -> usize {
         self.index
     }
 
+    pub fn file(&self) -> Option<&Arc<str>> {
+        self.file.as_ref()
+    }
+
     pub fn instruction(&self) -> &Instruction {
         &self.instruction
     }
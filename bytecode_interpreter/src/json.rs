@@ -0,0 +1,633 @@
+// Programs as JSON, so other tools don't have to emit the text format:
+//
+// {
+//   "format": "bytecode-program",
+//   "version": 1,
+//   "metadata": {"generator": "compiler"},
+//   "instructions": [
+//     {"opcode": "LOAD_VAL", "operands": [5], "line": 1, "file": "main.bc"},
+//     {"opcode": "RETURN_VALUE"}
+//   ]
+// }
+//
+// `metadata`, `operands`, `line` and `file` are optional. Lines are 1-based like in parse errors,
+// an instruction without the line gets its position. Values of `LOAD_VAL` may be strings, so they
+// stay exact in tools, which keep numbers as floats. Errors point at the JSON path of the wrong
+// value, e.g. `$.instructions[2].opcode`.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use crate::{instructions::IteratorWrapper, ByteCode, IndexedInstruction, Instruction, ParseError};
+
+const FORMAT: &str = "bytecode-program";
+const VERSION: &str = "1";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    // The text of the number, so big integers aren't rounded
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    // Fields in the order of the input
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn parse(input: &str) -> Result<Json, String> {
+        let mut parser = Parser { input, offset: 0 };
+        let value = parser.value(0)?;
+        parser.whitespace();
+        if parser.offset < input.len() {
+            return Err(parser.error("Unexpected characters after the value"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: &str) -> String {
+        let before = &self.input[..self.offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            + 1;
+        format!(
+            "Invalid JSON at line {}, column {}: {}",
+            line, column, message
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn whitespace(&mut self) {
+        let rest = &self.input[self.offset..];
+        let trimmed = rest.trim_start_matches([' ', '\t', '\n', '\r']);
+        self.offset += rest.len() - trimmed.len();
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("Expected `{}`", c)));
+        }
+        self.offset += 1;
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        // Nesting is limited, so a hostile input can't overflow the stack
+        if depth > 128 {
+            return Err(self.error("Values are nested too deeply"));
+        }
+        self.whitespace();
+        let rest = &self.input[self.offset..];
+        for (word, value) in [
+            ("null", Json::Null),
+            ("true", Json::Bool(true)),
+            ("false", Json::Bool(false)),
+        ] {
+            if rest.starts_with(word) {
+                self.offset += word.len();
+                return Ok(value);
+            }
+        }
+        match self.peek() {
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.offset += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.offset += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(',') => self.offset += 1,
+                        Some(']') => {
+                            self.offset += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(self.error("Expected `,` or `]`")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.offset += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.offset += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.whitespace();
+                    if self.peek() != Some('"') {
+                        return Err(self.error("Expected a name of a field"));
+                    }
+                    let name = self.string()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value(depth + 1)?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(',') => self.offset += 1,
+                        Some('}') => {
+                            self.offset += 1;
+                            return Ok(Json::Object(fields));
+                        }
+                        _ => return Err(self.error("Expected `,` or `}`")),
+                    }
+                }
+            }
+            Some('-' | '0'..='9') => {
+                let len = rest
+                    .find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                    .unwrap_or(rest.len());
+                let number = &rest[..len];
+                let digits = number.strip_prefix('-').unwrap_or(number);
+                let valid = !digits.is_empty()
+                    && digits.starts_with(|c: char| c.is_ascii_digit())
+                    && (!digits.starts_with('0') || len_of_integer(digits) == 1)
+                    && number.parse::<f64>().is_ok();
+                if !valid {
+                    return Err(self.error(&format!("Invalid number `{}`", number)));
+                }
+                self.offset += len;
+                Ok(Json::Number(number.into()))
+            }
+            Some(_) => Err(self.error("Expected a value")),
+            None => Err(self.error("Unexpected end of the input")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.offset += 1;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("Unterminated string"));
+            };
+            match c {
+                '"' => {
+                    self.offset += 1;
+                    return Ok(string);
+                }
+                '\\' => {
+                    self.offset += 1;
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.offset += 1;
+                            string.push(self.unicode()?);
+                            continue;
+                        }
+                        _ => return Err(self.error("Invalid escape sequence")),
+                    };
+                    self.offset += 1;
+                    string.push(escaped);
+                }
+                c if c.is_control() => return Err(self.error("Control character in a string")),
+                c => {
+                    self.offset += c.len_utf8();
+                    string.push(c);
+                }
+            }
+        }
+    }
+
+    // The character of `\uXXXX` or of a surrogate pair of them, the offset is after `\u`
+    fn unicode(&mut self) -> Result<char, String> {
+        let high = self.hex()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("Invalid unicode escape"));
+        }
+        if !self.input[self.offset..].starts_with("\\u") {
+            return Err(self.error("Unpaired surrogate"));
+        }
+        self.offset += 2;
+        let low = self.hex()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("Unpaired surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| self.error("Invalid unicode escape"))
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits = self.input.get(self.offset..self.offset + 4);
+        let code = digits
+            .filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("Invalid unicode escape"))?;
+        self.offset += 4;
+        Ok(code)
+    }
+}
+
+// Count of digits before the fraction or the exponent
+fn len_of_integer(digits: &str) -> usize {
+    digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len())
+}
+
+fn escape(output: &mut String, text: &str) {
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(output, "\\u{:04x}", u32::from(c));
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+// Instructions with free-form information about them, e.g. the tool, which has made them
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Program {
+    pub metadata: BTreeMap<String, String>,
+    pub instructions: Vec<IndexedInstruction>,
+}
+
+impl Program {
+    // Errors are prefixed with JSON paths of wrong values
+    pub fn from_json(input: impl AsRef<str>) -> Result<Self, Vec<String>> {
+        let json = Parser::parse(input.as_ref()).map_err(|e| vec![e])?;
+        let mut errors = Vec::new();
+        let mut program = Program::default();
+        let Json::Object(fields) = json else {
+            return Err(vec![expected("$", "an object", &json)]);
+        };
+        let mut instructions = None;
+        for (name, value) in &fields {
+            let path = format!("$.{}", name);
+            match (name.as_str(), value) {
+                ("format", Json::String(format)) if format == FORMAT => {}
+                ("format", value) => errors.push(format!(
+                    "{}: Expected \"{}\", not {}",
+                    path,
+                    FORMAT,
+                    show(value)
+                )),
+                ("version", Json::Number(version)) if version == VERSION => {}
+                ("version", value) => {
+                    errors.push(format!("{}: Unsupported version {}", path, show(value)))
+                }
+                ("metadata", Json::Object(metadata)) => {
+                    for (key, value) in metadata {
+                        match value {
+                            Json::String(value) => {
+                                program.metadata.insert(key.clone(), value.clone());
+                            }
+                            value => errors.push(expected(
+                                &format!("{}.{}", path, key),
+                                "a string",
+                                value,
+                            )),
+                        }
+                    }
+                }
+                ("metadata", value) => errors.push(expected(&path, "an object", value)),
+                ("instructions", Json::Array(items)) => instructions = Some(items),
+                ("instructions", value) => errors.push(expected(&path, "an array", value)),
+                _ => errors.push(format!("{}: Unknown field", path)),
+            }
+        }
+        for field in ["format", "version", "instructions"] {
+            if !fields.iter().any(|(name, _)| name == field) {
+                errors.push(format!("$.{}: Missing field", field));
+            }
+        }
+        for (position, item) in instructions.into_iter().flatten().enumerate() {
+            let path = format!("$.instructions[{}]", position);
+            match instruction(&path, position, item) {
+                Ok(instruction) => program.instructions.push(instruction),
+                Err(e) => errors.extend(e),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(program)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut output = format!(
+            "{{\n  \"format\": \"{}\",\n  \"version\": {},\n  \"metadata\": {{",
+            FORMAT, VERSION
+        );
+        for (i, (key, value)) in self.metadata.iter().enumerate() {
+            output.push_str(if i == 0 { "\n    " } else { ",\n    " });
+            escape(&mut output, key);
+            output.push_str(": ");
+            escape(&mut output, value);
+        }
+        if !self.metadata.is_empty() {
+            output.push_str("\n  ");
+        }
+        output.push_str("},\n  \"instructions\": [");
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            output.push_str(if i == 0 { "\n    " } else { ",\n    " });
+            let _ = write!(
+                output,
+                "{{\"opcode\": \"{}\"",
+                instruction.instruction().mnemonic()
+            );
+            match instruction.instruction() {
+                Instruction::LoadVal(value) => {
+                    let _ = write!(output, ", \"operands\": [{}]", value);
+                }
+                Instruction::WriteVar(ident)
+                | Instruction::ReadVar(ident)
+                | Instruction::CallHost(ident) => {
+                    output.push_str(", \"operands\": [");
+                    escape(&mut output, ident);
+                    output.push(']');
+                }
+                _ => {}
+            }
+            let _ = write!(output, ", \"line\": {}", instruction.index() + 1);
+            if let Some(file) = instruction.file() {
+                output.push_str(", \"file\": ");
+                escape(&mut output, file);
+            }
+            output.push('}');
+        }
+        if !self.instructions.is_empty() {
+            output.push_str("\n  ");
+        }
+        output.push_str("]\n}\n");
+        output
+    }
+}
+
+fn instruction(
+    path: &str,
+    position: usize,
+    item: &Json,
+) -> Result<IndexedInstruction, Vec<String>> {
+    let Json::Object(fields) = item else {
+        return Err(vec![expected(path, "an object", item)]);
+    };
+    let mut errors = Vec::new();
+    let (mut opcode, mut operands, mut line, mut file) = (None, Vec::new(), position, None);
+    for (name, value) in fields {
+        let path = format!("{}.{}", path, name);
+        match (name.as_str(), value) {
+            ("opcode", Json::String(mnemonic)) => opcode = Some(mnemonic.as_str()),
+            ("operands", Json::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    let path = format!("{}[{}]", path, i);
+                    match item {
+                        Json::Number(text) => operands.push(text.as_str()),
+                        // Words of the text format can't be empty, have spaces or look like
+                        // comments
+                        Json::String(text)
+                            if text.is_empty()
+                                || text.contains(char::is_whitespace)
+                                || text.starts_with("//") =>
+                        {
+                            errors.push(format!("{}: Invalid operand {}", path, show(item)))
+                        }
+                        Json::String(text) => operands.push(text.as_str()),
+                        item => errors.push(expected(&path, "a number or a string", item)),
+                    }
+                }
+            }
+            ("line", Json::Number(number)) => match number.parse::<usize>() {
+                Ok(number) if number > 0 => line = number - 1,
+                _ => errors.push(format!("{}: Invalid line {}", path, number)),
+            },
+            ("file", Json::String(name)) => file = Some(Arc::from(name.as_str())),
+            ("opcode", value) | ("file", value) => errors.push(expected(&path, "a string", value)),
+            ("operands", value) => errors.push(expected(&path, "an array", value)),
+            ("line", value) => errors.push(expected(&path, "a number", value)),
+            _ => errors.push(format!("{}: Unknown field", path)),
+        }
+    }
+    let Some(opcode) = opcode else {
+        if !fields.iter().any(|(name, _)| name == "opcode") {
+            errors.push(format!("{}.opcode: Missing field", path));
+        }
+        return Err(errors);
+    };
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let words = std::iter::once(opcode).chain(operands.iter().copied());
+    match Instruction::try_from(IteratorWrapper(words)) {
+        Ok(instruction) => Ok(IndexedInstruction::new(line, instruction).with_file(file)),
+        Err(e) => Err(vec![parse_error(path, &e)]),
+    }
+}
+
+// Words of the text format are the opcode and the operands
+fn parse_error(path: &str, e: &ParseError) -> String {
+    match e.word {
+        0 => format!("{}.opcode: {}", path, e),
+        word => format!("{}.operands[{}]: {}", path, word - 1, e),
+    }
+}
+
+fn expected(path: &str, kind: &str, value: &Json) -> String {
+    format!("{}: Expected {}, not {}", path, kind, value.kind())
+}
+
+fn show(value: &Json) -> String {
+    match value {
+        Json::Number(number) => number.clone(),
+        Json::String(text) => {
+            let mut output = String::new();
+            escape(&mut output, text);
+            output
+        }
+        value => value.kind().to_string(),
+    }
+}
+
+impl ByteCode {
+    pub fn from_json(input: impl AsRef<str>) -> Result<Self, Vec<String>> {
+        Program::from_json(input).map(|program| Self::new(program.instructions))
+    }
+
+    pub fn to_json(&self) -> String {
+        Program {
+            metadata: BTreeMap::new(),
+            instructions: self.instructions.to_vec(),
+        }
+        .to_json()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Json, Parser, Program};
+    use crate::{ByteCode, Instruction};
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            Parser::parse(r#" {"a": [1, -2.5e3, "\"\u00e9\ud83d\ude00", true, null], "b": {}} "#),
+            Ok(Json::Object(vec![
+                (
+                    "a".into(),
+                    Json::Array(vec![
+                        Json::Number("1".into()),
+                        Json::Number("-2.5e3".into()),
+                        Json::String("\"é😀".into()),
+                        Json::Bool(true),
+                        Json::Null,
+                    ])
+                ),
+                ("b".into(), Json::Object(vec![])),
+            ]))
+        );
+        for (input, error) in [
+            ("", "line 1, column 1: Unexpected end of the input"),
+            ("[1,]", "line 1, column 4: Expected a value"),
+            ("{\"a\" 1}", "line 1, column 6: Expected `:`"),
+            ("[\n01]", "line 2, column 1: Invalid number `01`"),
+            ("\"\\ud800\"", "line 1, column 8: Unpaired surrogate"),
+            (
+                "[1] 2",
+                "line 1, column 5: Unexpected characters after the value",
+            ),
+        ] {
+            assert_eq!(
+                Parser::parse(input),
+                Err(format!("Invalid JSON at {}", error)),
+                "{}",
+                input
+            );
+        }
+        assert!(Parser::parse(&"[".repeat(1000)).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytecode = ByteCode::from_bytecode_text(
+            "\nLOAD_VAL 340282366920938463463374607431768211455\nREAD_VAR x\nADD\nRETURN_VALUE",
+        )
+        .unwrap();
+        let json = bytecode.to_json();
+        assert_eq!(
+            json,
+            r#"{
+  "format": "bytecode-program",
+  "version": 1,
+  "metadata": {},
+  "instructions": [
+    {"opcode": "LOAD_VAL", "operands": [340282366920938463463374607431768211455], "line": 2},
+    {"opcode": "READ_VAR", "operands": ["x"], "line": 3},
+    {"opcode": "ADD", "line": 4},
+    {"opcode": "RETURN_VALUE", "line": 5}
+  ]
+}
+"#
+        );
+        let imported = ByteCode::from_json(&json).unwrap();
+        assert_eq!(imported.instructions(), bytecode.instructions());
+
+        let program = Program::from_json(
+            r#"{"format": "bytecode-program", "version": 1,
+                "metadata": {"generator": "test \"1\""},
+                "instructions": [{"opcode": "LOAD_VAL", "operands": ["7"], "file": "a.bc"},
+                                 {"opcode": "RETURN_VALUE"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(program.metadata["generator"], "test \"1\"");
+        assert_eq!(
+            program.instructions[0].instruction(),
+            &Instruction::LoadVal(7)
+        );
+        assert_eq!(program.instructions[0].file().map(|f| &**f), Some("a.bc"));
+        assert_eq!(program.instructions[1].index(), 1);
+        assert_eq!(Program::from_json(program.to_json()), Ok(program));
+    }
+
+    #[test]
+    fn validation_errors() {
+        let errors = ByteCode::from_json(
+            r#"{"format": "bytecode-program", "version": 2, "extra": 1, "metadata": {"a": 1},
+                "instructions": [
+                  {"opcode": "ADDD"},
+                  {"opcode": "LOAD_VAL", "operands": [-1]},
+                  {"opcode": "READ_VAR"},
+                  {"opcode": "ADD", "operands": ["x"]},
+                  {"opcode": "WRITE_VAR", "operands": ["a b"], "line": 0},
+                  {"operands": [true]},
+                  "RETURN_VALUE"
+                ]}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "$.version: Unsupported version 2",
+                "$.extra: Unknown field",
+                "$.metadata.a: Expected a string, not a number",
+                "$.instructions[0].opcode: Unknown instruction `ADDD`, did you mean `ADD`?",
+                "$.instructions[1].operands[0]: Invalid operand `-1` of LOAD_VAL",
+                "$.instructions[2].operands[0]: Empty operand for READ_VAR",
+                "$.instructions[3].operands[0]: Unexpected operand `x` of ADD",
+                "$.instructions[4].operands[0]: Invalid operand \"a b\"",
+                "$.instructions[4].line: Invalid line 0",
+                "$.instructions[5].operands[0]: Expected a number or a string, not a boolean",
+                "$.instructions[5].opcode: Missing field",
+                "$.instructions[6]: Expected an object, not a string",
+            ]
+        );
+        assert_eq!(
+            ByteCode::from_json("[]").unwrap_err(),
+            vec!["$: Expected an object, not an array"]
+        );
+        assert_eq!(
+            ByteCode::from_json("{}").unwrap_err(),
+            vec![
+                "$.format: Missing field",
+                "$.version: Missing field",
+                "$.instructions: Missing field"
+            ]
+        );
+    }
+}
@@ -0,0 +1,983 @@
+// Small language, which is compiled to instructions:
+//
+// fn child(n) {                    threads, which are started by `spawn`
+//     send(n * 2, parent_id());
+//     return 0;
+// }
+// a, b = spawn child(1), child(2); ids of both children
+// x = recv(a) + recv(b);           `+`, `-`, `*` and parentheses
+// if (x > 5) { log(x); } else { x = 0; }
+// while (x < 10) { x = x + 1; }
+// for (i = 0; i < 3; i = i + 1) { x = x * 2; }
+// big = x > 5 && !(x == 8);        comparisons and `&&`, `||`, `!` are 1 or 0
+// while (big || x < 3) { x = x + 1; big = 0; }
+// return x;
+//
+// Comparisons are `<`, `>`, `==`, `!=`, `<=`, `>=`. Conditions are expressions, which are true,
+// if they aren't 0, and `&&` and `||` evaluate both operands. Other calls in expressions are
+// `self_id()`, `parent_id()` and host functions. Threads and the program return 0, if they
+// don't return anything else. Instructions keep the lines of the statements, which they are
+// compiled from.
+
+use std::collections::HashMap;
+
+use crate::{
+    error::Location, instructions::Comparison, ByteCode, Data, Ident, IndexedInstruction,
+    Instruction,
+};
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Number(Data),
+    Ident(Ident),
+    Punct(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(n) => format!("`{}`", n),
+            Token::Ident(ident) => format!("`{}`", ident),
+            Token::Punct(punct) => format!("`{}`", punct),
+        }
+    }
+}
+
+const PUNCTS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "{", "}", ";", ",", "=", "+", "-", "*", "<", ">",
+    "!",
+];
+
+struct Spanned {
+    token: Token,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Spanned>, String> {
+    let mut tokens = Vec::new();
+    for (line, text) in source.lines().enumerate() {
+        let text = text.split("//").next().unwrap_or_default();
+        let mut rest = text.trim_start();
+        while !rest.is_empty() {
+            let token = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len());
+                let number = rest[..end].parse().map_err(|_| {
+                    format!(
+                        "{}, error: Invalid number `{}`",
+                        Location(None, line),
+                        &rest[..end]
+                    )
+                })?;
+                rest = &rest[end..];
+                Token::Number(number)
+            } else if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let ident = rest[..end].to_string();
+                rest = &rest[end..];
+                Token::Ident(ident)
+            } else {
+                let punct = PUNCTS
+                    .iter()
+                    .find(|punct| rest.starts_with(*punct))
+                    .ok_or_else(|| {
+                        let c = rest.chars().next().unwrap_or_default();
+                        format!(
+                            "{}, error: Unexpected character `{}`",
+                            Location(None, line),
+                            c
+                        )
+                    })?;
+                rest = &rest[punct.len()..];
+                Token::Punct(punct)
+            };
+            tokens.push(Spanned { token, line });
+            rest = rest.trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Compare(Comparison),
+    And,
+    Or,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(Data),
+    Var(Ident),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+    Not(Box<Expr>),
+    Call(Ident, Vec<Expr>),
+}
+
+#[derive(Debug)]
+struct Call {
+    function: Ident,
+    arguments: Vec<Expr>,
+}
+
+#[derive(Debug)]
+enum StmtKind {
+    Assign(Ident, Expr),
+    Spawn([Ident; 2], [Call; 2]),
+    Send(Expr, Expr),
+    Log(Expr),
+    Return(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    For(Box<Stmt>, Expr, Box<Stmt>, Vec<Stmt>),
+}
+
+#[derive(Debug)]
+struct Stmt {
+    line: usize,
+    kind: StmtKind,
+}
+
+struct Function {
+    line: usize,
+    name: Ident,
+    params: Vec<Ident>,
+    body: Vec<Stmt>,
+}
+
+const KEYWORDS: [&str; 9] = [
+    "if", "else", "while", "for", "return", "spawn", "fn", "send", "log",
+];
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|t| &t.token)
+    }
+
+    fn line(&self) -> usize {
+        match self.tokens.get(self.position) {
+            Some(token) => token.line,
+            None => self.tokens.last().map_or(0, |t| t.line),
+        }
+    }
+
+    fn error<T>(&self, message: impl AsRef<str>) -> Result<T, String> {
+        Err(format!(
+            "{}, error: {}",
+            Location(None, self.line()),
+            message.as_ref()
+        ))
+    }
+
+    fn found(&self) -> String {
+        self.peek()
+            .map_or("the end of the source".into(), Token::describe)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword)
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if !self.is_punct(punct) {
+            return self.error(format!("Expected `{}`, found {}", punct, self.found()));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        if !self.is_keyword(keyword) {
+            return self.error(format!("Expected `{}`, found {}", keyword, self.found()));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<Ident, String> {
+        match self.peek() {
+            Some(Token::Ident(ident)) if !KEYWORDS.contains(&ident.as_str()) => {
+                let ident = ident.clone();
+                self.position += 1;
+                Ok(ident)
+            }
+            _ => self.error(format!("Expected a name, found {}", self.found())),
+        }
+    }
+
+    fn program(&mut self) -> Result<(Vec<Stmt>, Vec<Function>), String> {
+        let mut statements = Vec::new();
+        let mut functions = Vec::new();
+        while self.peek().is_some() {
+            if self.is_keyword("fn") {
+                functions.push(self.function()?);
+            } else {
+                statements.push(self.statement()?);
+            }
+        }
+        Ok((statements, functions))
+    }
+
+    fn function(&mut self) -> Result<Function, String> {
+        let line = self.line();
+        self.expect_keyword("fn")?;
+        let name = self.ident()?;
+        self.expect("(")?;
+        let mut params = Vec::new();
+        while !self.is_punct(")") {
+            if !params.is_empty() {
+                self.expect(",")?;
+            }
+            params.push(self.ident()?);
+        }
+        self.expect(")")?;
+        let body = self.block()?;
+        Ok(Function {
+            line,
+            name,
+            params,
+            body,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, String> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        while !self.is_punct("}") {
+            if self.peek().is_none() {
+                return self.error("Expected `}`, found the end of the source");
+            }
+            statements.push(self.statement()?);
+        }
+        self.expect("}")?;
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        let line = self.line();
+        let kind = if self.is_keyword("if") {
+            self.position += 1;
+            let condition = self.condition()?;
+            let then = self.block()?;
+            let otherwise = if self.is_keyword("else") {
+                self.position += 1;
+                if self.is_keyword("if") {
+                    vec![self.statement()?]
+                } else {
+                    self.block()?
+                }
+            } else {
+                Vec::new()
+            };
+            StmtKind::If(condition, then, otherwise)
+        } else if self.is_keyword("while") {
+            self.position += 1;
+            let condition = self.condition()?;
+            StmtKind::While(condition, self.block()?)
+        } else if self.is_keyword("for") {
+            self.position += 1;
+            self.expect("(")?;
+            let init = self.simple_statement()?;
+            self.expect(";")?;
+            let condition = self.expression()?;
+            self.expect(";")?;
+            let step = self.simple_statement()?;
+            self.expect(")")?;
+            StmtKind::For(Box::new(init), condition, Box::new(step), self.block()?)
+        } else {
+            let statement = self.simple_statement()?;
+            self.expect(";")?;
+            return Ok(statement);
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    // Statement without a block, which is followed by `;` or `)`
+    fn simple_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.line();
+        let kind = if self.is_keyword("return") {
+            self.position += 1;
+            StmtKind::Return(self.expression()?)
+        } else if self.is_keyword("send") {
+            self.position += 1;
+            self.expect("(")?;
+            let data = self.expression()?;
+            self.expect(",")?;
+            let to = self.expression()?;
+            self.expect(")")?;
+            StmtKind::Send(data, to)
+        } else if self.is_keyword("log") {
+            self.position += 1;
+            self.expect("(")?;
+            let data = self.expression()?;
+            self.expect(")")?;
+            StmtKind::Log(data)
+        } else {
+            let name = self.ident()?;
+            if self.is_punct(",") {
+                self.position += 1;
+                let second = self.ident()?;
+                self.expect("=")?;
+                self.expect_keyword("spawn")?;
+                let a = self.call()?;
+                self.expect(",")?;
+                let b = self.call()?;
+                StmtKind::Spawn([name, second], [a, b])
+            } else {
+                self.expect("=")?;
+                StmtKind::Assign(name, self.expression()?)
+            }
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn call(&mut self) -> Result<Call, String> {
+        let function = self.ident()?;
+        let arguments = self.arguments()?;
+        Ok(Call {
+            function,
+            arguments,
+        })
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Expr>, String> {
+        self.expect("(")?;
+        let mut arguments = Vec::new();
+        while !self.is_punct(")") {
+            if !arguments.is_empty() {
+                self.expect(",")?;
+            }
+            arguments.push(self.expression()?);
+        }
+        self.expect(")")?;
+        Ok(arguments)
+    }
+
+    fn condition(&mut self) -> Result<Expr, String> {
+        self.expect("(")?;
+        let condition = self.expression()?;
+        self.expect(")")?;
+        Ok(condition)
+    }
+
+    // `||` binds weaker than `&&`, which binds weaker than comparisons
+    fn expression(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.conjunction()?;
+        while self.is_punct("||") {
+            self.position += 1;
+            lhs = Expr::Binary(Box::new(lhs), Operator::Or, Box::new(self.conjunction()?));
+        }
+        Ok(lhs)
+    }
+
+    fn conjunction(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.comparison()?;
+        while self.is_punct("&&") {
+            self.position += 1;
+            lhs = Expr::Binary(Box::new(lhs), Operator::And, Box::new(self.comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    // Comparisons aren't chained, `a < b < c` is an error
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let lhs = self.sum()?;
+        let comparison = match self.peek() {
+            Some(Token::Punct("<")) => Comparison::Less,
+            Some(Token::Punct(">")) => Comparison::Greater,
+            Some(Token::Punct("==")) => Comparison::Equal,
+            Some(Token::Punct("!=")) => Comparison::NotEqual,
+            Some(Token::Punct("<=")) => Comparison::LessEqual,
+            Some(Token::Punct(">=")) => Comparison::GreaterEqual,
+            _ => return Ok(lhs),
+        };
+        self.position += 1;
+        let rhs = self.sum()?;
+        Ok(Expr::Binary(
+            Box::new(lhs),
+            Operator::Compare(comparison),
+            Box::new(rhs),
+        ))
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Punct("+")) => Operator::Add,
+                Some(Token::Punct("-")) => Operator::Sub,
+                _ => return Ok(lhs),
+            };
+            self.position += 1;
+            lhs = Expr::Binary(Box::new(lhs), operator, Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while self.is_punct("*") {
+            self.position += 1;
+            lhs = Expr::Binary(Box::new(lhs), Operator::Mul, Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.is_punct("!") {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.factor()
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                let n = *n;
+                self.position += 1;
+                Ok(Expr::Number(n))
+            }
+            Some(Token::Punct("(")) => {
+                self.position += 1;
+                let expr = self.expression()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(_)) => {
+                let name = self.ident()?;
+                if self.is_punct("(") {
+                    Ok(Expr::Call(name, self.arguments()?))
+                } else {
+                    Ok(Expr::Var(name))
+                }
+            }
+            _ => self.error(format!("Expected an expression, found {}", self.found())),
+        }
+    }
+}
+
+enum Item {
+    Instruction(Instruction),
+    // Loads the position of the label
+    Address(usize),
+}
+
+#[derive(Default)]
+struct Codegen {
+    items: Vec<(usize, Item)>,
+    // Positions of labels
+    labels: Vec<Option<usize>>,
+    // Labels of functions and their count of parameters by names
+    functions: HashMap<Ident, (usize, usize)>,
+}
+
+impl Codegen {
+    fn emit(&mut self, line: usize, instruction: Instruction) {
+        self.items.push((line, Item::Instruction(instruction)));
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.items.len());
+    }
+
+    fn jump(&mut self, line: usize, label: usize) {
+        self.items.push((line, Item::Address(label)));
+        self.emit(line, Instruction::Jump);
+    }
+
+    fn program(&mut self, statements: &[Stmt], functions: &[Function]) -> Result<(), String> {
+        for function in functions {
+            let label = self.label();
+            let previous = self
+                .functions
+                .insert(function.name.clone(), (label, function.params.len()));
+            if previous.is_some() {
+                return Err(format!(
+                    "{}, error: Function `{}` is defined twice",
+                    Location(None, function.line),
+                    function.name
+                ));
+            }
+        }
+        self.body(statements, statements.last().map_or(0, |s| s.line))?;
+        for function in functions {
+            let (label, _) = self.functions[&function.name];
+            self.place(label);
+            // The first argument is on the top of the stack
+            for param in &function.params {
+                self.emit(function.line, Instruction::WriteVar(param.clone()));
+            }
+            let line = function.body.last().map_or(function.line, |s| s.line);
+            self.body(&function.body, line)?;
+        }
+        Ok(())
+    }
+
+    // Statements of a thread, which returns 0 at the end
+    fn body(&mut self, statements: &[Stmt], last_line: usize) -> Result<(), String> {
+        self.statements(statements)?;
+        if !matches!(
+            statements.last(),
+            Some(Stmt {
+                kind: StmtKind::Return(_),
+                ..
+            })
+        ) {
+            self.emit(last_line, Instruction::LoadVal(0));
+            self.emit(last_line, Instruction::RetVal);
+        }
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), String> {
+        statements.iter().try_for_each(|s| self.statement(s))
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), String> {
+        let line = statement.line;
+        match &statement.kind {
+            StmtKind::Assign(name, expr) => {
+                self.expression(line, expr)?;
+                self.emit(line, Instruction::WriteVar(name.clone()));
+            }
+            StmtKind::Spawn([a, b], calls) => {
+                let mut starts = Vec::new();
+                for call in calls {
+                    let Some(&(label, arity)) = self.functions.get(&call.function) else {
+                        return Err(format!(
+                            "{}, error: Function `{}` doesn't exist",
+                            Location(None, line),
+                            call.function
+                        ));
+                    };
+                    if call.arguments.len() != arity {
+                        return Err(format!(
+                            "{}, error: Function `{}` takes {} arguments, but {} are given",
+                            Location(None, line),
+                            call.function,
+                            arity,
+                            call.arguments.len()
+                        ));
+                    }
+                    for argument in &call.arguments {
+                        self.expression(line, argument)?;
+                    }
+                    starts.push((label, arity));
+                }
+                for (label, arity) in starts {
+                    self.emit(line, Instruction::LoadVal(arity as Data));
+                    self.items.push((line, Item::Address(label)));
+                }
+                self.emit(line, Instruction::SpawnIds);
+                self.emit(line, Instruction::WriteVar(b.clone()));
+                self.emit(line, Instruction::WriteVar(a.clone()));
+            }
+            StmtKind::Send(data, to) => {
+                self.expression(line, data)?;
+                self.expression(line, to)?;
+                self.emit(line, Instruction::SendChannel);
+            }
+            StmtKind::Log(data) => {
+                self.expression(line, data)?;
+                self.emit(line, Instruction::Log);
+            }
+            StmtKind::Return(data) => {
+                self.expression(line, data)?;
+                self.emit(line, Instruction::RetVal);
+            }
+            StmtKind::If(condition, then, otherwise) => {
+                let (target, negated) = self.branch(line, condition)?;
+                let end = self.label();
+                let (fallthrough, jumped) = if negated {
+                    (then, otherwise)
+                } else {
+                    (otherwise, then)
+                };
+                self.statements(fallthrough)?;
+                self.jump(line, end);
+                self.place(target);
+                self.statements(jumped)?;
+                self.place(end);
+            }
+            StmtKind::While(condition, body) => self.repeat(line, condition, body, None)?,
+            StmtKind::For(init, condition, step, body) => {
+                self.statement(init)?;
+                self.repeat(line, condition, body, Some(step))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Emits the condition and the jump to the returned label. The jump is taken, if the
+    // condition is true, unless the condition is negated. A comparison is fused with the jump,
+    // other conditions are computed and checked with `JUMP_IF` or `JUMP_IF_NOT`.
+    fn branch(&mut self, line: usize, condition: &Expr) -> Result<(usize, bool), String> {
+        let (instruction, negated) = match condition {
+            Expr::Binary(lhs, Operator::Compare(comparison), rhs) => {
+                self.expression(line, lhs)?;
+                self.expression(line, rhs)?;
+                match comparison {
+                    Comparison::Less => (Instruction::JumpLessThan, false),
+                    Comparison::Greater => (Instruction::JumpGreaterThan, false),
+                    Comparison::Equal => (Instruction::JumpEqual, false),
+                    Comparison::NotEqual => (Instruction::JumpEqual, true),
+                    Comparison::LessEqual => (Instruction::JumpGreaterThan, true),
+                    Comparison::GreaterEqual => (Instruction::JumpLessThan, true),
+                }
+            }
+            Expr::Not(operand) => {
+                self.expression(line, operand)?;
+                (Instruction::JumpIfNot, false)
+            }
+            condition => {
+                self.expression(line, condition)?;
+                (Instruction::JumpIf, false)
+            }
+        };
+        let target = self.label();
+        self.items.push((line, Item::Address(target)));
+        self.emit(line, instruction);
+        Ok((target, negated))
+    }
+
+    fn repeat(
+        &mut self,
+        line: usize,
+        condition: &Expr,
+        body: &[Stmt],
+        step: Option<&Stmt>,
+    ) -> Result<(), String> {
+        let top = self.label();
+        self.place(top);
+        let (target, negated) = self.branch(line, condition)?;
+        let end = if negated {
+            target
+        } else {
+            let end = self.label();
+            self.jump(line, end);
+            self.place(target);
+            end
+        };
+        self.statements(body)?;
+        if let Some(step) = step {
+            self.statement(step)?;
+        }
+        self.jump(line, top);
+        self.place(end);
+        Ok(())
+    }
+
+    fn expression(&mut self, line: usize, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::Number(n) => self.emit(line, Instruction::LoadVal(*n)),
+            Expr::Var(name) => self.emit(line, Instruction::ReadVar(name.clone())),
+            Expr::Binary(lhs, operator, rhs) => {
+                self.expression(line, lhs)?;
+                self.expression(line, rhs)?;
+                let instruction = match operator {
+                    Operator::Add => Instruction::Add,
+                    Operator::Sub => Instruction::Sub,
+                    Operator::Mul => Instruction::Mul,
+                    Operator::Compare(Comparison::Less) => Instruction::LessThan,
+                    Operator::Compare(Comparison::Greater) => Instruction::GreaterThan,
+                    Operator::Compare(Comparison::Equal) => Instruction::Equal,
+                    Operator::Compare(Comparison::NotEqual) => Instruction::NotEqual,
+                    Operator::Compare(Comparison::LessEqual) => Instruction::LessEqual,
+                    Operator::Compare(Comparison::GreaterEqual) => Instruction::GreaterEqual,
+                    Operator::And => Instruction::LogicalAnd,
+                    Operator::Or => Instruction::LogicalOr,
+                };
+                self.emit(line, instruction);
+            }
+            Expr::Not(operand) => {
+                self.expression(line, operand)?;
+                self.emit(line, Instruction::LogicalNot);
+            }
+            Expr::Call(name, arguments) => {
+                let builtin = match name.as_str() {
+                    "recv" => Some((1, Instruction::RecvChannel)),
+                    "self_id" => Some((0, Instruction::SelfId)),
+                    "parent_id" => Some((0, Instruction::ParentId)),
+                    _ => None,
+                };
+                if let Some((arity, _)) = builtin {
+                    if arguments.len() != arity {
+                        return Err(format!(
+                            "{}, error: Function `{}` takes {} arguments, but {} are given",
+                            Location(None, line),
+                            name,
+                            arity,
+                            arguments.len()
+                        ));
+                    }
+                }
+                for argument in arguments {
+                    self.expression(line, argument)?;
+                }
+                let instruction =
+                    builtin.map_or_else(|| Instruction::CallHost(name.clone()), |(_, i)| i);
+                self.emit(line, instruction);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Vec<IndexedInstruction> {
+        let labels = self.labels;
+        self.items
+            .into_iter()
+            .map(|(line, item)| {
+                let instruction = match item {
+                    Item::Instruction(instruction) => instruction,
+                    Item::Address(label) => {
+                        Instruction::LoadVal(labels[label].expect("Label isn't placed") as Data)
+                    }
+                };
+                IndexedInstruction::new(line, instruction)
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn compile(source: &str) -> Result<Vec<IndexedInstruction>, String> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+    };
+    let (statements, functions) = parser.program()?;
+    let mut codegen = Codegen::default();
+    codegen.program(&statements, &functions)?;
+    Ok(codegen.finish())
+}
+
+impl ByteCode {
+    pub fn from_source(input: impl AsRef<str>) -> Result<Self, Vec<String>> {
+        compile(input.as_ref()).map(Self::new).map_err(|e| vec![e])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ByteCode;
+
+    fn run(source: &str) -> u128 {
+        let mut bytecode = ByteCode::from_source(source).unwrap();
+        bytecode.interpret().unwrap();
+        *bytecode.ret().unwrap()
+    }
+
+    #[test]
+    fn expressions() {
+        let source = r#"
+x = 1;
+y = 2;
+z = 56;
+w = z + x + y;
+return (x + 1) * y * z + (w + 33);
+"#;
+        assert_eq!(run(source), 316);
+        assert_eq!(run("return 10 - 2 * 3 - 1;"), 3);
+        assert_eq!(run("x = 5;"), 0);
+    }
+
+    #[test]
+    fn pow() {
+        let source = r#"
+base = 12;
+exponent = 15;
+result = 1;
+while (exponent > 0) {
+    result = result * base;
+    exponent = exponent - 1;
+}
+return result;
+"#;
+        assert_eq!(run(source), 15_407_021_574_586_368);
+    }
+
+    #[test]
+    fn conditions() {
+        let compare = |lhs: u128, comparison: &str, rhs: u128| {
+            let source = format!(
+                "if ({} {} {}) {{ return 1; }} else {{ return 2; }}",
+                lhs, comparison, rhs
+            );
+            run(&source) == 1
+        };
+        for (lhs, rhs) in [(1, 2), (2, 2), (3, 2)] {
+            assert_eq!(compare(lhs, "<", rhs), lhs < rhs);
+            assert_eq!(compare(lhs, ">", rhs), lhs > rhs);
+            assert_eq!(compare(lhs, "==", rhs), lhs == rhs);
+            assert_eq!(compare(lhs, "!=", rhs), lhs != rhs);
+            assert_eq!(compare(lhs, "<=", rhs), lhs <= rhs);
+            assert_eq!(compare(lhs, ">=", rhs), lhs >= rhs);
+        }
+        let source = r#"
+x = 7;
+if (x < 5) {
+    return 1;
+} else if (x < 10) {
+    return 2;
+}
+return 3;
+"#;
+        assert_eq!(run(source), 2);
+    }
+
+    #[test]
+    fn logical_operators() {
+        let source = r#"
+count = 0;
+for (n = 0; n < 6; n = n + 1) {
+    done = n == 4;
+    if (n <= 1 || done) {
+        count = count + 1;
+    }
+    if (!(n < 2) && !done) {
+        count = count + 10;
+    }
+}
+return count * 10 + (3 > 2) + !7 + !0 * 2;
+"#;
+        assert_eq!(run(source), 333);
+        assert_eq!(run("return 1 + 1 == 2 && 0 || 2 * 3 >= 6;"), 1);
+        assert_eq!(run("x = 2; while (x) { x = x - 1; } return x;"), 0);
+        // Both operands are evaluated
+        let mut bytecode = ByteCode::from_source("return 1 || 0 - 1;").unwrap();
+        assert_eq!(
+            bytecode.interpret().unwrap_err().to_string(),
+            "Line: 1, error: Substraction overflow occurred (0 - 1)"
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let source = r#"
+sum = 0;
+for (i = 0; i < 10; i = i + 1) {
+    for (j = i; j != 0; j = j - 1) {
+        sum = sum + 1;
+    }
+}
+n = 0;
+while (n <= 4) { n = n + 1; }
+return sum * 100 + n;
+"#;
+        assert_eq!(run(source), 4505);
+    }
+
+    #[test]
+    fn fibonacci_multithreaded() {
+        let source = r#"
+a, b = spawn fib(10), fib(0);
+return recv(a) + recv(b);
+
+// sends the n-th Fibonacci number to the parent
+fn fib(n) {
+    if (n < 2) {
+        send(n, parent_id());
+        return 0;
+    }
+    a, b = spawn fib(n - 1), fib(n - 2);
+    send(recv(a) + recv(b), parent_id());
+}
+"#;
+        assert_eq!(run(source), 55);
+    }
+
+    #[test]
+    fn arguments_and_host_functions() {
+        let source = r#"
+a, b = spawn f(1, 2), f(3, 4);
+log(self_id());
+return recv(a) * 100 + recv(b) + double(1000);
+
+fn f(x, y) {
+    send(x * 10 + y, parent_id());
+}
+"#;
+        let mut bytecode = ByteCode::from_source(source).unwrap();
+        bytecode.register_host_function("double", 1, |args| Ok(args[0] * 2));
+        bytecode.interpret().unwrap();
+        assert_eq!(bytecode.ret(), Some(&(1200 + 34 + 2000)));
+    }
+
+    #[test]
+    fn compile_errors() {
+        let error = |source: &str| ByteCode::from_source(source).unwrap_err().join("\n");
+        assert_eq!(
+            error("x = 1;\ny = (x + 2;\n"),
+            "Line: 2, error: Expected `)`, found `;`"
+        );
+        assert_eq!(
+            error("x = 1\nreturn x;"),
+            "Line: 2, error: Expected `;`, found `return`"
+        );
+        assert_eq!(
+            error("x = 1 $ 2;"),
+            "Line: 1, error: Unexpected character `$`"
+        );
+        assert_eq!(
+            error("\nwhile (x <) { }"),
+            "Line: 2, error: Expected an expression, found `)`"
+        );
+        assert_eq!(
+            error("x = 1 < 2 < 3;"),
+            "Line: 1, error: Expected `;`, found `<`"
+        );
+        assert_eq!(
+            error("a, b = spawn f(), g();\nfn f() { }"),
+            "Line: 1, error: Function `g` doesn't exist"
+        );
+        assert_eq!(
+            error("a, b = spawn f(), f(1);\nfn f() { }"),
+            "Line: 1, error: Function `f` takes 0 arguments, but 1 are given"
+        );
+        assert_eq!(
+            error("fn f() { }\nfn f() { }"),
+            "Line: 2, error: Function `f` is defined twice"
+        );
+        assert_eq!(
+            error("if (1 < 2) {\n"),
+            "Line: 1, error: Expected `}`, found the end of the source"
+        );
+        assert_eq!(
+            error("while = 1;"),
+            "Line: 1, error: Expected `(`, found `=`"
+        );
+    }
+
+    #[test]
+    fn runtime_errors_point_to_source() {
+        let source = r#"
+x = 1;
+y = x - 2;
+return y;
+"#;
+        let mut bytecode = ByteCode::from_source(source).unwrap();
+        assert_eq!(
+            bytecode.interpret().unwrap_err().to_string(),
+            "Line: 3, error: Substraction overflow occurred (1 - 2)"
+        );
+    }
+}
@@ -1,60 +1,158 @@
-use std::{
-    collections::HashMap,
-    sync::{atomic::AtomicUsize, mpsc, Arc},
-};
-
+use std::{collections::HashMap, path::Path, sync::Arc, time::Instant};
+
+mod cfg;
+mod config;
+mod coverage;
+mod diagnostic;
+mod error;
+mod explore;
+mod future;
+pub mod fuzz;
+mod host;
 mod instructions;
-use instructions::{Ident, IndexedInstruction, Instruction, IteratorWrapper};
+mod json;
+mod lang;
+mod observer;
+mod preprocess;
+mod profile;
+mod record;
+mod register;
+mod repl;
+mod runtime;
+mod snapshot;
+mod transpile;
+mod vm;
+pub use cfg::{Block, Cfg, Dominators, Edge, EdgeKind, Loop, Successor};
+pub use config::{CancellationToken, Config, FailurePolicy, Limits, Overflow, Resource};
+pub use coverage::{Coverage, CoverageRecorder};
+pub use error::Error;
+pub use explore::{Execution, Explorer, Report, Schedule};
+pub use future::{block_on, Interpretation, LocalExecutor};
+pub use host::HostFunction;
+pub use instructions::{Comparison, IndexedInstruction, Instruction, Logic, Operation, ParseError};
+use instructions::{Ident, IteratorWrapper};
+pub use json::Program;
+pub use observer::{Observer, Step};
+use preprocess::Preprocessor;
+pub use profile::{Counter, Profile, Profiler, ThreadProfile};
+pub use record::{Event, Recording};
+pub use register::{Op, Operand, Register, RegisterCode};
+pub use repl::Repl;
+pub use runtime::{Registry, ThreadInfo, ThreadState};
+use runtime::{Runtime, Wait};
+pub use vm::{Outcome, Scheduling, VmBuilder};
 
 // TODO: There should be a hash number like `u256`
 type Data = u128;
 type Stack = Vec<Data>;
 type Memory = HashMap<Ident, Data>;
 type Address = Data;
-// TODO: We should use UUID for example or another unique id
-type Id = usize;
+const DATA_BYTES: usize = std::mem::size_of::<Data>();
+const CLOCK_INTERVAL: usize = 1024;
+// Unique inside of one thread tree
+pub type Id = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Finished,
+    // The limit of steps is over, interpreting again resumes the execution
+    Paused,
+}
 
 #[derive(Debug, Default)]
 pub struct ByteCode {
     id: Id,
-    // FIXME: dirty hack
-    count_of_threads: Arc<AtomicUsize>,
-    instructions: Vec<IndexedInstruction>,
+    parent: Option<Id>,
+    instructions: Arc<[IndexedInstruction]>,
     stack: Stack,
     memory: Memory,
     position: Address,
-    senders: HashMap<Id, mpsc::SyncSender<Data>>,
-    receivers: HashMap<Id, mpsc::Receiver<Data>>,
+    runtime: Arc<Runtime>,
+    config: Arc<Config>,
+    // Set by an instruction, which can't be completed right now
+    waiting: Option<Wait>,
+    // Count of executed instructions
+    steps: u64,
     ret: Option<Data>,
+    // Count of ancestors
+    depth: usize,
+    usage: Usage,
+}
+
+// Bytes, which the thread takes in the heap of the tree. They are counted only with the limit.
+#[derive(Debug, Default, Clone, Copy)]
+struct Usage {
+    // Count of variables, whose bytes are counted
+    vars: usize,
+    memory: usize,
+    total: usize,
 }
 
 impl ByteCode {
     pub fn new(instructions: Vec<IndexedInstruction>) -> Self {
         Self {
-            instructions,
+            instructions: instructions.into(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn spawn_child(&self, position: Address) -> Self {
+        Self {
+            id: self.runtime.allocate_id(),
+            parent: Some(self.id),
+            instructions: self.instructions.clone(),
+            stack: Stack::new(),
+            memory: Memory::new(),
+            position,
+            runtime: self.runtime.clone(),
+            config: self.config.clone(),
+            waiting: None,
+            steps: 0,
+            ret: None,
+            depth: self.depth + 1,
+            usage: Usage::default(),
+        }
+    }
+
+    // The same bytecode in its own new tree
+    pub(crate) fn fresh(&self) -> Self {
+        Self {
+            instructions: self.instructions.clone(),
+            stack: self.stack.clone(),
+            memory: self.memory.clone(),
+            position: self.position,
+            config: self.config.clone(),
             ..Default::default()
         }
     }
 
     pub fn from_bytecode_text(input: impl AsRef<str>) -> Result<Self, Vec<String>> {
-        let (instructions, errors): (Vec<_>, Vec<_>) = input
-            .as_ref()
-            .lines()
-            .enumerate()
-            .filter(|(_, l)| !(l.starts_with("//") || l.is_empty()))
-            .map(|(i, l)| (i, l.split_ascii_whitespace()))
-            .map(|(i, l)| (i, Instruction::try_from(IteratorWrapper(l))))
-            .partition(|(_, l)| l.is_ok());
+        let mut preprocessor = Preprocessor::default();
+        preprocessor.process(input.as_ref(), None, None);
+        Self::from_source_lines(preprocessor)
+    }
+
+    // Paths of included files are relative to the file
+    pub fn from_bytecode_file(path: impl AsRef<Path>) -> Result<Self, Vec<String>> {
+        let mut preprocessor = Preprocessor::default();
+        preprocessor.include(path.as_ref()).map_err(|e| vec![e])?;
+        Self::from_source_lines(preprocessor)
+    }
+
+    fn from_source_lines(preprocessor: Preprocessor) -> Result<Self, Vec<String>> {
+        let mut errors = preprocessor.errors;
+        let mut instructions = Vec::with_capacity(preprocessor.lines.len());
+        for l in preprocessor.lines {
+            let words = l.words.iter().map(|w| w.text.as_str());
+            match Instruction::try_from(IteratorWrapper(words)) {
+                Ok(instruction) => instructions
+                    .push(IndexedInstruction::new(l.line, instruction).with_file(l.file)),
+                Err(e) => errors.push(l.error(&e)),
+            }
+        }
         if !errors.is_empty() {
-            return Err(errors
-                .into_iter()
-                .map(|(i, e)| format!("Line: {}, error: {}", i, e.unwrap_err()))
-                .collect());
+            return Err(errors);
         }
-        let instructions = instructions
-            .into_iter()
-            .map(|(i, ins)| IndexedInstruction::new(i, ins.unwrap()))
-            .collect();
         Ok(Self::new(instructions))
     }
 
@@ -62,29 +160,354 @@ impl ByteCode {
         &self.instructions
     }
 
-    pub fn interpret(&mut self) -> Result<(), String> {
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = Arc::new(config);
+    }
+
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        Arc::make_mut(&mut self.config).failure_policy = policy;
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        Arc::make_mut(&mut self.config).overflow = overflow;
+    }
+
+    pub fn set_workers(&mut self, workers: usize) {
+        Arc::make_mut(&mut self.config).workers = workers;
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        Arc::make_mut(&mut self.config).limits = limits;
+    }
+
+    pub fn set_max_threads(&mut self, max_threads: usize) {
+        Arc::make_mut(&mut self.config).limits.max_threads = Some(max_threads);
+    }
+
+    pub fn set_gas(&mut self, gas: u64) {
+        Arc::make_mut(&mut self.config).limits.gas = Some(gas);
+    }
+
+    pub fn set_cancellation(&mut self, token: CancellationToken) {
+        Arc::make_mut(&mut self.config).cancellation = Some(token);
+    }
+
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        Arc::make_mut(&mut self.config).deadline = Some(deadline);
+    }
+
+    pub fn set_observer(&mut self, observer: Arc<dyn Observer>) {
+        Arc::make_mut(&mut self.config).observer = Some(observer);
+    }
+
+    pub fn register_host_function(
+        &mut self,
+        name: impl Into<String>,
+        arity: usize,
+        function: impl Fn(&[Data]) -> Result<Data, Error> + Send + Sync + 'static,
+    ) {
+        Arc::make_mut(&mut self.config)
+            .host_functions
+            .insert(name.into(), HostFunction::new(arity, function));
+    }
+
+    // Checks, that the instructions can be executed with the current config
+    pub fn verify(&self) -> Result<(), Vec<String>> {
+        let errors: Vec<_> = self.verification_errors().map(|e| e.to_string()).collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn verification_errors(&self) -> impl Iterator<Item = Error> + '_ {
+        self.instructions.iter().filter_map(|instruction| {
+            let message = match instruction.instruction() {
+                Instruction::CallHost(name) if !self.config.host_functions.contains_key(name) => {
+                    format!("Host function `{}` isn't registered", name)
+                }
+                _ => return None,
+            };
+            Some(Error::Line {
+                file: instruction.file().cloned(),
+                line: instruction.index(),
+                source: Box::new(message.into()),
+            })
+        })
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn parent(&self) -> Option<Id> {
+        self.parent
+    }
+
+    pub fn registry(&self) -> Registry {
+        Registry(self.runtime.clone())
+    }
+
+    pub fn set_record(&mut self, record: bool) {
+        Arc::make_mut(&mut self.config).record = record;
+    }
+
+    // Interactions between threads of the last execution, if they have been recorded
+    pub fn recording(&self) -> Option<Recording> {
+        let events = self.runtime.recorded_events()?;
+        Some(Recording { events })
+    }
+
+    // Executes the tree, so the interactions between threads happen in the recorded order. Other
+    // steps are not recorded, so an execution, which has depended on their interleaving, diverges
+    pub fn replay(&mut self, recording: &Recording) -> Result<(), Error> {
+        record::replay(self, recording)
+    }
+
+    pub fn interpret(&mut self) -> Result<(), Error> {
+        self.run(None)
+    }
+
+    // Executes at most `max_steps` instructions of the whole tree
+    pub fn interpret_steps(&mut self, max_steps: u64) -> Result<Status, Error> {
+        self.run(Some(max_steps))?;
+        if self.is_finished() {
+            Ok(Status::Finished)
+        } else {
+            Ok(Status::Paused)
+        }
+    }
+
+    fn run(&mut self, max_steps: Option<u64>) -> Result<(), Error> {
+        if let Some(e) = self.verification_errors().next() {
+            return Err(e);
+        }
+        let runtime = self.runtime.clone();
+        let workers = self.config.workers;
+        let gas = self.config.limits.gas;
+        let budget = min_limit(max_steps, gas);
+        *self = runtime.run(std::mem::take(self), workers, budget);
+        self.result()?;
+        // Steps requested by the caller aren't the gas, even if there are as many of them
+        match gas {
+            Some(gas) if max_steps.is_none_or(|max| max > gas) && !self.is_finished() => {
+                Err(format!("Limit of steps is exceeded (maximum is {})", gas).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        !matches!(
+            self.runtime.thread(self.id).map(|info| info.state),
+            Some(ThreadState::Running)
+        )
+    }
+
+    // Executes the tree on the current thread, the threads are switched at scheduling points in
+    // the order of the schedule
+    pub fn interpret_schedule(&mut self, schedule: &Schedule) -> Result<(), Error> {
+        let mut schedule = schedule.0.iter();
+        self.interpret_deterministic(
+            &mut |runnable| match schedule.next() {
+                Some(id) if runnable.contains(id) => Ok(*id),
+                Some(id) => Err(format!("Schedule diverged, thread {} isn't runnable", id).into()),
+                None => Err("Schedule is over".into()),
+            },
+            None,
+        )
+    }
+
+    pub(crate) fn interpret_deterministic(
+        &mut self,
+        choose: &mut dyn FnMut(&[Id]) -> Result<Id, Error>,
+        max_steps: Option<u64>,
+    ) -> Result<(), Error> {
+        if let Some(e) = self.verification_errors().next() {
+            return Err(e);
+        }
+        let runtime = self.runtime.clone();
+        let max_steps = min_limit(max_steps, self.config.limits.gas);
+        let (root, interrupted) =
+            runtime.run_deterministic(std::mem::take(self), choose, max_steps);
+        *self = root;
+        interrupted?;
+        self.result()
+    }
+
+    fn result(&self) -> Result<(), Error> {
+        let result = match self.runtime.thread(self.id).map(|info| info.state) {
+            Some(ThreadState::Failed(e)) => Err(e),
+            _ => Ok(()),
+        };
+        let result = match result {
+            // Report the failure, which has caused the abort, instead of its consequences
+            Err(e) if e.is_aborted() => match self.runtime.failure() {
+                Some((id, failure)) if id == self.id => Err(failure),
+                Some((id, failure)) => Err(Error::Child {
+                    id,
+                    source: Box::new(failure),
+                }),
+                None => Err(e),
+            },
+            result => result,
+        };
+        // The whole tree is stopped, so the thread, which has noticed it first, doesn't matter
+        result.map_err(|e| {
+            if e.is_interrupted() {
+                e.cause().clone()
+            } else {
+                e
+            }
+        })
+    }
+
+    // Executes instructions, until the bytecode returns, has to wait or the quantum is over. If
+    // `preemptive` is false, also stops before the next instruction, which interacts with other
+    // threads.
+    pub(crate) fn run_slice(&mut self, quantum: usize, preemptive: bool) -> Result<(), Error> {
+        let result = self.run_steps(quantum, preemptive);
+        if let Some(observer) = &self.config.observer {
+            observer.flush(self.id);
+        }
+        result
+    }
+
+    fn run_steps(&mut self, quantum: usize, preemptive: bool) -> Result<(), Error> {
+        self.waiting = None;
+        for i in 0..quantum {
+            if self.ret.is_some() || self.waiting.is_some() {
+                break;
+            }
+            if !preemptive
+                && i > 0
+                && self
+                    .next_instruction()
+                    .is_some_and(Instruction::is_scheduling_point)
+            {
+                break;
+            }
+            if self.runtime.is_aborted() {
+                return Err(Error::Aborted);
+            }
+            self.check_interruption(i)?;
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn step(&mut self) -> Result<(), Error> {
         let instructions = self.instructions.clone();
-        while self.ret().is_none() {
-            let instruction = instructions.get(self.position()).ok_or(format!(
-                "Instruction doesn't exist at {} position",
-                self.position
-            ))?;
-            instruction
-                .instruction()
-                .interpret(self)
-                .map_err(|e| format!("Line: {}, error: {}", instruction.index(), e))?;
-            // TODO: Remove me pls
-            dbg!(instruction, self.position, &self.stack);
+        let instruction = instructions.get(self.position()).ok_or(format!(
+            "Instruction doesn't exist at {} position",
+            self.position
+        ))?;
+        let position = self.position();
+        let start = self
+            .config
+            .observer
+            .as_ref()
+            .filter(|observer| observer.timed())
+            .map(|_| Instant::now());
+        let result = instruction.instruction().interpret(self);
+        result
+            .and_then(|()| self.check_limits())
+            .map_err(|e| Error::Line {
+                file: instruction.file().cloned(),
+                line: instruction.index(),
+                source: Box::new(e),
+            })?;
+        if self.waiting.is_none() {
+            self.steps += 1;
+            if let Some(observer) = &self.config.observer {
+                observer.step(&Step {
+                    thread: self.id,
+                    position,
+                    next: self.position(),
+                    instruction,
+                    stack: &self.stack,
+                    elapsed: start.map(|start| start.elapsed()).unwrap_or_default(),
+                });
+            }
         }
         Ok(())
     }
 
+    // The clock is read only once per `CLOCK_INTERVAL` instructions, because it's slow
+    fn check_interruption(&self, i: usize) -> Result<(), Error> {
+        let config = &self.config;
+        if config
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(Error::Cancelled);
+        }
+        match config.deadline {
+            Some(deadline) if i.is_multiple_of(CLOCK_INTERVAL) && Instant::now() >= deadline => {
+                Err(Error::TimedOut)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_limits(&mut self) -> Result<(), Error> {
+        let limits = self.config.limits;
+        let exceeded = |resource, maximum| Err(Error::Exceeded { resource, maximum });
+        match limits.max_stack {
+            Some(max) if self.stack.len() > max => return exceeded(Resource::StackDepth, max),
+            _ => {}
+        }
+        match limits.max_vars {
+            Some(max) if self.memory.len() > max => return exceeded(Resource::Variables, max),
+            _ => {}
+        }
+        if let Some(max) = limits.max_heap {
+            // Variables are never removed, so their bytes change only with their count
+            if self.usage.vars != self.memory.len() {
+                self.usage.vars = self.memory.len();
+                self.usage.memory = self
+                    .memory
+                    .keys()
+                    .map(|ident| ident.len() + DATA_BYTES)
+                    .sum();
+            }
+            let total = self.stack.len() * DATA_BYTES + self.usage.memory;
+            let heap = self.runtime.resize_heap(self.usage.total, total);
+            self.usage.total = total;
+            if heap > max {
+                return exceeded(Resource::HeapBytes, max);
+            }
+        }
+        Ok(())
+    }
+
+    fn next_instruction(&self) -> Option<&Instruction> {
+        self.instructions
+            .get(self.position())
+            .map(IndexedInstruction::instruction)
+    }
+
+    // What the next instruction is going to wait for
+    pub(crate) fn next_wait(&self) -> Option<Wait> {
+        match self.next_instruction()? {
+            Instruction::RecvChannel => Some(Wait::Recv(Id::try_from(*self.stack.last()?).ok()?)),
+            _ => None,
+        }
+    }
+
     pub fn ret(&self) -> Option<&Data> {
         self.ret.as_ref()
     }
 
+    // A position beyond `usize` is beyond the instructions too
     pub fn position(&self) -> usize {
-        self.position as usize
+        usize::try_from(self.position).unwrap_or(usize::MAX)
     }
 
     pub(crate) fn stack_pop(&mut self) -> Result<Data, &'static str> {
@@ -92,9 +515,19 @@ impl ByteCode {
     }
 }
 
+fn min_limit(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{instructions::IndexedInstruction, ByteCode, Instruction};
+    use crate::{
+        instructions::IndexedInstruction, ByteCode, Data, Error, FailurePolicy, Instruction,
+        Overflow, Status, ThreadInfo, ThreadState,
+    };
 
     #[test]
     fn parse_bytecode_example() {
@@ -634,6 +1067,142 @@ JUMP
         assert_eq!(*bytecode.ret().unwrap(), 15_407_021_574_586_368);
     }
 
+    #[test]
+    fn overflow() {
+        let run = |lhs: Data, rhs: Data, opcode: &str, overflow| {
+            let input = format!(
+                "LOAD_VAL {}\nLOAD_VAL {}\n{}\nRETURN_VALUE",
+                lhs, rhs, opcode
+            );
+            let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+            bytecode.set_overflow(overflow);
+            // The returned top and the rest of the stack
+            bytecode
+                .interpret()
+                .map(|()| (bytecode.ret, bytecode.stack.clone()))
+        };
+        let max = Data::MAX;
+        assert_eq!(
+            run(max, 2, "ADD", Overflow::Checked)
+                .unwrap_err()
+                .to_string(),
+            format!("Line: 3, error: Addition overflow occurred ({} + 2)", max)
+        );
+        assert_eq!(
+            run(1, 2, "SUB", Overflow::Checked).unwrap_err().to_string(),
+            "Line: 3, error: Substraction overflow occurred (1 - 2)"
+        );
+        assert_eq!(
+            run(max, 2, "ADD", Overflow::Wrapping),
+            Ok((Some(1), vec![]))
+        );
+        assert_eq!(
+            run(1, 2, "SUB", Overflow::Wrapping),
+            Ok((Some(max), vec![]))
+        );
+        assert_eq!(
+            run(max, 2, "MULTIPLY", Overflow::Wrapping),
+            Ok((Some(max - 1), vec![]))
+        );
+        assert_eq!(
+            run(max, 2, "ADD", Overflow::Saturating),
+            Ok((Some(max), vec![]))
+        );
+        assert_eq!(
+            run(1, 2, "SUB", Overflow::Saturating),
+            Ok((Some(0), vec![]))
+        );
+        assert_eq!(
+            run(max, 2, "MULTIPLY", Overflow::Saturating),
+            Ok((Some(max), vec![]))
+        );
+        assert_eq!(
+            run(3, 2, "SUB", Overflow::Saturating),
+            Ok((Some(1), vec![]))
+        );
+
+        // Explicit opcodes don't depend on the config
+        assert_eq!(
+            run(max, 2, "ADD_WRAP", Overflow::Checked),
+            Ok((Some(1), vec![]))
+        );
+        assert_eq!(
+            run(1, 2, "SUB_SAT", Overflow::Wrapping),
+            Ok((Some(0), vec![]))
+        );
+        assert_eq!(
+            run(max, 2, "MULTIPLY_SAT", Overflow::Checked),
+            Ok((Some(max), vec![]))
+        );
+        // The carry is on the top
+        assert_eq!(
+            run(max, 2, "ADD_CARRY", Overflow::Checked),
+            Ok((Some(1), vec![1]))
+        );
+        assert_eq!(
+            run(1, 2, "SUB_CARRY", Overflow::Saturating),
+            Ok((Some(1), vec![max]))
+        );
+        assert_eq!(
+            run(3, 2, "MULTIPLY_CARRY", Overflow::Checked),
+            Ok((Some(0), vec![6]))
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let run = |input: &str| {
+            let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+            bytecode.interpret().map(|()| bytecode.ret)
+        };
+        let compare = |lhs: Data, rhs: Data, opcode: &str| {
+            run(&format!(
+                "LOAD_VAL {}\nLOAD_VAL {}\n{}\nRETURN_VALUE",
+                lhs, rhs, opcode
+            ))
+            .unwrap()
+            .unwrap()
+        };
+        for (lhs, rhs) in [(1, 2), (2, 2), (3, 2)] {
+            assert_eq!(compare(lhs, rhs, "LT"), (lhs < rhs).into());
+            assert_eq!(compare(lhs, rhs, "GT"), (lhs > rhs).into());
+            assert_eq!(compare(lhs, rhs, "EQ"), (lhs == rhs).into());
+            assert_eq!(compare(lhs, rhs, "NE"), (lhs != rhs).into());
+            assert_eq!(compare(lhs, rhs, "LE"), (lhs <= rhs).into());
+            assert_eq!(compare(lhs, rhs, "GE"), (lhs >= rhs).into());
+        }
+        // Every operand, which isn't 0, is true
+        for (lhs, rhs) in [(0, 0), (0, 7), (7, 0), (7, 9)] {
+            assert_eq!(
+                compare(lhs, rhs, "LOGICAL_AND"),
+                (lhs != 0 && rhs != 0).into()
+            );
+            assert_eq!(
+                compare(lhs, rhs, "LOGICAL_OR"),
+                (lhs != 0 || rhs != 0).into()
+            );
+            assert_eq!(compare(lhs, rhs, "LOGICAL_NOT"), (rhs == 0).into());
+        }
+
+        // return n <= 1 || done ? 10 : 20
+        let input = |n: Data, done: Data| {
+            format!(
+                "LOAD_VAL {}\nLOAD_VAL 1\nLE\nLOAD_VAL {}\nLOGICAL_OR\nLOAD_VAL 9\nJUMP_IF\n\
+                 LOAD_VAL 20\nRETURN_VALUE\nLOAD_VAL 10\nRETURN_VALUE",
+                n, done
+            )
+        };
+        assert_eq!(run(&input(1, 0)), Ok(Some(10)));
+        assert_eq!(run(&input(5, 3)), Ok(Some(10)));
+        assert_eq!(run(&input(5, 0)), Ok(Some(20)));
+        let input = input(5, 0).replace("JUMP_IF", "JUMP_IF_NOT");
+        assert_eq!(run(&input), Ok(Some(10)));
+        assert_eq!(
+            run("LOAD_VAL 1\nLT").unwrap_err().to_string(),
+            "Line: 2, error: Stack is empty"
+        );
+    }
+
     #[test]
     fn fibonacci_space_optimized() {
         let input = r#"
@@ -729,6 +1298,20 @@ RETURN_VALUE
         let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
         bytecode.interpret().unwrap();
         assert_eq!(*bytecode.ret().unwrap(), 0);
+
+        // `SPAWN` keeps the rest of the stack, `SPAWN_IDS` pushes ids of children
+        let run = |spawn: &str| {
+            let input = format!(
+                "LOAD_VAL 7\nLOAD_VAL 0\nLOAD_VAL 7\nLOAD_VAL 0\nLOAD_VAL 7\n{}\nRETURN_VALUE\n\
+                 LOAD_VAL 0\nRETURN_VALUE",
+                spawn
+            );
+            let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+            bytecode.interpret().unwrap();
+            (bytecode.ret, bytecode.stack.clone())
+        };
+        assert_eq!(run("SPAWN"), (Some(7), vec![]));
+        assert_eq!(run("SPAWN_IDS"), (Some(2), vec![7, 1]));
     }
 
     #[test]
@@ -769,27 +1352,207 @@ RETURN_VALUE
         assert_eq!(*bytecode.ret().unwrap(), 42);
     }
 
-    #[ignore = "Not enough time to debug"]
     #[test]
-    fn fibonacci_multithreaded_without_caching() {
+    fn send_waits_for_recv() {
         let input = r#"
-// fib(11)
-LOAD_VAL 11
-WRITE_VAR input
+// spawn(f_send, f_send)
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 7
+SPAWN
 
-// n = input
-READ_VAR input
-READ_VAR input
-LOG
-WRITE_VAR n
+// return 0
+LOAD_VAL 0
+RETURN_VALUE
+
+// send(1), which is never received
+LOAD_VAL 1
+LOAD_VAL 0
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+        // The root waits for its children and they wait for the root to receive
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        assert_eq!(bytecode.interpret().unwrap_err(), Error::Deadlock);
+    }
+
+    #[test]
+    fn child_failure_on_recv() {
+        let input = r#"
+// spawn(f_fail, f_ok)
+LOAD_VAL 0
+LOAD_VAL 8
+LOAD_VAL 0
+LOAD_VAL 9
+SPAWN
+
+// return recv(1)
+LOAD_VAL 1
+RECV_CHANNEL
+RETURN_VALUE
+
+// return x
+READ_VAR x
+
+// return 0
+LOAD_VAL 0
+RETURN_VALUE
+"#;
 
-// if n <= 1
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        let error = bytecode.interpret().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line: 11, error: Thread 1 failed: Line: 15, error: Variable `x` doesn't exist"
+        );
+        assert!(bytecode.ret().is_none());
+    }
+
+    #[test]
+    fn child_failure_on_finish() {
+        let input = r#"
+// spawn(f_ok, f_fail)
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 9
+SPAWN
+LOAD_VAL 0
+RETURN_VALUE
+
+// return 0
+LOAD_VAL 0
+RETURN_VALUE
+
+// return 1 - 2
+LOAD_VAL 1
 LOAD_VAL 2
+SUB
+RETURN_VALUE
+"#;
+
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        let error = bytecode.interpret().unwrap_err();
+        assert!(matches!(error, Error::Child { id: 2, .. }));
+    }
+
+    #[test]
+    fn abort_tree_on_failure() {
+        let input = r#"
+// spawn(f_fail, f_loop)
+LOAD_VAL 0
+LOAD_VAL 8
+LOAD_VAL 0
+LOAD_VAL 9
+SPAWN
+
+// return recv(2)
+LOAD_VAL 2
+RECV_CHANNEL
+RETURN_VALUE
+
+// return x
+READ_VAR x
+
+// loop {}
+LOAD_VAL 9
+JUMP
+"#;
+
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        bytecode.set_failure_policy(FailurePolicy::AbortTree);
+        let error = bytecode.interpret().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Thread 1 failed: Line: 15, error: Variable `x` doesn't exist"
+        );
+    }
+
+    #[test]
+    fn thread_ids() {
+        let input = r#"
+// a, b = spawn(f, f)
+LOAD_VAL 0
+LOAD_VAL 15
+LOAD_VAL 0
+LOAD_VAL 15
+SPAWN_IDS
+WRITE_VAR b
+WRITE_VAR a
+
+// return recv(a) * 10 + recv(b)
+READ_VAR a
+RECV_CHANNEL
+LOAD_VAL 10
+MULTIPLY
+READ_VAR b
+RECV_CHANNEL
+ADD
+RETURN_VALUE
+
+// send(self_id(), parent_id())
+SELF_ID
+PARENT_ID
+SEND_CHANNEL
+PARENT_ID
+RETURN_VALUE
+"#;
+
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        let registry = bytecode.registry();
+        bytecode.interpret().unwrap();
+        assert_eq!(*bytecode.ret().unwrap(), 12);
+        assert_eq!(registry.live_threads(), 0);
+        assert_eq!(
+            registry.threads(),
+            [
+                ThreadInfo {
+                    id: 0,
+                    parent: None,
+                    state: ThreadState::Finished(12)
+                },
+                ThreadInfo {
+                    id: 1,
+                    parent: Some(0),
+                    state: ThreadState::Finished(0)
+                },
+                ThreadInfo {
+                    id: 2,
+                    parent: Some(0),
+                    state: ThreadState::Finished(0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parent_id_of_root() {
+        let mut bytecode = ByteCode::from_bytecode_text("PARENT_ID\nRETURN_VALUE").unwrap();
+        let error = bytecode.interpret().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line: 1, error: Thread doesn't have a parent"
+        );
+    }
+
+    // fib(n), where n is on the stack
+    const FIBONACCI_MULTITHREADED: &str = r#"
+// n = <argument>
+// res = n
+WRITE_VAR n
 READ_VAR n
-LOAD_VAL 42
-JUMP_GREATER_THAN
+WRITE_VAR res
 
-// spawn(fib(n - 1), fib(n - 2))
+// if n < 2 goto reply
+READ_VAR n
+LOAD_VAL 2
+LOAD_VAL 25
+JUMP_LESS_THAN
+
+// a, b = spawn(fib(n - 1), fib(n - 2))
 READ_VAR n
 LOAD_VAL 1
 SUB
@@ -800,47 +1563,102 @@ LOAD_VAL 1
 LOAD_VAL 1
 LOAD_VAL 1
 LOAD_VAL 1
-SPAWN
+SPAWN_IDS
 
-// return fib(n - 1) + fib(n - 2)
-LOAD_VAL 11
-READ_VAR n
-SUB
-LOAD_VAL 1
-ADD
+// res = recv(b) + recv(a)
 RECV_CHANNEL
-LOAD_VAL 11
-READ_VAR n
-SUB
-LOAD_VAL 2
-ADD
+WRITE_VAR res
 RECV_CHANNEL
+READ_VAR res
 ADD
 WRITE_VAR res
+
+// label reply:
+// if self_id() != 0 {
+//   send(res, parent_id())
+// }
+SELF_ID
+LOAD_VAL 0
+LOAD_VAL 32
+JUMP_EQUAL
 READ_VAR res
-READ_VAR input
-READ_VAR n
-SUB
+PARENT_ID
 SEND_CHANNEL
+
+// return res
 READ_VAR res
 RETURN_VALUE
+"#;
 
-// label return_n:
-// send(n)
-// return n
-READ_VAR n
-READ_VAR input
-READ_VAR n
-SUB
-// FIXME: there will be panic in main thread
-SEND_CHANNEL
+    #[test]
+    fn fibonacci_multithreaded_without_caching() {
+        let input = format!("LOAD_VAL 11\n{}", FIBONACCI_MULTITHREADED);
 
-READ_VAR n
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        bytecode.interpret().unwrap();
+        assert_eq!(*bytecode.ret().unwrap(), 89);
+    }
+
+    #[test]
+    fn fibonacci_multithreaded_single_worker() {
+        let input = format!("LOAD_VAL 15\n{}", FIBONACCI_MULTITHREADED);
+
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        bytecode.set_workers(1);
+        bytecode.interpret().unwrap();
+        assert_eq!(*bytecode.ret().unwrap(), 610);
+        assert_eq!(bytecode.registry().threads().len(), 1973);
+    }
+
+    #[test]
+    fn fibonacci_multithreaded_thread_limit() {
+        let input = format!("LOAD_VAL 11\n{}", FIBONACCI_MULTITHREADED);
+
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        bytecode.set_max_threads(8);
+        let error = bytecode.interpret().unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("Limit of live threads is exceeded (maximum is 8)"));
+        assert_eq!(bytecode.registry().live_threads(), 0);
+    }
+
+    #[test]
+    fn gas_and_steps() {
+        let endless = || {
+            let mut bytecode = ByteCode::from_bytecode_text("LOAD_VAL 0\nJUMP").unwrap();
+            bytecode.set_gas(10);
+            bytecode
+        };
+        // Only the gas is reported as exceeded, steps of the caller pause the tree
+        assert_eq!(endless().interpret_steps(10), Ok(Status::Paused));
+        assert_eq!(endless().interpret_steps(5), Ok(Status::Paused));
+        let exceeded = Err("Limit of steps is exceeded (maximum is 10)".into());
+        assert_eq!(endless().interpret_steps(11), exceeded);
+        assert_eq!(endless().interpret().map(|()| Status::Finished), exceeded);
+    }
+
+    #[test]
+    fn deadlock() {
+        let input = r#"
+// spawn(f_recv, f_recv)
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 7
+SPAWN_IDS
+
+// return recv(b)
+RECV_CHANNEL
+RETURN_VALUE
+
+// return recv(parent_id())
+PARENT_ID
+RECV_CHANNEL
 RETURN_VALUE
 "#;
 
         let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
-        bytecode.interpret().unwrap();
-        assert_eq!(*bytecode.ret().unwrap(), 42);
+        assert_eq!(bytecode.interpret().unwrap_err(), Error::Deadlock);
     }
 }
@@ -0,0 +1,399 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    process::ExitCode,
+    sync::Arc,
+};
+
+use bytecode_interpreter::{
+    fuzz::Fuzzer, ByteCode, Cfg, Id, Limits, Observer, Overflow, Repl, Step, VmBuilder,
+};
+
+const USAGE: &str = "\
+Usage: bytecode <command> [options] [file]
+
+Commands:
+    run       Execute the program and print its return value
+    check     Parse and verify the program
+    disasm    Print instructions with their positions and lines
+    cfg       Print the control-flow graph of basic blocks in DOT format
+    json      Print the program in JSON format
+    rust      Print the single-threaded program as the Rust function `program`
+    registers Print the single-threaded program translated to register operations
+    repl      Execute instructions from the standard input line by line
+    fuzz      Search for panics and hangs with generated inputs, the failing input is written
+              to the file
+
+Options of `run`:
+    --trace             Print every executed instruction to stderr
+    --gas <count>       Limit count of executed instructions
+    --overflow <mode>   Handle overflows of arithmetic by `checked` (default), `wrapping` or
+                        `saturating` mode
+    --var <name=value>  Set the variable before the execution, can be repeated
+    --profile           Print counts and times of executed instructions to stderr
+    --folded <file>     Write the profile as folded stacks for flamegraph tools
+    --coverage <file>   Write executed lines and conditional jumps in lcov format
+
+Options of `fuzz`:
+    --runs <count>      Count of inputs of every target, 1000 by default
+    --seed <number>     Seed of generated inputs, 0 by default
+
+The program is read from the standard input, if the file is `-` or missing. Files with `.json`
+extension and input, which starts with `{`, are read in JSON format.";
+
+// Exit code of invalid arguments or programs, failed executions exit with 1
+const USAGE_ERROR: u8 = 2;
+
+struct Options {
+    command: String,
+    file: Option<String>,
+    trace: bool,
+    gas: Option<u64>,
+    overflow: Option<Overflow>,
+    vars: Vec<(String, u128)>,
+    profile: bool,
+    folded: Option<String>,
+    coverage: Option<String>,
+    runs: Option<u64>,
+    seed: Option<u64>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let command = args.next().ok_or("Command is missing")?;
+        let mut options = Self {
+            command,
+            file: None,
+            trace: false,
+            gas: None,
+            overflow: None,
+            vars: Vec::new(),
+            profile: false,
+            folded: None,
+            coverage: None,
+            runs: None,
+            seed: None,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Value of `{}` is missing", arg));
+            match arg.as_str() {
+                "--trace" => options.trace = true,
+                "--gas" => {
+                    let gas = value()?;
+                    options.gas = Some(gas.parse().map_err(|_| format!("Invalid gas `{}`", gas))?);
+                }
+                "--overflow" => {
+                    options.overflow = Some(match value()?.as_str() {
+                        "checked" => Overflow::Checked,
+                        "wrapping" => Overflow::Wrapping,
+                        "saturating" => Overflow::Saturating,
+                        overflow => return Err(format!("Invalid overflow `{}`", overflow)),
+                    });
+                }
+                "--var" => {
+                    let var = value()?;
+                    let (name, data) = var
+                        .split_once('=')
+                        .ok_or(format!("Variable `{}` isn't `name=value`", var))?;
+                    let data = data
+                        .parse()
+                        .map_err(|_| format!("Invalid value of variable `{}`", name))?;
+                    options.vars.push((name.into(), data));
+                }
+                "--profile" => options.profile = true,
+                "--folded" => options.folded = Some(value()?),
+                "--coverage" => options.coverage = Some(value()?),
+                "--runs" => {
+                    let runs = value()?;
+                    options.runs = Some(
+                        runs.parse()
+                            .map_err(|_| format!("Invalid runs `{}`", runs))?,
+                    );
+                }
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = Some(
+                        seed.parse()
+                            .map_err(|_| format!("Invalid seed `{}`", seed))?,
+                    );
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option `{}`", arg)),
+                _ if options.file.is_some() => {
+                    return Err(format!("Unexpected argument `{}`", arg))
+                }
+                _ => options.file = Some(arg),
+            }
+        }
+        Ok(options)
+    }
+
+    // Includes of a file are resolved relative to it, and of the standard input to the current
+    // directory
+    fn bytecode(&self) -> Result<ByteCode, ExitCode> {
+        let bytecode = match self.file.as_deref() {
+            None | Some("-") => {
+                let mut input = String::new();
+                io::stdin()
+                    .read_to_string(&mut input)
+                    .map_err(|e| vec![format!("Reading of standard input failed: {}", e)])
+                    .and_then(|_| match input.trim_start().starts_with('{') {
+                        true => ByteCode::from_json(input),
+                        false => ByteCode::from_bytecode_text(input),
+                    })
+            }
+            Some(file) if file.ends_with(".json") => fs::read_to_string(file)
+                .map_err(|e| vec![format!("Reading of `{}` failed: {}", file, e)])
+                .and_then(|input| {
+                    ByteCode::from_json(input).map_err(|errors| {
+                        errors
+                            .into_iter()
+                            .map(|e| format!("{}: {}", file, e))
+                            .collect()
+                    })
+                }),
+            Some(file) => ByteCode::from_bytecode_file(file),
+        };
+        bytecode.map_err(|errors| {
+            for e in errors {
+                eprintln!("{}", e);
+            }
+            ExitCode::from(USAGE_ERROR)
+        })
+    }
+}
+
+struct Tracer;
+
+impl Observer for Tracer {
+    fn step(&self, step: &Step) {
+        let stack: Vec<_> = step.stack.iter().map(u128::to_string).collect();
+        eprintln!(
+            "[{}] {:>4}: {:<24} [{}]",
+            step.thread,
+            step.position,
+            step.instruction.instruction().to_string(),
+            stack.join(", ")
+        );
+    }
+
+    fn log(&self, thread: Id, data: u128) {
+        Logger.log(thread, data);
+    }
+}
+
+struct Logger;
+
+impl Observer for Logger {
+    fn log(&self, thread: Id, data: u128) {
+        eprintln!("LOG [{}]: {}", thread, data);
+    }
+}
+
+fn run(options: &Options) -> Result<(), ExitCode> {
+    let bytecode = options.bytecode()?;
+    let instructions = bytecode.instructions().to_vec();
+    let mut builder = VmBuilder::new(bytecode).limits(Limits {
+        gas: options.gas,
+        ..Default::default()
+    });
+    if let Some(overflow) = options.overflow {
+        builder = builder.overflow(overflow);
+    }
+    for (name, data) in &options.vars {
+        builder = builder.var(name, *data);
+    }
+    if options.profile || options.folded.is_some() {
+        builder = builder.profile();
+    }
+    if options.coverage.is_some() {
+        builder = builder.coverage();
+    }
+    let observer: Arc<dyn Observer> = if options.trace {
+        Arc::new(Tracer)
+    } else {
+        Arc::new(Logger)
+    };
+    match builder.observer(observer).run() {
+        Ok(outcome) => {
+            println!("{}", outcome.ret);
+            let profile = outcome.profile.unwrap_or_default();
+            if options.profile {
+                eprint!("{}", profile.to_text());
+            }
+            if let Some(file) = &options.folded {
+                write(file, profile.to_folded())?;
+            }
+            if let Some(file) = &options.coverage {
+                let coverage = outcome.coverage.unwrap_or_default();
+                write(file, coverage.to_lcov(&instructions))?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn write(file: &str, contents: impl AsRef<[u8]>) -> Result<(), ExitCode> {
+    fs::write(file, contents).map_err(|e| {
+        eprintln!("Writing of `{}` failed: {}", file, e);
+        ExitCode::FAILURE
+    })
+}
+
+fn check(options: &Options) -> Result<(), ExitCode> {
+    let bytecode = options.bytecode()?;
+    bytecode.verify().map_err(|errors| {
+        for e in errors {
+            eprintln!("{}", e);
+        }
+        ExitCode::from(USAGE_ERROR)
+    })?;
+    println!("OK, {} instructions", bytecode.instructions().len());
+    Ok(())
+}
+
+fn disasm(options: &Options) -> Result<(), ExitCode> {
+    println!("{:>8} {:>8}  instruction", "position", "line");
+    for (position, instruction) in options.bytecode()?.instructions().iter().enumerate() {
+        println!(
+            "{:>8} {:>8}  {}",
+            position,
+            instruction.index() + 1,
+            instruction.instruction()
+        );
+    }
+    Ok(())
+}
+
+fn cfg(options: &Options) -> Result<(), ExitCode> {
+    let bytecode = options.bytecode()?;
+    print!("{}", Cfg::new(bytecode.instructions()).to_dot());
+    Ok(())
+}
+
+fn json(options: &Options) -> Result<(), ExitCode> {
+    print!("{}", options.bytecode()?.to_json());
+    Ok(())
+}
+
+fn rust(options: &Options) -> Result<(), ExitCode> {
+    let rust = options.bytecode()?.to_rust("program").map_err(|e| {
+        eprintln!("{}", e);
+        ExitCode::from(USAGE_ERROR)
+    })?;
+    print!("{}", rust);
+    Ok(())
+}
+
+fn registers(options: &Options) -> Result<(), ExitCode> {
+    let registers = options.bytecode()?.to_registers().map_err(|e| {
+        eprintln!("{}", e);
+        ExitCode::from(USAGE_ERROR)
+    })?;
+    print!("{}", registers);
+    Ok(())
+}
+
+fn fuzz(options: &Options) -> Result<(), ExitCode> {
+    let fuzzer = Fuzzer {
+        seed: options.seed.unwrap_or_default(),
+        runs: options.runs.unwrap_or(Fuzzer::default().runs),
+        ..Default::default()
+    };
+    let Err(failure) = fuzzer.run() else {
+        println!("OK, {} inputs of every target", fuzzer.runs);
+        return Ok(());
+    };
+    eprintln!(
+        "{:?} of `{}` target on {:?}",
+        failure.finding,
+        failure.target,
+        String::from_utf8_lossy(&failure.input)
+    );
+    if let Some(file) = &options.file {
+        write(file, failure.input)?;
+    }
+    Err(ExitCode::FAILURE)
+}
+
+fn repl() -> Result<(), ExitCode> {
+    let mut repl = Repl::new();
+    let interactive = io::stdin().is_terminal();
+    let prompt = || {
+        if interactive {
+            print!("> ");
+            let _ = io::stdout().flush();
+        }
+    };
+    prompt();
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            return Err(ExitCode::FAILURE);
+        };
+        if line.trim() == ":quit" {
+            break;
+        }
+        match repl.eval(&line) {
+            Ok(output) => print!("{}", output),
+            Err(e) => println!("Error: {}", e),
+        }
+        prompt();
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(USAGE_ERROR);
+        }
+    };
+    if options.command == "help" || options.command == "--help" {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let run_options = options.trace
+        || options.gas.is_some()
+        || options.overflow.is_some()
+        || !options.vars.is_empty()
+        || options.profile
+        || options.folded.is_some()
+        || options.coverage.is_some();
+    let fuzz_options = options.runs.is_some() || options.seed.is_some();
+    if (run_options && options.command != "run") || (fuzz_options && options.command != "fuzz") {
+        eprintln!(
+            "Options aren't supported by `{}`\n\n{}",
+            options.command, USAGE
+        );
+        return ExitCode::from(USAGE_ERROR);
+    }
+    if options.command == "repl" {
+        return match repl() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(code) => code,
+        };
+    }
+    let result = match options.command.as_str() {
+        "run" => run(&options),
+        "check" => check(&options),
+        "disasm" => disasm(&options),
+        "cfg" => cfg(&options),
+        "json" => json(&options),
+        "rust" => rust(&options),
+        "registers" => registers(&options),
+        "fuzz" => fuzz(&options),
+        command => {
+            eprintln!("Unknown command `{}`\n\n{}", command, USAGE);
+            Err(ExitCode::from(USAGE_ERROR))
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use crate::{Data, Id, IndexedInstruction};
+
+// Instruction, which a thread has executed, and the stack after it
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'a> {
+    pub thread: Id,
+    pub position: usize,
+    // The position of the next instruction of the thread
+    pub next: usize,
+    pub instruction: &'a IndexedInstruction,
+    pub stack: &'a [Data],
+    // Wall time of the execution, it's zero, if the observer isn't timed
+    pub elapsed: Duration,
+}
+
+// Receives events of all threads of a tree, so it's called from several workers at once
+pub trait Observer: Send + Sync {
+    fn step(&self, _step: &Step) {}
+
+    // Called after a slice of instructions of the thread on the same OS thread as its steps, so
+    // the observer may gather the steps without locking and merge them once per slice
+    fn flush(&self, _thread: Id) {}
+
+    // Whether instructions are timed for `Step::elapsed`, which slows down the execution
+    fn timed(&self) -> bool {
+        false
+    }
+
+    // Data, which is logged by `LOG`
+    fn log(&self, _thread: Id, _data: Data) {}
+}
+
+impl fmt::Debug for dyn Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observer")
+    }
+}
+
+static NEXT_ACCUMULATOR: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // States of the current slice of the OS thread by ids of their accumulators
+    static SLICES: RefCell<HashMap<usize, Box<dyn Any>>> = RefCell::default();
+}
+
+// State of an observer, which is updated per OS thread during a slice and merged into the shared
+// one by `flush`, so workers don't contend for a lock on every instruction
+#[derive(Debug)]
+pub(crate) struct Accumulator<T> {
+    id: usize,
+    shared: Mutex<T>,
+}
+
+impl<T: Default> Default for Accumulator<T> {
+    fn default() -> Self {
+        Self {
+            id: NEXT_ACCUMULATOR.fetch_add(1, Ordering::Relaxed),
+            shared: Mutex::default(),
+        }
+    }
+}
+
+impl<T: Default + 'static> Accumulator<T> {
+    pub(crate) fn update(&self, f: impl FnOnce(&mut T)) {
+        SLICES.with(|slices| {
+            let mut slices = slices.borrow_mut();
+            let slice = slices
+                .entry(self.id)
+                .or_insert_with(|| Box::new(T::default()));
+            f(slice.downcast_mut().expect("Accumulators have unique ids"));
+        });
+    }
+
+    // Merges the state of the current slice of the OS thread into the shared one
+    pub(crate) fn flush(&self, merge: impl FnOnce(&mut T, &T)) {
+        let slice = SLICES.with(|slices| slices.borrow_mut().remove(&self.id));
+        if let Some(slice) = slice {
+            let slice = slice.downcast().expect("Accumulators have unique ids");
+            merge(&mut self.lock(), &slice);
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        self.shared.lock().unwrap()
+    }
+}
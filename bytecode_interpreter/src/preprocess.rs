@@ -0,0 +1,461 @@
+// Directives of the text format, which are expanded before instructions are parsed:
+//
+// .include "file.bc"      instructions of the file, the path is relative to the including file
+// .macro name a b         instructions till `.endm`, whose words `a` and `b` are replaced by
+// LOAD_VAL a              arguments, when the macro is used like an instruction: `name 1 2`
+// .endm
+//
+// Expanded instructions keep the file and the line, where they are included or used.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    diagnostic::{self, Diagnostic, Span},
+    instructions::ParseError,
+};
+
+// Maximum depth of macros, which are used by other macros
+const MAX_DEPTH: usize = 32;
+
+// Maximum count of expanded instructions and macros, because nested macros grow exponentially
+const MAX_EXPANSIONS: usize = 1 << 20;
+
+#[derive(Clone)]
+pub(crate) struct Word {
+    pub(crate) text: String,
+    // Words of expanded macros point at the arguments or the name of the used macro
+    pub(crate) span: Span,
+}
+
+pub(crate) struct SourceLine {
+    pub(crate) file: Option<Arc<str>>,
+    pub(crate) line: usize,
+    pub(crate) text: Arc<str>,
+    pub(crate) words: Vec<Word>,
+    // The macro, which is used at the line
+    pub(crate) expanded_from: Option<String>,
+}
+
+impl SourceLine {
+    pub(crate) fn error(&self, error: &ParseError) -> String {
+        let span = match self.words.get(error.word) {
+            Some(word) => word.span,
+            None => self.words.last().map_or(
+                Span {
+                    column: 0,
+                    width: 1,
+                },
+                |w| w.span.after(),
+            ),
+        };
+        let message = match &self.expanded_from {
+            Some(name) => format!("{} (in macro `{}`)", error.message, name),
+            None => error.message.clone(),
+        };
+        Diagnostic {
+            file: self.file.as_deref(),
+            line: self.line,
+            text: &self.text,
+            span,
+            message: &message,
+            help: error.help.as_deref(),
+        }
+        .to_string()
+    }
+}
+
+struct Macro {
+    line: usize,
+    text: Arc<str>,
+    name: Span,
+    params: Vec<String>,
+    body: Vec<Vec<Word>>,
+}
+
+#[derive(Default)]
+pub(crate) struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    // Files, which are being included, to find cycles
+    includes: Vec<PathBuf>,
+    expansions: usize,
+    pub(crate) lines: Vec<SourceLine>,
+    pub(crate) errors: Vec<String>,
+}
+
+impl Preprocessor {
+    // `dir` is the directory, which relative paths of includes are resolved from
+    pub(crate) fn process(&mut self, input: &str, file: Option<Arc<str>>, dir: Option<&Path>) {
+        let mut definition: Option<(String, Macro)> = None;
+        for (i, l) in input.lines().enumerate() {
+            let error = |span: Span, message: &str| {
+                Diagnostic {
+                    file: file.as_deref(),
+                    line: i,
+                    text: l,
+                    span,
+                    message,
+                    help: None,
+                }
+                .to_string()
+            };
+            let words: Vec<Word> = diagnostic::words(l)
+                .map(|(text, span)| Word {
+                    text: text.into(),
+                    span,
+                })
+                .collect();
+            if let Some((name, mut body)) = definition.take() {
+                if words.first().map(|w| w.text.as_str()) == Some(".endm") {
+                    self.macros.insert(name, body);
+                } else {
+                    if !words.is_empty() {
+                        body.body.push(words);
+                    }
+                    definition = Some((name, body));
+                }
+                continue;
+            }
+            let Some(first) = words.first() else {
+                continue;
+            };
+            match first.text.as_str() {
+                ".include" => {
+                    let path = match &words[1..] {
+                        [path]
+                            if path.text.len() > 1
+                                && path.text.starts_with('"')
+                                && path.text.ends_with('"') =>
+                        {
+                            path
+                        }
+                        _ => {
+                            self.errors
+                                .push(error(first.span, "Expected `.include \"file\"`"));
+                            continue;
+                        }
+                    };
+                    let name = &path.text[1..path.text.len() - 1];
+                    let name = match dir {
+                        Some(dir) => dir.join(name),
+                        None => PathBuf::from(name),
+                    };
+                    if let Err(e) = self.include(&name) {
+                        self.errors.push(error(path.span, &e));
+                    }
+                }
+                ".macro" => match words.get(1) {
+                    Some(name) => {
+                        definition = Some((
+                            name.text.clone(),
+                            Macro {
+                                line: i,
+                                text: l.into(),
+                                name: name.span,
+                                params: words[2..].iter().map(|w| w.text.clone()).collect(),
+                                body: Vec::new(),
+                            },
+                        ));
+                    }
+                    None => self
+                        .errors
+                        .push(error(first.span.after(), "Empty name of macro")),
+                },
+                ".endm" => self
+                    .errors
+                    .push(error(first.span, "`.endm` without `.macro`")),
+                directive if directive.starts_with('.') => self.errors.push(error(
+                    first.span,
+                    &format!("Unknown directive `{}`", directive),
+                )),
+                _ => {
+                    let text: Arc<str> = l.into();
+                    if let Err(e) = self.expand(words.clone(), &file, i, &text, None, 0) {
+                        self.errors.push(error(first.span, &e));
+                    }
+                }
+            }
+        }
+        if let Some((name, definition)) = definition {
+            let message = format!("Macro `{}` isn't terminated by `.endm`", name);
+            self.errors.push(
+                Diagnostic {
+                    file: file.as_deref(),
+                    line: definition.line,
+                    text: &definition.text,
+                    span: definition.name,
+                    message: &message,
+                    help: None,
+                }
+                .to_string(),
+            );
+        }
+    }
+
+    pub(crate) fn include(&mut self, path: &Path) -> Result<(), String> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| format!("Including of `{}` failed: {}", path.display(), e))?;
+        if self.includes.contains(&canonical) {
+            return Err(format!("Including of `{}` makes a cycle", path.display()));
+        }
+        let input = fs::read_to_string(path)
+            .map_err(|e| format!("Including of `{}` failed: {}", path.display(), e))?;
+        self.includes.push(canonical);
+        let file = Arc::from(path.display().to_string());
+        self.process(&input, Some(file), path.parent());
+        self.includes.pop();
+        Ok(())
+    }
+
+    // Pushes the instruction or the body of the macro
+    fn expand(
+        &mut self,
+        words: Vec<Word>,
+        file: &Option<Arc<str>>,
+        line: usize,
+        text: &Arc<str>,
+        expanded_from: Option<&str>,
+        depth: usize,
+    ) -> Result<(), String> {
+        // Only the first line over the maximum is reported, the rest are dropped
+        if self.expansions > MAX_EXPANSIONS {
+            return Ok(());
+        }
+        if self.expansions == MAX_EXPANSIONS {
+            self.expansions += 1;
+            return Err(format!(
+                "Macros are expanded into too many instructions (maximum is {})",
+                MAX_EXPANSIONS
+            ));
+        }
+        self.expansions += 1;
+        let Some(definition) = words.first().and_then(|name| self.macros.get(&name.text)) else {
+            self.lines.push(SourceLine {
+                file: file.clone(),
+                line,
+                text: text.clone(),
+                words,
+                expanded_from: expanded_from.map(String::from),
+            });
+            return Ok(());
+        };
+        let name = &words[0];
+        let arguments = &words[1..];
+        if arguments.len() != definition.params.len() {
+            return Err(format!(
+                "Macro `{}` takes {} arguments, but {} are given",
+                name.text,
+                definition.params.len(),
+                arguments.len()
+            ));
+        }
+        if depth == MAX_DEPTH {
+            return Err(format!(
+                "Macro `{}` is nested too deep (maximum is {})",
+                name.text, MAX_DEPTH
+            ));
+        }
+        let body: Vec<Vec<Word>> = definition
+            .body
+            .iter()
+            .map(|words| {
+                words
+                    .iter()
+                    .map(
+                        |word| match definition.params.iter().position(|p| *p == word.text) {
+                            Some(i) => arguments[i].clone(),
+                            None => Word {
+                                text: word.text.clone(),
+                                span: name.span,
+                            },
+                        },
+                    )
+                    .collect()
+            })
+            .collect();
+        let expanded_from = expanded_from.unwrap_or(&name.text).to_string();
+        for words in body {
+            self.expand(words, file, line, text, Some(&expanded_from), depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use crate::ByteCode;
+
+    #[test]
+    fn macros() {
+        let input = r#"
+// spawn(start_a, start_b) without arguments
+.macro spawn start_a start_b
+LOAD_VAL 0
+LOAD_VAL start_a
+LOAD_VAL 0
+LOAD_VAL start_b
+SPAWN
+.endm
+
+.macro square var
+READ_VAR var
+READ_VAR var
+MULTIPLY
+.endm
+
+.macro sum_of_squares a b result
+square a
+square b
+ADD
+WRITE_VAR result
+.endm
+
+LOAD_VAL 3
+WRITE_VAR x
+LOAD_VAL 4
+WRITE_VAR y
+sum_of_squares x y z
+READ_VAR z
+RETURN_VALUE
+"#;
+        let mut bytecode = ByteCode::from_bytecode_text(input).unwrap();
+        assert_eq!(bytecode.instructions().len(), 14);
+        assert!(bytecode.instructions()[4..12]
+            .iter()
+            .all(|instruction| instruction.index() == 27));
+        bytecode.interpret().unwrap();
+        assert_eq!(bytecode.ret(), Some(&25));
+    }
+
+    #[test]
+    fn includes() {
+        let dir = env::temp_dir().join(format!("bytecode-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("lib/spawn.bc"),
+            ".macro spawn start_a start_b\nLOAD_VAL 0\nLOAD_VAL start_a\nLOAD_VAL 0\nLOAD_VAL start_b\nSPAWN_IDS\n.endm\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/child.bc"),
+            "// child\nSELF_ID\nPARENT_ID\nSEND_CHANNEL\nLOAD_VAL 1\nLOAD_VAL 2\nSUB\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.bc"),
+            r#".include "lib/spawn.bc"
+spawn 11 11
+RECV_CHANNEL
+WRITE_VAR b
+RECV_CHANNEL
+READ_VAR b
+ADD
+RETURN_VALUE
+.include "lib/child.bc"
+"#,
+        )
+        .unwrap();
+        let main = dir.join("main.bc");
+        let mut bytecode = ByteCode::from_bytecode_file(&main).unwrap();
+        let error = bytecode.interpret().unwrap_err().to_string();
+        let child = dir.join("lib").join("child.bc");
+        assert!(
+            error.ends_with(&format!(
+                "File: {}, line: 7, error: Substraction overflow occurred (1 - 2)",
+                child.display()
+            )),
+            "{}",
+            error
+        );
+
+        fs::write(dir.join("lib/child.bc"), "SELF_ID\nPARENT_ID\nSEND_CHANNEL\nLOAD_VAL 0\nRETURN_VALUE 1\n.include \"../main.bc\"\n").unwrap();
+        let errors = ByteCode::from_bytecode_file(&main).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                format!(
+                    "{}:6:10: error: Including of `{}` makes a cycle\n  |\n6 | .include \"../main.bc\"\n  |          ^^^^^^^^^^^^",
+                    child.display(),
+                    dir.join("lib").join("../main.bc").display()
+                ),
+                format!(
+                    "{}:5:14: error: Unexpected operand `1` of RETURN_VALUE\n  |\n5 | RETURN_VALUE 1\n  |              ^",
+                    child.display()
+                ),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directive_errors() {
+        let errors = ByteCode::from_bytecode_text(
+            ".macro one x\nLOAD_VAL x\n.endm\none\none 1 2\none push\n.endm\n.define\n.include file\n.include \"missing.bc\"\n.macro open\n",
+        )
+        .unwrap_err();
+        let first_lines: Vec<_> = errors.iter().map(|e| e.lines().next().unwrap()).collect();
+        assert_eq!(
+            first_lines,
+            vec![
+                "<input>:4:1: error: Macro `one` takes 1 arguments, but 0 are given",
+                "<input>:5:1: error: Macro `one` takes 1 arguments, but 2 are given",
+                "<input>:7:1: error: `.endm` without `.macro`",
+                "<input>:8:1: error: Unknown directive `.define`",
+                "<input>:9:1: error: Expected `.include \"file\"`",
+                "<input>:10:10: error: Including of `missing.bc` failed: No such file or directory (os error 2)",
+                "<input>:11:8: error: Macro `open` isn't terminated by `.endm`",
+                "<input>:6:5: error: Invalid operand `push` of LOAD_VAL (in macro `one`)",
+            ]
+        );
+        assert_eq!(
+            errors[7],
+            "<input>:6:5: error: Invalid operand `push` of LOAD_VAL (in macro `one`)\n  |\n6 | one push\n  |     ^^^^"
+        );
+
+        let errors = ByteCode::from_bytecode_text(
+            ".macro a\n.endm\n.macro b\na\na\na\na\n.endm\n.macro c\nb\nb\nb\nb\n.endm\n.macro d\nc\nc\nc\nc\n.endm\n.macro e\nd\nd\nd\nd\n.endm\n.macro f\ne\ne\ne\ne\n.endm\n.macro g\nf\nf\nf\nf\n.endm\n.macro h\ng\ng\ng\ng\n.endm\n.macro i\nh\nh\nh\nh\n.endm\n.macro j\ni\ni\ni\ni\n.endm\n.macro k\nj\nj\nj\nj\n.endm\nk\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "<input>:63:1: error: Macros are expanded into too many instructions (maximum is 1048576)\n   |\n63 | k\n   | ^",
+            ]
+        );
+
+        let errors = ByteCode::from_bytecode_text(".macro loop\nloop\n.endm\nloop\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["<input>:4:1: error: Macro `loop` is nested too deep (maximum is 32)\n  |\n4 | loop\n  | ^^^^"]
+        );
+    }
+
+    #[test]
+    fn too_many_expansions() {
+        let mut input = String::new();
+        for (name, used) in ["b", "c", "d", "e", "f", "g", "h", "i", "j", "k"]
+            .into_iter()
+            .zip(["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"])
+        {
+            input += &format!(
+                ".macro {}\n{}\n{}\n{}\n{}\n.endm\n",
+                name, used, used, used, used
+            );
+        }
+        let input = format!(".macro a\n.endm\n{}k\nLOAD_VAL 1\nk\nRETURN_VALUE\n", input);
+        let errors = ByteCode::from_bytecode_text(&input).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "<input>:63:1: error: Macros are expanded into too many instructions (maximum is 1048576)\n   |\n63 | k\n   | ^",
+            ]
+        );
+    }
+}
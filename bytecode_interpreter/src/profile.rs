@@ -0,0 +1,340 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    time::Duration,
+};
+
+use crate::{observer::Accumulator, Id, IndexedInstruction, Observer, Step};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counter {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, other: Counter) {
+        self.count += other.count;
+        self.time += other.time;
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ThreadProfile {
+    pub positions: BTreeMap<usize, Counter>,
+    pub opcodes: BTreeMap<&'static str, Counter>,
+    // Taken jumps to the same or a previous position by the positions of the jump and the target
+    pub back_edges: BTreeMap<(usize, usize), u64>,
+}
+
+impl ThreadProfile {
+    fn merge(&mut self, other: &ThreadProfile) {
+        for (position, counter) in &other.positions {
+            self.positions.entry(*position).or_default().add(*counter);
+        }
+        for (opcode, counter) in &other.opcodes {
+            self.opcodes.entry(opcode).or_default().add(*counter);
+        }
+        for (edge, count) in &other.back_edges {
+            *self.back_edges.entry(*edge).or_default() += count;
+        }
+    }
+
+    pub fn total(&self) -> Counter {
+        let mut total = Counter::default();
+        self.positions
+            .values()
+            .for_each(|counter| total.add(*counter));
+        total
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    pub threads: BTreeMap<Id, ThreadProfile>,
+    // Executed instructions by their positions
+    pub instructions: BTreeMap<usize, IndexedInstruction>,
+}
+
+impl Profile {
+    // Profiles of runs of the same program are merged into one
+    pub fn merge(&mut self, other: &Profile) {
+        for (id, thread) in &other.threads {
+            self.threads.entry(*id).or_default().merge(thread);
+        }
+        for (position, instruction) in &other.instructions {
+            self.instructions
+                .entry(*position)
+                .or_insert_with(|| instruction.clone());
+        }
+    }
+
+    pub fn total(&self) -> Counter {
+        let mut total = Counter::default();
+        self.threads
+            .values()
+            .for_each(|thread| total.add(thread.total()));
+        total
+    }
+
+    // Source lines of all threads from the hottest one
+    pub fn lines(&self) -> Vec<(String, Counter)> {
+        let mut lines: HashMap<String, Counter> = HashMap::new();
+        for thread in self.threads.values() {
+            for (position, counter) in &thread.positions {
+                let location = self.location(*position);
+                lines.entry(location).or_default().add(*counter);
+            }
+        }
+        sorted(lines)
+    }
+
+    // Opcodes of all threads from the hottest one
+    pub fn opcodes(&self) -> Vec<(&'static str, Counter)> {
+        let mut opcodes: HashMap<&'static str, Counter> = HashMap::new();
+        for thread in self.threads.values() {
+            for (opcode, counter) in &thread.opcodes {
+                opcodes.entry(opcode).or_default().add(*counter);
+            }
+        }
+        sorted(opcodes)
+    }
+
+    // Back-edges of loops of all threads from the most taken one
+    pub fn loops(&self) -> Vec<((usize, usize), u64)> {
+        let mut loops: BTreeMap<(usize, usize), u64> = BTreeMap::new();
+        for thread in self.threads.values() {
+            for (edge, count) in &thread.back_edges {
+                *loops.entry(*edge).or_default() += count;
+            }
+        }
+        let mut loops: Vec<_> = loops.into_iter().collect();
+        loops.sort_by(|(a, count_a), (b, count_b)| count_b.cmp(count_a).then(a.cmp(b)));
+        loops
+    }
+
+    // `file:line` of the instruction at the position, lines are 1-based like in parse errors
+    pub fn location(&self, position: usize) -> String {
+        match self.instructions.get(&position) {
+            Some(instruction) => format!(
+                "{}:{}",
+                instruction.file().map_or("<input>", |file| file),
+                instruction.index() + 1
+            ),
+            None => format!("<unknown>:{}", position),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let total = self.total();
+        let mut output = format!(
+            "Profile: {} steps, {:?} in {} threads\n",
+            total.count,
+            total.time,
+            self.threads.len()
+        );
+        let row = |output: &mut String, name: &str, counter: Counter| {
+            let _ = writeln!(
+                output,
+                "{:>12} {:>14} {:>6.2}%  {}",
+                counter.count,
+                format!("{:?}", counter.time),
+                percent(counter.time, total.time),
+                name
+            );
+        };
+        let header = format!("{:>12} {:>14} {:>7}  ", "count", "time", "time");
+        let _ = writeln!(output, "\nHottest lines:\n{}line", header);
+        for (line, counter) in self.lines() {
+            row(&mut output, &line, counter);
+        }
+        let _ = writeln!(output, "\nOpcodes:\n{}opcode", header);
+        for (opcode, counter) in self.opcodes() {
+            row(&mut output, opcode, counter);
+        }
+        let _ = writeln!(output, "\nThreads:\n{}thread", header);
+        for (id, thread) in &self.threads {
+            row(&mut output, &id.to_string(), thread.total());
+        }
+        let loops = self.loops();
+        if !loops.is_empty() {
+            let _ = writeln!(output, "\nLoops:\n{:>12}  back-edge", "count");
+            for ((from, to), count) in loops {
+                let _ = writeln!(
+                    output,
+                    "{:>12}  {} -> {} (position {} -> {})",
+                    count,
+                    self.location(from),
+                    self.location(to),
+                    from,
+                    to
+                );
+            }
+        }
+        output
+    }
+
+    // Stacks of thread, line and opcode with nanoseconds for flamegraph tools
+    pub fn to_folded(&self) -> String {
+        let mut output = String::new();
+        for (id, thread) in &self.threads {
+            for (position, counter) in &thread.positions {
+                let opcode = self
+                    .instructions
+                    .get(position)
+                    .map_or("UNKNOWN", |i| i.instruction().mnemonic());
+                let _ = writeln!(
+                    output,
+                    "thread {};{};{} {}",
+                    id,
+                    self.location(*position),
+                    opcode,
+                    counter.time.as_nanos()
+                );
+            }
+        }
+        output
+    }
+}
+
+fn sorted<K: Ord>(map: HashMap<K, Counter>) -> Vec<(K, Counter)> {
+    let mut entries: Vec<_> = map.into_iter().collect();
+    entries.sort_by(|(name_a, a), (name_b, b)| {
+        (b.time, b.count)
+            .cmp(&(a.time, a.count))
+            .then(name_a.cmp(name_b))
+    });
+    entries
+}
+
+fn percent(time: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        return 0.0;
+    }
+    time.as_secs_f64() * 100.0 / total.as_secs_f64()
+}
+
+// Counts and times every executed instruction. Steps are gathered per slice of a thread and
+// merged into the profile after the slice.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    profile: Accumulator<Profile>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn profile(&self) -> Profile {
+        // Steps, which are executed by the caller outside of slices
+        self.profile.flush(Profile::merge);
+        self.profile.lock().clone()
+    }
+}
+
+impl Observer for Profiler {
+    fn step(&self, step: &Step) {
+        let counter = Counter {
+            count: 1,
+            time: step.elapsed,
+        };
+        self.profile.update(|profile| {
+            profile
+                .instructions
+                .entry(step.position)
+                .or_insert_with(|| step.instruction.clone());
+            let thread = profile.threads.entry(step.thread).or_default();
+            thread
+                .positions
+                .entry(step.position)
+                .or_default()
+                .add(counter);
+            let instruction = step.instruction.instruction();
+            thread
+                .opcodes
+                .entry(instruction.mnemonic())
+                .or_default()
+                .add(counter);
+            if instruction.is_jump() && step.next <= step.position {
+                *thread
+                    .back_edges
+                    .entry((step.position, step.next))
+                    .or_default() += 1;
+            }
+        });
+    }
+
+    fn flush(&self, _thread: Id) {
+        self.profile.flush(Profile::merge);
+    }
+
+    fn timed(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::Profiler;
+    use crate::ByteCode;
+
+    // count down from 3
+    const LOOP: &str = r#"
+LOAD_VAL 3
+WRITE_VAR x
+READ_VAR x
+LOAD_VAL 1
+SUB
+WRITE_VAR x
+READ_VAR x
+LOAD_VAL 0
+LOAD_VAL 2
+JUMP_GREATER_THAN
+READ_VAR x
+RETURN_VALUE
+"#;
+
+    #[test]
+    fn counts_and_loops() {
+        let profiler = Arc::new(Profiler::new());
+        let mut bytecode = ByteCode::from_bytecode_text(LOOP).unwrap();
+        bytecode.set_observer(profiler.clone());
+        bytecode.interpret().unwrap();
+        let profile = profiler.profile();
+
+        assert_eq!(profile.total().count, 2 + 3 * 8 + 2);
+        let thread = &profile.threads[&0];
+        assert_eq!(thread.positions[&0].count, 1);
+        assert_eq!(thread.positions[&9].count, 3);
+        assert_eq!(thread.opcodes["READ_VAR"].count, 3 * 2 + 1);
+        assert_eq!(thread.opcodes["JUMP_GREATER_THAN"].count, 3);
+        assert_eq!(thread.back_edges.len(), 1);
+        assert_eq!(thread.back_edges[&(9, 2)], 2);
+        assert_eq!(profile.loops(), vec![((9, 2), 2)]);
+        assert_eq!(profile.location(9), "<input>:11");
+
+        let mut lines = profile.lines();
+        lines.sort_by_key(|(line, _)| line.clone());
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[0].0, "<input>:10");
+        assert_eq!(lines[0].1.count, 3);
+
+        let text = profile.to_text();
+        assert!(text.starts_with("Profile: 28 steps, "), "{}", text);
+        assert!(
+            text.ends_with(
+                "\nLoops:\n       count  back-edge\n           2  <input>:11 -> <input>:4 (position 9 -> 2)\n"
+            ),
+            "{}",
+            text
+        );
+        let folded = profile.to_folded();
+        assert_eq!(folded.lines().count(), 12);
+        assert!(folded
+            .lines()
+            .any(|line| line.starts_with("thread 0;<input>:11;JUMP_GREATER_THAN ")));
+    }
+}
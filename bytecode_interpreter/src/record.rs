@@ -0,0 +1,333 @@
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+use crate::{ByteCode, Data, Error, Id};
+
+const HEADER: &str = "bytecode-recording 1";
+
+// Interaction between threads. Only interactions are recorded, so a replay reproduces executions,
+// which depend only on their order. Limits of the tree, cancellation, deadlines and host functions
+// depend on the interleaving of all instructions of threads, so their replay may diverge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Spawn { parent: Id, children: [Id; 2] },
+    Send { from: Id, to: Id, data: Data },
+    Recv { from: Id, to: Id, data: Data },
+    Return { id: Id, data: Data },
+    Fail { id: Id },
+}
+
+impl Event {
+    // The thread, which causes the event
+    pub fn thread(&self) -> Id {
+        match *self {
+            Event::Spawn { parent, .. } => parent,
+            Event::Send { from, .. } => from,
+            Event::Recv { to, .. } => to,
+            Event::Return { id, .. } | Event::Fail { id } => id,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Spawn {
+                parent,
+                children: [a, b],
+            } => write!(f, "spawn {} {} {}", parent, a, b),
+            Event::Send { from, to, data } => write!(f, "send {} {} {}", from, to, data),
+            Event::Recv { from, to, data } => write!(f, "recv {} {} {}", from, to, data),
+            Event::Return { id, data } => write!(f, "return {} {}", id, data),
+            Event::Fail { id } => write!(f, "fail {}", id),
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_ascii_whitespace();
+        let kind = words.next().ok_or("Empty event")?;
+        let mut operand = || {
+            words
+                .next()
+                .ok_or(format!("Not enough operands of `{}`", kind))?
+                .parse::<Data>()
+                .map_err(|_| format!("Parsing of operand of `{}`", kind))
+        };
+        let mut id = || {
+            operand().and_then(|id| Id::try_from(id).map_err(|_| format!("Invalid id `{}`", id)))
+        };
+        let event = match kind {
+            "spawn" => Event::Spawn {
+                parent: id()?,
+                children: [id()?, id()?],
+            },
+            "send" => Event::Send {
+                from: id()?,
+                to: id()?,
+                data: operand()?,
+            },
+            "recv" => Event::Recv {
+                from: id()?,
+                to: id()?,
+                data: operand()?,
+            },
+            "return" => Event::Return {
+                id: id()?,
+                data: operand()?,
+            },
+            "fail" => Event::Fail { id: id()? },
+            _ => return Err(format!("Unknown event `{}`", kind)),
+        };
+        Ok(event)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub events: Vec<Event>,
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Recording {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("Unsupported format of recording".into());
+        }
+        // Lines are counted from 1 with the header
+        let events = lines
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(i, l)| {
+                l.parse()
+                    .map_err(|e| format!("Line: {}, error: {}", i + 2, e))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { events })
+    }
+}
+
+// Executes the tree on the current thread, so the interactions between threads happen in the
+// recorded order
+pub(crate) fn replay(bytecode: &mut ByteCode, recording: &Recording) -> Result<(), Error> {
+    let runtime = bytecode.runtime.clone();
+    let expected = &recording.events;
+    let mut occurred = 0;
+    let check = |occurred: &mut usize| -> Result<(), Error> {
+        for event in runtime.events(*occurred) {
+            match expected.get(*occurred) {
+                Some(expected) if *expected == event => *occurred += 1,
+                Some(expected) => {
+                    return Err(format!(
+                        "Replay diverged at event {}: expected `{}`, got `{}`",
+                        occurred, expected, event
+                    )
+                    .into())
+                }
+                None => return Err(format!("Replay diverged: unexpected event `{}`", event).into()),
+            }
+        }
+        Ok(())
+    };
+    let mut config = bytecode.config().clone();
+    config.record = true;
+    bytecode.set_config(config);
+    let result = bytecode.interpret_deterministic(
+        &mut |runnable| {
+            check(&mut occurred)?;
+            let id = expected.get(occurred).map_or(runnable[0], Event::thread);
+            if !runnable.contains(&id) {
+                return Err(format!(
+                    "Replay diverged at event {}: thread {} isn't runnable",
+                    occurred, id
+                )
+                .into());
+            }
+            Ok(id)
+        },
+        None,
+    );
+    check(&mut occurred)?;
+    if occurred < expected.len() {
+        return Err(format!(
+            "Replay diverged: event `{}` hasn't occurred",
+            expected[occurred]
+        )
+        .into());
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use crate::{ByteCode, Event, Limits, Recording};
+
+    const SEND_RECV: &str = r#"
+// spawn(f_send, f_send)
+LOAD_VAL 0
+LOAD_VAL 13
+LOAD_VAL 0
+LOAD_VAL 13
+SPAWN_IDS
+
+// return recv(a) * 10 + recv(b)
+RECV_CHANNEL
+WRITE_VAR b
+RECV_CHANNEL
+LOAD_VAL 10
+MULTIPLY
+READ_VAR b
+ADD
+RETURN_VALUE
+
+// send(self_id(), parent_id())
+SELF_ID
+PARENT_ID
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+    // Child 1 sends to child 2, which may have already finished
+    const RACE: &str = r#"
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 12
+SPAWN
+LOAD_VAL 0
+RETURN_VALUE
+
+LOAD_VAL 1
+LOAD_VAL 2
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+    // Both children write a variable, but the heap of the tree holds only one of them
+    const HEAP: &str = r#"
+LOAD_VAL 0
+LOAD_VAL 7
+LOAD_VAL 0
+LOAD_VAL 7
+SPAWN
+LOAD_VAL 0
+RETURN_VALUE
+
+LOAD_VAL 1
+WRITE_VAR variable
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+    #[test]
+    fn record_and_replay() {
+        let mut bytecode = ByteCode::from_bytecode_text(SEND_RECV).unwrap();
+        bytecode.set_record(true);
+        bytecode.interpret().unwrap();
+        let recording = bytecode.recording().unwrap();
+        assert_eq!(recording.events.len(), 8);
+        assert_eq!(
+            recording.events[0],
+            Event::Spawn {
+                parent: 0,
+                children: [1, 2]
+            }
+        );
+        assert_eq!(
+            recording.events.last(),
+            Some(&Event::Return { id: 0, data: 12 })
+        );
+
+        let path = env::temp_dir().join(format!("bytecode-recording-{}", std::process::id()));
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, recording);
+
+        let mut bytecode = ByteCode::from_bytecode_text(SEND_RECV).unwrap();
+        bytecode.replay(&loaded).unwrap();
+        assert_eq!(bytecode.ret(), Some(&12));
+        assert_eq!(bytecode.recording(), Some(recording));
+    }
+
+    #[test]
+    fn replay_reproduces_failure() {
+        let recording: Recording =
+            "bytecode-recording 1\nspawn 0 1 2\nreturn 2 0\nfail 1\nfail 0\n"
+                .parse()
+                .unwrap();
+        for _ in 0..10 {
+            let mut bytecode = ByteCode::from_bytecode_text(RACE).unwrap();
+            let error = bytecode.replay(&recording).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Thread 1 failed: Line: 12, error: Thread 2 has already finished"
+            );
+        }
+    }
+
+    #[test]
+    fn replay_divergence() {
+        let recording: Recording = "bytecode-recording 1\nspawn 0 1 2\nsend 1 2 5\n"
+            .parse()
+            .unwrap();
+        let mut bytecode = ByteCode::from_bytecode_text(RACE).unwrap();
+        let error = bytecode.replay(&recording).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Replay diverged at event 1: expected `send 1 2 5`, got `send 1 2 1`"
+        );
+    }
+
+    #[test]
+    fn replay_of_limit_diverges() {
+        // Recorded on parallel workers, which have executed both children at the same time
+        let recording: Recording =
+            "bytecode-recording 1\nspawn 0 1 2\nfail 1\nreturn 2 0\nfail 0\n"
+                .parse()
+                .unwrap();
+        let mut bytecode = ByteCode::from_bytecode_text(HEAP).unwrap();
+        bytecode.set_limits(Limits {
+            max_heap: Some(64),
+            ..Limits::default()
+        });
+        let error = bytecode.replay(&recording).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Replay diverged at event 1: expected `fail 1`, got `return 1 0`"
+        );
+    }
+}
@@ -0,0 +1,992 @@
+// Register machine for single-threaded programs. Variables and slots of the stack are numbered
+// registers, so an assignment like `x = x + 1` is one three-address operation instead of four
+// instructions. The translator follows the stack of every block: constants are folded into
+// operands, variables, which are written or checked before in the block, are read without a copy,
+// and the result of an operation is written right into the variable. At the end of a block the
+// stack is stored in its slots, so the depth has to be the same on all paths to a block, and the
+// targets of jumps have to be constant. Errors are the same as the ones of the stack interpreter.
+
+use std::{fmt, sync::Arc};
+
+use crate::{
+    instructions::{Comparison, Logic, Operation},
+    ByteCode, Cfg, Data, Error, Ident, IndexedInstruction, Instruction, Overflow,
+};
+
+pub type Register = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Const(Data),
+}
+
+// Targets of jumps are indexes of operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Move {
+        dst: Register,
+        src: Operand,
+    },
+    // Fails, if the variable hasn't been written
+    Read {
+        dst: Register,
+        var: Register,
+    },
+    Arithmetic {
+        operation: Operation,
+        overflow: Overflow,
+        dst: Register,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    // The wrapped result and 1, if the operation has overflowed, otherwise 0
+    Carry {
+        operation: Operation,
+        dst: Register,
+        flag: Register,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    // 1, if the comparison holds, otherwise 0
+    Compare {
+        comparison: Comparison,
+        dst: Register,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Logical {
+        logic: Logic,
+        dst: Register,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Jump(usize),
+    // Jumps, if the comparison holds, otherwise goes on
+    Branch {
+        comparison: Comparison,
+        lhs: Operand,
+        rhs: Operand,
+        target: usize,
+    },
+    Return(Operand),
+    Log(Operand),
+    Fail(&'static str),
+    // Jump to a position outside of the stack instructions
+    Missing(Data),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "r{}", register),
+            Operand::Const(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = |operation| match operation {
+            Operation::Add => "+",
+            Operation::Sub => "-",
+            Operation::Mul => "*",
+        };
+        match self {
+            Op::Move { dst, src } => write!(f, "r{} = {}", dst, src),
+            Op::Read { dst, var } => write!(f, "r{} = read r{}", dst, var),
+            Op::Arithmetic {
+                operation,
+                overflow,
+                dst,
+                lhs,
+                rhs,
+            } => {
+                write!(f, "r{} = {} {} {}", dst, lhs, sign(*operation), rhs)?;
+                match overflow {
+                    Overflow::Checked => Ok(()),
+                    Overflow::Wrapping => write!(f, " wrapping"),
+                    Overflow::Saturating => write!(f, " saturating"),
+                }
+            }
+            Op::Carry {
+                operation,
+                dst,
+                flag,
+                lhs,
+                rhs,
+            } => write!(
+                f,
+                "r{}, r{} = {} {} {} carry",
+                dst,
+                flag,
+                lhs,
+                sign(*operation),
+                rhs
+            ),
+            Op::Compare {
+                comparison,
+                dst,
+                lhs,
+                rhs,
+            } => write!(f, "r{} = {} {} {}", dst, lhs, comparison.operator(), rhs),
+            Op::Logical {
+                logic,
+                dst,
+                lhs,
+                rhs,
+            } => write!(f, "r{} = {} {} {}", dst, lhs, logic.operator(), rhs),
+            Op::Jump(target) => write!(f, "jump {}", target),
+            Op::Branch {
+                comparison,
+                lhs,
+                rhs,
+                target,
+            } => write!(
+                f,
+                "if {} {} {} jump {}",
+                lhs,
+                comparison.operator(),
+                rhs,
+                target
+            ),
+            Op::Return(value) => write!(f, "return {}", value),
+            Op::Log(value) => write!(f, "log {}", value),
+            Op::Fail(message) => write!(f, "fail {:?}", message),
+            Op::Missing(position) => write!(f, "missing {}", position),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RegisterCode {
+    ops: Vec<Op>,
+    // Positions of stack instructions, which the operations are translated from
+    origins: Vec<usize>,
+    // Names of variables, which are the first registers, slots of the stack follow them
+    vars: Vec<Ident>,
+    registers: usize,
+    instructions: Arc<[IndexedInstruction]>,
+}
+
+impl ByteCode {
+    // The overflow handling of the config is kept by the operations
+    pub fn to_registers(&self) -> Result<RegisterCode, Error> {
+        Translator::new(self)?.translate()
+    }
+}
+
+impl RegisterCode {
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn vars(&self) -> &[Ident] {
+        &self.vars
+    }
+
+    pub fn registers(&self) -> usize {
+        self.registers
+    }
+
+    // Logged data is printed like by the stack interpreter without an observer
+    pub fn run(&self, vars: &[(&str, Data)]) -> Result<Data, Error> {
+        self.execute(vars, &mut |data| println!("\x1b[31mLOG: {}\x1b[0m", data))
+    }
+
+    pub fn execute(&self, vars: &[(&str, Data)], log: &mut dyn FnMut(Data)) -> Result<Data, Error> {
+        let mut registers: Vec<Option<Data>> = vec![None; self.registers];
+        for (name, value) in vars {
+            if let Some(var) = self.vars.iter().position(|var| var == name) {
+                registers[var] = Some(*value);
+            }
+        }
+        // The translator reads registers without the check only after they are written
+        let value = |registers: &[Option<Data>], operand| match operand {
+            Operand::Register(register) => registers[register].unwrap_or_default(),
+            Operand::Const(value) => value,
+        };
+        let mut next = 0;
+        loop {
+            let current = next;
+            next += 1;
+            match self.ops[current] {
+                Op::Move { dst, src } => registers[dst] = Some(value(&registers, src)),
+                Op::Read { dst, var } => {
+                    let message = || format!("Variable `{}` doesn't exist", self.vars[var]);
+                    let data = registers[var].ok_or_else(|| self.fail(current, message()))?;
+                    registers[dst] = Some(data);
+                }
+                Op::Arithmetic {
+                    operation,
+                    overflow,
+                    dst,
+                    lhs,
+                    rhs,
+                } => {
+                    let (lhs, rhs) = (value(&registers, lhs), value(&registers, rhs));
+                    let data = operation
+                        .apply(lhs, rhs, overflow)
+                        .map_err(|e| self.fail(current, e))?;
+                    registers[dst] = Some(data);
+                }
+                Op::Carry {
+                    operation,
+                    dst,
+                    flag,
+                    lhs,
+                    rhs,
+                } => {
+                    let (lhs, rhs) = (value(&registers, lhs), value(&registers, rhs));
+                    let (data, overflowed) = operation.overflowing(lhs, rhs);
+                    registers[dst] = Some(data);
+                    registers[flag] = Some(overflowed.into());
+                }
+                Op::Compare {
+                    comparison,
+                    dst,
+                    lhs,
+                    rhs,
+                } => {
+                    let holds = comparison.holds(value(&registers, lhs), value(&registers, rhs));
+                    registers[dst] = Some(holds.into());
+                }
+                Op::Logical {
+                    logic,
+                    dst,
+                    lhs,
+                    rhs,
+                } => {
+                    let holds = logic.holds(value(&registers, lhs), value(&registers, rhs));
+                    registers[dst] = Some(holds.into());
+                }
+                Op::Jump(target) => next = target,
+                Op::Branch {
+                    comparison,
+                    lhs,
+                    rhs,
+                    target,
+                } => {
+                    if comparison.holds(value(&registers, lhs), value(&registers, rhs)) {
+                        next = target;
+                    }
+                }
+                Op::Return(data) => return Ok(value(&registers, data)),
+                Op::Log(data) => log(value(&registers, data)),
+                Op::Fail(message) => return Err(self.fail(current, message)),
+                Op::Missing(position) => {
+                    return Err(
+                        format!("Instruction doesn't exist at {} position", position).into(),
+                    )
+                }
+            }
+        }
+    }
+
+    fn fail(&self, op: usize, e: impl Into<Error>) -> Error {
+        let instruction = &self.instructions[self.origins[op]];
+        Error::Line {
+            file: instruction.file().cloned(),
+            line: instruction.index(),
+            source: Box::new(e.into()),
+        }
+    }
+}
+
+// Registers of variables and operations with their positions
+impl fmt::Display for RegisterCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (register, var) in self.vars.iter().enumerate() {
+            writeln!(f, "// r{}: {}", register, var)?;
+        }
+        for (index, op) in self.ops.iter().enumerate() {
+            writeln!(f, "{:>6}  {}", index, op)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Block(usize),
+    Missing(Data),
+}
+
+// Operation, which pushes 1 or 0
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    Compare(Comparison),
+    Logical(Logic),
+}
+
+impl Condition {
+    fn holds(self, lhs: Data, rhs: Data) -> bool {
+        match self {
+            Condition::Compare(comparison) => comparison.holds(lhs, rhs),
+            Condition::Logical(logic) => logic.holds(lhs, rhs),
+        }
+    }
+
+    fn op(self, dst: Register, lhs: Operand, rhs: Operand) -> Op {
+        match self {
+            Condition::Compare(comparison) => Op::Compare {
+                comparison,
+                dst,
+                lhs,
+                rhs,
+            },
+            Condition::Logical(logic) => Op::Logical {
+                logic,
+                dst,
+                lhs,
+                rhs,
+            },
+        }
+    }
+}
+
+// Operation, whose target isn't known, until all blocks are placed
+#[derive(Debug, Clone, Copy)]
+enum Pending {
+    Op(Op),
+    Jump(Target),
+    Branch {
+        comparison: Comparison,
+        lhs: Operand,
+        rhs: Operand,
+        target: Target,
+    },
+}
+
+// Operations of a block with their positions and the following blocks with their depths
+type Translated = (Vec<(Pending, usize)>, Vec<(usize, usize)>);
+
+struct Translator<'a> {
+    instructions: &'a Arc<[IndexedInstruction]>,
+    cfg: Cfg,
+    vars: Vec<Ident>,
+    overflow: Overflow,
+    id: Data,
+    parent: Option<Data>,
+    // The maximal depth of the stack
+    slots: usize,
+}
+
+impl<'a> Translator<'a> {
+    fn new(bytecode: &'a ByteCode) -> Result<Self, Error> {
+        let mut vars = Vec::new();
+        for instruction in bytecode.instructions.iter() {
+            match instruction.instruction() {
+                Instruction::Spawn
+                | Instruction::SpawnIds
+                | Instruction::SendChannel
+                | Instruction::RecvChannel
+                | Instruction::CallHost(_) => {
+                    return Err(error(
+                        instruction,
+                        format!(
+                            "{} can't be translated, only single-threaded programs without host \
+                             functions are supported",
+                            instruction.instruction().mnemonic()
+                        ),
+                    ));
+                }
+                Instruction::ReadVar(ident) | Instruction::WriteVar(ident)
+                    if !vars.contains(ident) =>
+                {
+                    vars.push(ident.clone());
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            instructions: &bytecode.instructions,
+            cfg: Cfg::new(&bytecode.instructions),
+            vars,
+            overflow: bytecode.config.overflow,
+            id: bytecode.id as Data,
+            parent: bytecode.parent.map(|parent| parent as Data),
+            slots: 0,
+        })
+    }
+
+    fn translate(mut self) -> Result<RegisterCode, Error> {
+        // Blocks are translated, when they are reached, so their depths are known
+        let blocks = self.cfg.blocks.len();
+        let mut depths = vec![None; blocks];
+        let mut translated = vec![None; blocks];
+        let mut queue = Vec::new();
+        if blocks > 0 {
+            depths[0] = Some(0);
+            queue.push(0);
+        }
+        while let Some(block) = queue.pop() {
+            let (ops, successors) = self.block(block, depths[block].unwrap_or_default())?;
+            translated[block] = Some(ops);
+            for (successor, depth) in successors {
+                match depths[successor] {
+                    None => {
+                        depths[successor] = Some(depth);
+                        queue.push(successor);
+                    }
+                    Some(known) if known != depth => {
+                        let instruction = &self.instructions[self.cfg.blocks[successor].start];
+                        let message = format!(
+                            "Depth of the stack is {} or {} on different paths, it has to be \
+                             the same",
+                            known.min(depth),
+                            known.max(depth)
+                        );
+                        return Err(error(instruction, message));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        // Blocks are placed in the order of positions, so a block falls through to the next one
+        let mut starts = vec![0; blocks];
+        let mut pending = Vec::new();
+        for (block, ops) in translated.into_iter().enumerate() {
+            starts[block] = pending.len();
+            pending.extend(ops.into_iter().flatten());
+        }
+        if pending.is_empty() {
+            pending.push((Pending::Op(Op::Missing(0)), 0));
+        }
+        // Missing targets of branches are placed after all blocks
+        let end = pending.len();
+        let mut missing = Vec::new();
+        let mut ops = Vec::with_capacity(end);
+        let mut origins = Vec::with_capacity(end);
+        for (op, origin) in pending {
+            let mut resolve = |target| match target {
+                Target::Block(block) => starts[block],
+                Target::Missing(position) => {
+                    missing.push((Op::Missing(position), origin));
+                    end + missing.len() - 1
+                }
+            };
+            let op = match op {
+                Pending::Op(op) => op,
+                Pending::Jump(Target::Missing(position)) => Op::Missing(position),
+                Pending::Jump(target) => Op::Jump(resolve(target)),
+                Pending::Branch {
+                    comparison,
+                    lhs,
+                    rhs,
+                    target,
+                } => Op::Branch {
+                    comparison,
+                    lhs,
+                    rhs,
+                    target: resolve(target),
+                },
+            };
+            ops.push(op);
+            origins.push(origin);
+        }
+        for (op, origin) in missing {
+            ops.push(op);
+            origins.push(origin);
+        }
+
+        Ok(RegisterCode {
+            ops,
+            origins,
+            registers: self.vars.len() + self.slots,
+            vars: self.vars,
+            instructions: self.instructions.clone(),
+        })
+    }
+
+    fn slot(&mut self, depth: usize) -> Register {
+        self.slots = self.slots.max(depth + 1);
+        self.vars.len() + depth
+    }
+
+    fn var(&self, ident: &Ident) -> Register {
+        self.vars
+            .iter()
+            .position(|var| var == ident)
+            .expect("Variable isn't collected")
+    }
+
+    fn target(&self, operand: Operand, instruction: &IndexedInstruction) -> Result<Target, Error> {
+        let Operand::Const(position) = operand else {
+            return Err(error(
+                instruction,
+                "Target of the jump isn't constant".into(),
+            ));
+        };
+        match usize::try_from(position) {
+            Ok(start) if start < self.instructions.len() => match self.cfg.block_of(start) {
+                Some(block) if self.cfg.blocks[block].start == start => Ok(Target::Block(block)),
+                _ => Err(error(
+                    instruction,
+                    "Target of the jump isn't pushed right before it".into(),
+                )),
+            },
+            _ => Ok(Target::Missing(position)),
+        }
+    }
+
+    // Stores operands of the stack in their slots, only the ones of the variable, if it's given
+    fn store(
+        &mut self,
+        stack: &mut [Operand],
+        only: Option<Register>,
+        ops: &mut Vec<(Pending, usize)>,
+        origin: usize,
+    ) {
+        for (depth, operand) in stack.iter_mut().enumerate() {
+            let slot = self.slot(depth);
+            if *operand == Operand::Register(slot)
+                || only.is_some_and(|var| *operand != Operand::Register(var))
+            {
+                continue;
+            }
+            ops.push((
+                Pending::Op(Op::Move {
+                    dst: slot,
+                    src: *operand,
+                }),
+                origin,
+            ));
+            *operand = Operand::Register(slot);
+        }
+    }
+
+    fn block(&mut self, block: usize, depth: usize) -> Result<Translated, Error> {
+        let block = self.cfg.blocks[block];
+        let len = self.instructions.len();
+        let mut stack: Vec<_> = (0..depth)
+            .map(|depth| Operand::Register(self.slot(depth)))
+            .collect();
+        // Variables, which are known to be written in the block, so they are read without checks
+        let mut written = vec![false; self.vars.len()];
+        let mut ops = Vec::new();
+        let mut successors = Vec::new();
+        let instructions = self.instructions.clone();
+        for position in block.start..block.end {
+            let instruction = &instructions[position];
+            let popped = match instruction.instruction() {
+                Instruction::WriteVar(_)
+                | Instruction::RetVal
+                | Instruction::Jump
+                | Instruction::Log
+                | Instruction::LogicalNot => 1,
+                Instruction::JumpLessThan
+                | Instruction::JumpGreaterThan
+                | Instruction::JumpEqual => 3,
+                Instruction::JumpIf | Instruction::JumpIfNot => 2,
+                Instruction::LoadVal(_)
+                | Instruction::ReadVar(_)
+                | Instruction::SelfId
+                | Instruction::ParentId
+                | Instruction::Unk => 0,
+                _ => 2,
+            };
+            // The instruction fails, so the rest of the block isn't executed
+            if stack.len() < popped {
+                ops.push((Pending::Op(Op::Fail("Stack is empty")), position));
+                return Ok((ops, successors));
+            }
+            let operands = stack.split_off(stack.len() - popped);
+            let arithmetic = |operation, overflow| (Some(operation), Some(overflow));
+            let (operation, overflow) = match instruction.instruction() {
+                Instruction::Add => arithmetic(Operation::Add, self.overflow),
+                Instruction::Sub => arithmetic(Operation::Sub, self.overflow),
+                Instruction::Mul => arithmetic(Operation::Mul, self.overflow),
+                Instruction::AddWrap => arithmetic(Operation::Add, Overflow::Wrapping),
+                Instruction::SubWrap => arithmetic(Operation::Sub, Overflow::Wrapping),
+                Instruction::MulWrap => arithmetic(Operation::Mul, Overflow::Wrapping),
+                Instruction::AddSat => arithmetic(Operation::Add, Overflow::Saturating),
+                Instruction::SubSat => arithmetic(Operation::Sub, Overflow::Saturating),
+                Instruction::MulSat => arithmetic(Operation::Mul, Overflow::Saturating),
+                Instruction::AddCarry => (Some(Operation::Add), None),
+                Instruction::SubCarry => (Some(Operation::Sub), None),
+                Instruction::MulCarry => (Some(Operation::Mul), None),
+                _ => (None, None),
+            };
+            let comparison = match instruction.instruction() {
+                Instruction::JumpLessThan => Some(Comparison::Less),
+                Instruction::JumpGreaterThan => Some(Comparison::Greater),
+                Instruction::JumpEqual => Some(Comparison::Equal),
+                // The condition is compared with 0
+                Instruction::JumpIf => Some(Comparison::NotEqual),
+                Instruction::JumpIfNot => Some(Comparison::Equal),
+                _ => None,
+            };
+            let condition = match instruction.instruction() {
+                Instruction::LogicalAnd => Some(Condition::Logical(Logic::And)),
+                Instruction::LogicalOr => Some(Condition::Logical(Logic::Or)),
+                Instruction::LogicalNot => Some(Condition::Compare(Comparison::Equal)),
+                instruction => instruction.comparison().map(Condition::Compare),
+            };
+            let op = |op| (Pending::Op(op), position);
+            if let Some(condition) = condition {
+                // The negation compares its operand with 0
+                let (lhs, rhs) = match *operands.as_slice() {
+                    [lhs, rhs] => (lhs, rhs),
+                    [operand] => (operand, Operand::Const(0)),
+                    _ => unreachable!(),
+                };
+                if let (Operand::Const(lhs), Operand::Const(rhs)) = (lhs, rhs) {
+                    stack.push(Operand::Const(condition.holds(lhs, rhs).into()));
+                    continue;
+                }
+                let dst = self.slot(stack.len());
+                ops.push(op(condition.op(dst, lhs, rhs)));
+                stack.push(Operand::Register(dst));
+                continue;
+            }
+            match (instruction.instruction(), operation, comparison) {
+                (_, Some(operation), _) => {
+                    let (lhs, rhs) = (operands[0], operands[1]);
+                    match (lhs, rhs, overflow) {
+                        // Constants are folded, unless the operation fails
+                        (Operand::Const(lhs), Operand::Const(rhs), Some(overflow)) => {
+                            if let Ok(data) = operation.apply(lhs, rhs, overflow) {
+                                stack.push(Operand::Const(data));
+                                continue;
+                            }
+                        }
+                        (Operand::Const(lhs), Operand::Const(rhs), None) => {
+                            let (data, overflowed) = operation.overflowing(lhs, rhs);
+                            stack.push(Operand::Const(data));
+                            stack.push(Operand::Const(overflowed.into()));
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    let dst = self.slot(stack.len());
+                    match overflow {
+                        Some(overflow) => ops.push(op(Op::Arithmetic {
+                            operation,
+                            overflow,
+                            dst,
+                            lhs,
+                            rhs,
+                        })),
+                        None => {
+                            let flag = self.slot(stack.len() + 1);
+                            ops.push(op(Op::Carry {
+                                operation,
+                                dst,
+                                flag,
+                                lhs,
+                                rhs,
+                            }));
+                            stack.push(Operand::Register(dst));
+                            stack.push(Operand::Register(flag));
+                            continue;
+                        }
+                    }
+                    stack.push(Operand::Register(dst));
+                }
+                (Instruction::LoadVal(value), ..) => stack.push(Operand::Const(*value)),
+                (Instruction::ReadVar(ident), ..) => {
+                    let var = self.var(ident);
+                    if written[var] {
+                        stack.push(Operand::Register(var));
+                        continue;
+                    }
+                    let dst = self.slot(stack.len());
+                    ops.push(op(Op::Read { dst, var }));
+                    stack.push(Operand::Register(dst));
+                    written[var] = true;
+                }
+                (Instruction::WriteVar(ident), ..) => {
+                    let var = self.var(ident);
+                    let src = operands[0];
+                    // The old value, which is still on the stack, is kept in its slot
+                    self.store(&mut stack, Some(var), &mut ops, position);
+                    written[var] = true;
+                    // The operation, which has pushed the value, writes the variable instead
+                    let slot = Operand::Register(self.slot(stack.len()));
+                    if let Some((Pending::Op(last), _)) = ops.last_mut() {
+                        let dst = match last {
+                            Op::Move { dst, .. }
+                            | Op::Read { dst, .. }
+                            | Op::Arithmetic { dst, .. }
+                            | Op::Carry { flag: dst, .. }
+                            | Op::Compare { dst, .. }
+                            | Op::Logical { dst, .. } => Some(dst),
+                            _ => None,
+                        };
+                        if let Some(dst) = dst.filter(|dst| Operand::Register(**dst) == slot) {
+                            if src == slot {
+                                *dst = var;
+                                continue;
+                            }
+                        }
+                    }
+                    ops.push((Pending::Op(Op::Move { dst: var, src }), position));
+                }
+                (Instruction::RetVal, ..) => ops.push(op(Op::Return(operands[0]))),
+                (Instruction::Log, ..) => ops.push(op(Op::Log(operands[0]))),
+                (Instruction::SelfId, ..) => stack.push(Operand::Const(self.id)),
+                (Instruction::ParentId, ..) => match self.parent {
+                    Some(parent) => stack.push(Operand::Const(parent)),
+                    None => {
+                        ops.push(op(Op::Fail("Thread doesn't have a parent")));
+                        return Ok((ops, successors));
+                    }
+                },
+                (Instruction::Unk, ..) => {
+                    ops.push(op(Op::Fail("Unknown instruction")));
+                    return Ok((ops, successors));
+                }
+                (Instruction::Jump, ..) => {
+                    let target = self.target(operands[0], instruction)?;
+                    self.store(&mut stack, None, &mut ops, position);
+                    ops.push((Pending::Jump(target), position));
+                    if let Target::Block(block) = target {
+                        successors.push((block, stack.len()));
+                    }
+                }
+                (_, _, Some(comparison)) => {
+                    let (lhs, rhs, target) = match *operands.as_slice() {
+                        [lhs, rhs, target] => (lhs, rhs, target),
+                        [condition, target] => (condition, Operand::Const(0), target),
+                        _ => unreachable!(),
+                    };
+                    let target = self.target(target, instruction)?;
+                    self.store(&mut stack, None, &mut ops, position);
+                    ops.push((
+                        Pending::Branch {
+                            comparison,
+                            lhs,
+                            rhs,
+                            target,
+                        },
+                        position,
+                    ));
+                    if let Target::Block(block) = target {
+                        successors.push((block, stack.len()));
+                    }
+                    match self.cfg.block_of(position + 1) {
+                        Some(next) => successors.push((next, stack.len())),
+                        None => ops.push((Pending::Op(Op::Missing(len as Data)), position)),
+                    }
+                }
+                // They are rejected before
+                _ => unreachable!(),
+            }
+        }
+        let last = block.end - 1;
+        let instruction = self.instructions[last].instruction();
+        if !instruction.is_jump() && *instruction != Instruction::RetVal {
+            self.store(&mut stack, None, &mut ops, last);
+            match self.cfg.block_of(block.end) {
+                Some(next) => successors.push((next, stack.len())),
+                None => ops.push((Pending::Op(Op::Missing(len as Data)), last)),
+            }
+        }
+        Ok((ops, successors))
+    }
+}
+
+fn error(instruction: &IndexedInstruction, message: String) -> Error {
+    Error::Line {
+        file: instruction.file().cloned(),
+        line: instruction.index(),
+        source: Box::new(message.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        fuzz::Generator, ByteCode, Data, Error, IndexedInstruction, Instruction, Limits, Overflow,
+        VmBuilder,
+    };
+
+    // Compares results and logs of both machines, `None`, if the program doesn't finish in time
+    fn compare(bytecode: ByteCode, vars: &[(&str, Data)]) -> Option<Result<Data, Error>> {
+        let registers = bytecode.to_registers().unwrap();
+        let mut vm = VmBuilder::new(bytecode).limits(Limits {
+            gas: Some(10_000),
+            ..Default::default()
+        });
+        for (name, value) in vars {
+            vm = vm.var(*name, *value);
+        }
+        let expected = match vm.run() {
+            Err(e) if e.to_string().starts_with("Limit of steps") => return None,
+            result => result,
+        };
+        let mut logs = Vec::new();
+        let result = registers.execute(vars, &mut |data| logs.push(data));
+        match &expected {
+            Ok(outcome) => {
+                let expected_logs: Vec<_> = outcome.logs.iter().map(|(_, data)| *data).collect();
+                assert_eq!(logs, expected_logs, "{}", registers);
+                assert_eq!(result, Ok(outcome.ret), "{}", registers);
+            }
+            Err(e) => assert_eq!(result.as_ref(), Err(e), "{}", registers),
+        }
+        Some(result)
+    }
+
+    #[test]
+    fn assignment() {
+        let bytecode = ByteCode::from_bytecode_text(
+            "LOAD_VAL 1\nWRITE_VAR x\nREAD_VAR x\nLOAD_VAL 1\nADD\nWRITE_VAR x\nREAD_VAR x\n\
+             RETURN_VALUE",
+        )
+        .unwrap();
+        let registers = bytecode.to_registers().unwrap();
+        assert_eq!(
+            registers.to_string(),
+            "// r0: x\n     0  r0 = 1\n     1  r0 = r0 + 1\n     2  return r0\n"
+        );
+        assert_eq!(registers.run(&[]), Ok(2));
+    }
+
+    #[test]
+    fn programs() {
+        let pow = r#"
+LOAD_VAL 1
+WRITE_VAR result
+READ_VAR exponent
+LOAD_VAL 0
+LOAD_VAL 8
+JUMP_GREATER_THAN
+READ_VAR result
+RETURN_VALUE
+READ_VAR result
+READ_VAR base
+MULTIPLY
+WRITE_VAR result
+READ_VAR exponent
+LOAD_VAL 1
+SUB
+WRITE_VAR exponent
+LOAD_VAL 2
+JUMP
+"#;
+        // The sum is kept on the stack over the loop
+        let sum = r#"
+LOAD_VAL 0
+READ_VAR n
+LOAD_VAL 0
+LOAD_VAL 15
+JUMP_EQUAL
+READ_VAR n
+LOG
+READ_VAR n
+ADD
+READ_VAR n
+LOAD_VAL 1
+SUB
+WRITE_VAR n
+LOAD_VAL 1
+JUMP
+RETURN_VALUE
+"#;
+        let run = |program, vars: &[(&str, Data)]| {
+            compare(ByteCode::from_bytecode_text(program).unwrap(), vars).unwrap()
+        };
+        assert_eq!(run(pow, &[("base", 3), ("exponent", 4)]), Ok(81));
+        assert_eq!(
+            run(pow, &[("base", 2), ("exponent", 200)])
+                .unwrap_err()
+                .to_string(),
+            "Line: 12, error: Multiplication overflow occurred \
+             (170141183460469231731687303715884105728 * 2)"
+        );
+        assert_eq!(
+            run(pow, &[("base", 2)]).unwrap_err().to_string(),
+            "Line: 4, error: Variable `exponent` doesn't exist"
+        );
+        assert_eq!(run(sum, &[("n", 4)]), Ok(10));
+        // return (n <= 1 || done ? 10 : 20) + !n
+        let conditions = "READ_VAR n\nLOAD_VAL 1\nLE\nREAD_VAR done\nLOGICAL_OR\nLOAD_VAL 10\n\
+                          JUMP_IF\nLOAD_VAL 20\nLOAD_VAL 11\nJUMP\nLOAD_VAL 10\nREAD_VAR n\n\
+                          LOGICAL_NOT\nADD\nRETURN_VALUE";
+        assert_eq!(run(conditions, &[("n", 0), ("done", 0)]), Ok(11));
+        assert_eq!(run(conditions, &[("n", 5), ("done", 2)]), Ok(10));
+        assert_eq!(run(conditions, &[("n", 5), ("done", 0)]), Ok(20));
+        let registers = ByteCode::from_bytecode_text(conditions)
+            .unwrap()
+            .to_registers()
+            .unwrap();
+        assert_eq!(
+            registers.to_string().lines().nth(6),
+            Some("     4  if r2 != 0 jump 7")
+        );
+        for program in [
+            "LOAD_VAL 1\nADD",
+            "LOAD_VAL 1\nLOAD_VAL 2\nSUB_SAT\nLOAD_VAL 2\nMULTIPLY_CARRY\nADD\nRETURN_VALUE",
+            "LOAD_VAL 1\nLOAD_VAL 0\nSUB\nRETURN_VALUE",
+            "LOAD_VAL 1\nLOAD_VAL 2\nLOAD_VAL 9\nJUMP_LESS_THAN",
+            "LOAD_VAL 1\nLOAD_VAL 2\nGE\nLOGICAL_NOT\nLOAD_VAL 3\nLOGICAL_AND\nRETURN_VALUE",
+            "LOAD_VAL 0\nLOAD_VAL 5\nJUMP_IF_NOT\nLOAD_VAL 1\nRETURN_VALUE\nLOAD_VAL 2\nRETURN_VALUE",
+            "LOGICAL_NOT",
+            "SELF_ID\nPARENT_ID",
+            "LOAD_VAL 1\nWRITE_VAR x",
+            "",
+        ] {
+            let _ = run(program, &[]);
+        }
+        let mut bytecode = ByteCode::from_bytecode_text(pow).unwrap();
+        bytecode.set_overflow(Overflow::Wrapping);
+        assert!(compare(bytecode, &[("base", 3), ("exponent", 100)])
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn generated_programs() {
+        let mut compared = 0;
+        for seed in 0..2000 {
+            let program = Generator::new(seed).program();
+            let instructions = program
+                .into_iter()
+                .enumerate()
+                .map(|(index, instruction)| IndexedInstruction::new(index, instruction))
+                .collect();
+            let bytecode = ByteCode::new(instructions);
+            if bytecode.to_registers().is_ok() && compare(bytecode, &[]).is_some() {
+                compared += 1;
+            }
+        }
+        assert!(compared > 100, "{}", compared);
+    }
+
+    #[test]
+    fn untranslatable() {
+        for (program, error) in [
+            (
+                "LOAD_VAL 0\nLOAD_VAL 3\nLOAD_VAL 0\nLOAD_VAL 3\nSPAWN",
+                "Line: 5, error: SPAWN can't be translated, only single-threaded programs without \
+                 host functions are supported",
+            ),
+            (
+                "READ_VAR x\nJUMP",
+                "Line: 2, error: Target of the jump isn't constant",
+            ),
+            (
+                "LOAD_VAL 1\nLOAD_VAL 0\nLOAD_VAL 0\nLOAD_VAL 0\nJUMP_EQUAL",
+                "Line: 1, error: Depth of the stack is 0 or 1 on different paths, it has to be \
+                 the same",
+            ),
+        ] {
+            let bytecode = ByteCode::from_bytecode_text(program).unwrap();
+            assert_eq!(bytecode.to_registers().unwrap_err().to_string(), error);
+        }
+        assert_eq!(
+            ByteCode::new(vec![IndexedInstruction::new(0, Instruction::Unk)])
+                .to_registers()
+                .unwrap()
+                .run(&[]),
+            Err(Error::Line {
+                file: None,
+                line: 0,
+                source: Box::new("Unknown instruction".into())
+            })
+        );
+    }
+}
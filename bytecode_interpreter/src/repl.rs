@@ -0,0 +1,401 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    instructions::IteratorWrapper, ByteCode, Data, Id, IndexedInstruction, Instruction, Memory,
+    Observer, Stack,
+};
+
+// Count of instructions, which one input may execute, so an endless loop doesn't hang the REPL
+const MAX_STEPS: u64 = 1_000_000;
+
+const HELP: &str = "\
+Instructions are executed right after they are entered, but not while the execution is stopped
+at a breakpoint. Meta-commands:
+    :stack              Show the stack, the top is the last
+    :memory             Show variables
+    :program            Show instructions with their positions
+    :break <position>   Stop before the instruction at the position
+    :delete <position>  Remove the breakpoint
+    :continue           Go on after a breakpoint
+    :undo               Revert the last input
+    :load <file>        Replace the program with the file and execute it
+    :reset              Start from scratch
+    :help               Show this help
+    :quit               Exit";
+
+// State before an input, so the input can be reverted
+struct Checkpoint {
+    instructions: Arc<[IndexedInstruction]>,
+    stack: Stack,
+    memory: Memory,
+    position: Data,
+    ret: Option<Data>,
+    stopped: Option<usize>,
+}
+
+#[derive(Default)]
+struct Logs(Mutex<Vec<Data>>);
+
+impl Observer for Logs {
+    fn log(&self, _thread: Id, data: Data) {
+        self.0.lock().unwrap().push(data);
+    }
+}
+
+// Executes instructions of the root thread one input after another. Threads can't be spawned,
+// because nobody would execute them.
+pub struct Repl {
+    bytecode: ByteCode,
+    logs: Arc<Logs>,
+    history: Vec<Checkpoint>,
+    breakpoints: BTreeSet<usize>,
+    // The position of the breakpoint, where the execution has stopped
+    stopped: Option<usize>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let logs = Arc::new(Logs::default());
+        let mut bytecode = ByteCode::default();
+        bytecode.set_observer(logs.clone());
+        Self {
+            bytecode,
+            logs,
+            history: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            stopped: None,
+        }
+    }
+
+    pub fn bytecode(&self) -> &ByteCode {
+        &self.bytecode
+    }
+
+    // Returns the text, which should be shown after the input
+    pub fn eval(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            return Ok(String::new());
+        }
+        let Some(command) = line.strip_prefix(':') else {
+            let instruction = Instruction::try_from(IteratorWrapper(line.split_ascii_whitespace()))
+                .map_err(|e| e.to_string())?;
+            if self.bytecode.ret.is_some() {
+                return Err("The program has returned, use :undo or :reset".into());
+            }
+            // The instruction would be appended after the rest of the program, so it wouldn't be
+            // executed at the breakpoint
+            if let Some(position) = self.stopped {
+                return Err(format!(
+                    "The execution is stopped at a breakpoint at {}, use :continue",
+                    position
+                ));
+            }
+            self.save();
+            let mut instructions = self.bytecode.instructions.to_vec();
+            instructions.push(IndexedInstruction::new(instructions.len(), instruction));
+            self.bytecode.instructions = instructions.into();
+            return self.execute();
+        };
+        let (command, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let argument = argument.trim();
+        match command {
+            "stack" => Ok(self.stack()),
+            "memory" => {
+                let mut memory: Vec<_> = self.bytecode.memory.iter().collect();
+                memory.sort_unstable();
+                let mut output = String::new();
+                for (ident, data) in memory {
+                    let _ = writeln!(output, "{} = {}", ident, data);
+                }
+                Ok(output)
+            }
+            "program" => {
+                let mut output = String::new();
+                for (position, instruction) in self.bytecode.instructions.iter().enumerate() {
+                    let current = if position == self.bytecode.position() {
+                        ">"
+                    } else {
+                        " "
+                    };
+                    let breakpoint = if self.breakpoints.contains(&position) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    let _ = writeln!(
+                        output,
+                        "{}{}{:>4}  {}",
+                        current,
+                        breakpoint,
+                        position,
+                        instruction.instruction()
+                    );
+                }
+                Ok(output)
+            }
+            "break" => {
+                self.breakpoints.insert(parse_position(argument)?);
+                Ok(String::new())
+            }
+            "delete" => {
+                let position = parse_position(argument)?;
+                if !self.breakpoints.remove(&position) {
+                    return Err(format!("Breakpoint at {} doesn't exist", position));
+                }
+                Ok(String::new())
+            }
+            "continue" => {
+                if self.stopped.is_none() {
+                    return Err("The execution isn't stopped at a breakpoint".into());
+                }
+                self.save();
+                self.execute()
+            }
+            "undo" => {
+                let checkpoint = self.history.pop().ok_or("Nothing to undo")?;
+                self.restore(checkpoint);
+                Ok(self.stack())
+            }
+            "load" => {
+                let loaded = ByteCode::from_bytecode_file(argument).map_err(|e| e.join("\n"))?;
+                self.save();
+                self.restore(Checkpoint {
+                    instructions: loaded.instructions,
+                    stack: Stack::new(),
+                    memory: Memory::new(),
+                    position: 0,
+                    ret: None,
+                    stopped: None,
+                });
+                self.execute()
+            }
+            "reset" => {
+                *self = Self::new();
+                Ok(String::new())
+            }
+            "help" => Ok(format!("{}\n", HELP)),
+            _ => Err(format!("Unknown command `:{}`, see :help", command)),
+        }
+    }
+
+    fn stack(&self) -> String {
+        let stack: Vec<_> = self.bytecode.stack.iter().map(Data::to_string).collect();
+        format!("[{}]\n", stack.join(", "))
+    }
+
+    fn save(&mut self) {
+        self.history.push(Checkpoint {
+            instructions: self.bytecode.instructions.clone(),
+            stack: self.bytecode.stack.clone(),
+            memory: self.bytecode.memory.clone(),
+            position: self.bytecode.position,
+            ret: self.bytecode.ret,
+            stopped: self.stopped,
+        });
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        self.bytecode.instructions = checkpoint.instructions;
+        self.bytecode.stack = checkpoint.stack;
+        self.bytecode.memory = checkpoint.memory;
+        self.bytecode.position = checkpoint.position;
+        self.bytecode.ret = checkpoint.ret;
+        self.stopped = checkpoint.stopped;
+    }
+
+    // Executes instructions until the end of the program, a breakpoint or the return. A failed
+    // input is reverted.
+    fn execute(&mut self) -> Result<String, String> {
+        let result = self.run();
+        let mut output = String::new();
+        for data in self.logs.0.lock().unwrap().drain(..) {
+            let _ = writeln!(output, "LOG: {}", data);
+        }
+        match result {
+            Ok(()) => {
+                if let Some(position) = self.stopped {
+                    let _ = writeln!(output, "Breakpoint at {}", position);
+                }
+                match self.bytecode.ret {
+                    Some(ret) => {
+                        let _ = writeln!(output, "Returned {}", ret);
+                    }
+                    None => output.push_str(&self.stack()),
+                }
+                Ok(output)
+            }
+            Err(e) => {
+                let checkpoint = self.history.pop().expect("Input isn't saved");
+                self.restore(checkpoint);
+                Err(format!("{}{}", output, e))
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        // The breakpoint, where the execution has stopped, is passed only once
+        let resumed = self.stopped.take();
+        for i in 0..MAX_STEPS {
+            let position = self.bytecode.position();
+            if self.bytecode.ret.is_some() || position == self.bytecode.instructions.len() {
+                return Ok(());
+            }
+            if self.breakpoints.contains(&position) && (i > 0 || resumed != Some(position)) {
+                self.stopped = Some(position);
+                return Ok(());
+            }
+            if let Some(instruction) = self.bytecode.next_instruction() {
+                if instruction.is_scheduling_point() && *instruction != Instruction::RetVal {
+                    return Err(format!(
+                        "Instruction `{}` isn't supported by the REPL",
+                        instruction
+                    ));
+                }
+            }
+            self.bytecode.step().map_err(|e| e.to_string())?;
+        }
+        Err(format!(
+            "Limit of steps is exceeded (maximum is {})",
+            MAX_STEPS
+        ))
+    }
+}
+
+fn parse_position(argument: &str) -> Result<usize, String> {
+    argument
+        .parse()
+        .map_err(|_| format!("Invalid position `{}`", argument))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::Repl;
+
+    fn eval(repl: &mut Repl, lines: &[&str]) -> Vec<Result<String, String>> {
+        lines.iter().map(|line| repl.eval(line)).collect()
+    }
+
+    #[test]
+    fn instructions_and_state() {
+        let mut repl = Repl::new();
+        let outputs = eval(
+            &mut repl,
+            &[
+                "LOAD_VAL 2",
+                "WRITE_VAR x",
+                "READ_VAR x",
+                "LOAD_VAL 3",
+                ":stack",
+                "MULTIPLY",
+                "LOG",
+                ":memory",
+            ],
+        );
+        assert_eq!(
+            outputs,
+            vec![
+                Ok("[2]\n".into()),
+                Ok("[]\n".into()),
+                Ok("[2]\n".into()),
+                Ok("[2, 3]\n".into()),
+                Ok("[2, 3]\n".into()),
+                Ok("[6]\n".into()),
+                Ok("LOG: 6\n[]\n".into()),
+                Ok("x = 2\n".into()),
+            ]
+        );
+        assert_eq!(repl.bytecode().instructions().len(), 6);
+        assert_eq!(repl.eval("LOAD_VAL 7"), Ok("[7]\n".into()));
+        assert_eq!(repl.eval("RETURN_VALUE"), Ok("Returned 7\n".into()));
+        assert!(repl.eval("LOAD_VAL 1").is_err());
+        assert_eq!(repl.eval(":reset"), Ok("".into()));
+        assert_eq!(repl.eval(":memory"), Ok("".into()));
+    }
+
+    #[test]
+    fn errors_and_undo() {
+        let mut repl = Repl::new();
+        repl.eval("LOAD_VAL 1").unwrap();
+        assert_eq!(
+            repl.eval("ADD"),
+            Err("Line: 2, error: Stack is empty".into())
+        );
+        // The failed instruction isn't kept
+        assert_eq!(repl.eval(":stack"), Ok("[1]\n".into()));
+        assert_eq!(repl.bytecode().instructions().len(), 1);
+        assert_eq!(
+            repl.eval("PUSH 1"),
+            Err("Unknown instruction `PUSH`".into())
+        );
+        assert_eq!(
+            repl.eval("ad"),
+            Err("Unknown instruction `ad`, did you mean `ADD`?".into())
+        );
+        assert_eq!(
+            repl.eval("SPAWN"),
+            Err("Instruction `SPAWN` isn't supported by the REPL".into())
+        );
+
+        repl.eval("WRITE_VAR x").unwrap();
+        assert_eq!(repl.eval(":undo"), Ok("[1]\n".into()));
+        assert_eq!(repl.eval(":memory"), Ok("".into()));
+        assert_eq!(repl.eval(":undo"), Ok("[]\n".into()));
+        assert_eq!(repl.eval(":undo"), Err("Nothing to undo".into()));
+        assert!(repl.eval(":jump").is_err());
+    }
+
+    #[test]
+    fn load_and_breakpoints() {
+        let program = r#"
+// count down from 3
+LOAD_VAL 3
+WRITE_VAR x
+READ_VAR x
+LOAD_VAL 1
+SUB
+WRITE_VAR x
+READ_VAR x
+LOAD_VAL 0
+LOAD_VAL 2
+JUMP_GREATER_THAN
+READ_VAR x
+RETURN_VALUE
+"#;
+        let path = env::temp_dir().join(format!("bytecode-repl-{}.bc", std::process::id()));
+        fs::write(&path, program).unwrap();
+        let load = format!(":load {}", path.display());
+
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval(&load), Ok("Returned 0\n".into()));
+
+        repl.eval(":break 6").unwrap();
+        assert_eq!(repl.eval(&load), Ok("Breakpoint at 6\n[]\n".into()));
+        assert_eq!(repl.eval(":memory"), Ok("x = 2\n".into()));
+        assert_eq!(
+            repl.eval("LOAD_VAL 1"),
+            Err("The execution is stopped at a breakpoint at 6, use :continue".into())
+        );
+        assert_eq!(repl.bytecode().instructions().len(), 12);
+        assert_eq!(repl.eval(":continue"), Ok("Breakpoint at 6\n[]\n".into()));
+        assert_eq!(repl.eval(":memory"), Ok("x = 1\n".into()));
+        assert_eq!(repl.eval(":undo"), Ok("[]\n".into()));
+        assert_eq!(repl.eval(":memory"), Ok("x = 2\n".into()));
+        repl.eval(":delete 6").unwrap();
+        assert_eq!(repl.eval(":continue"), Ok("Returned 0\n".into()));
+        assert!(repl.eval(":continue").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
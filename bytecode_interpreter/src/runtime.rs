@@ -0,0 +1,657 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use crate::{ByteCode, Data, Error, Event, FailurePolicy, Id, Resource};
+
+// Count of instructions, which a worker executes before switching to another bytecode
+const QUANTUM: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThreadState {
+    Running,
+    Finished(Data),
+    Failed(Error),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadInfo {
+    pub id: Id,
+    pub parent: Option<Id>,
+    pub state: ThreadState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Wait {
+    // A message from the thread
+    Recv(Id),
+    // Receiving of the sent message by the thread
+    Send(Id),
+    // Finishing of all children
+    Children,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    threads: BTreeMap<Id, ThreadInfo>,
+    live: usize,
+    run_queue: VecDeque<ByteCode>,
+    // Count of bytecodes, which are executed by workers right now
+    running: usize,
+    waiting: HashMap<Id, ByteCode>,
+    // Threads, which wait for a message from the key thread
+    receivers: HashMap<Id, Vec<Id>>,
+    // Messages, which are sent, but not received yet, by (sender, receiver). Channels are
+    // rendezvous ones, so the sender waits, until the receiver takes the message.
+    mailboxes: HashMap<(Id, Id), Data>,
+    // Messages, which are received, but their senders haven't gone on yet
+    delivered: HashSet<(Id, Id)>,
+    // Count of messages in all mailboxes
+    messages: usize,
+    // Count of unfinished children of running threads
+    children: HashMap<Id, usize>,
+    // The first failed child
+    failed_children: HashMap<Id, Id>,
+    root: Option<ByteCode>,
+    failure: Option<(Id, Error)>,
+    // Interactions between threads in the order of their occurrence, if they are recorded
+    events: Option<Vec<Event>>,
+    // Count of instructions, which can be executed before the tree is paused
+    budget: Option<u64>,
+    // Count of instructions, which finished threads have executed
+    steps: u64,
+}
+
+impl State {
+    fn register(&mut self, id: Id, parent: Option<Id>) {
+        let info = ThreadInfo {
+            id,
+            parent,
+            state: ThreadState::Running,
+        };
+        if !matches!(
+            self.threads.insert(id, info),
+            Some(ThreadInfo {
+                state: ThreadState::Running,
+                ..
+            })
+        ) {
+            self.live += 1;
+        }
+        self.children.entry(id).or_default();
+        if let Some(parent) = parent {
+            *self.children.entry(parent).or_default() += 1;
+        }
+    }
+
+    fn is_running(&self, id: Id) -> bool {
+        matches!(
+            self.threads.get(&id),
+            Some(ThreadInfo {
+                state: ThreadState::Running,
+                ..
+            })
+        )
+    }
+
+    fn can_receive(&self, from: Id, to: Id) -> bool {
+        self.mailboxes.contains_key(&(from, to)) || !self.is_running(from)
+    }
+
+    fn wake(&mut self, id: Id, wait: Wait) -> bool {
+        if self.waiting.get(&id).and_then(|bytecode| bytecode.waiting) != Some(wait) {
+            return false;
+        }
+        let mut bytecode = self.waiting.remove(&id).unwrap();
+        bytecode.waiting = None;
+        self.run_queue.push_back(bytecode);
+        true
+    }
+
+    fn record(&mut self, event: Event) {
+        if let Some(events) = &mut self.events {
+            events.push(event);
+        }
+    }
+
+    // The root is kept by the runtime, until it is returned from the execution
+    fn take_root(&mut self) -> ByteCode {
+        if let Some(root) = self.root.take() {
+            return root;
+        }
+        let index = self.run_queue.iter().position(|b| b.parent.is_none());
+        if let Some(root) = index.and_then(|index| self.run_queue.remove(index)) {
+            return root;
+        }
+        let root = self.waiting.values().find(|b| b.parent.is_none());
+        let id = root.expect("Root is lost").id;
+        self.waiting.remove(&id).unwrap()
+    }
+
+    fn child_failure(&self, id: Id) -> Option<Error> {
+        let child = *self.failed_children.get(&id)?;
+        match &self.threads[&child].state {
+            ThreadState::Failed(e) => Some(Error::Child {
+                id: child,
+                source: Box::new(e.clone()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// State shared between all threads of one tree
+#[derive(Debug, Default)]
+pub(crate) struct Runtime {
+    // The root thread always has the id 0, so the ids of spawned threads start from 1 and are
+    // never reused
+    last_id: AtomicUsize,
+    aborted: AtomicBool,
+    // Bytes of stacks and variables of live threads, which are counted only with the limit
+    heap: AtomicUsize,
+    state: Mutex<State>,
+    wakeup: Condvar,
+}
+
+impl Runtime {
+    pub(crate) fn allocate_id(&self) -> Id {
+        self.last_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+
+    // Replaces bytes of a thread and returns the bytes of the tree
+    pub(crate) fn resize_heap(&self, from: usize, to: usize) -> usize {
+        if to >= from {
+            self.heap.fetch_add(to - from, Ordering::Relaxed) + (to - from)
+        } else {
+            self.heap.fetch_sub(from - to, Ordering::Relaxed) - (from - to)
+        }
+    }
+
+    pub(crate) fn failure(&self) -> Option<(Id, Error)> {
+        self.lock().failure.clone()
+    }
+
+    pub(crate) fn thread(&self, id: Id) -> Option<ThreadInfo> {
+        self.lock().threads.get(&id).cloned()
+    }
+
+    pub(crate) fn steps(&self) -> u64 {
+        self.lock().steps
+    }
+
+    pub(crate) fn last_id(&self) -> Id {
+        self.last_id.load(Ordering::Relaxed)
+    }
+
+    // Calls `f` for every bytecode, which is kept by the paused tree, in the order of ids
+    pub(crate) fn for_each_bytecode(&self, f: &mut dyn FnMut(&ByteCode)) {
+        let state = self.lock();
+        let mut bytecodes: Vec<_> = state
+            .run_queue
+            .iter()
+            .chain(state.waiting.values())
+            .collect();
+        bytecodes.sort_unstable_by_key(|bytecode| bytecode.id);
+        bytecodes.into_iter().for_each(f);
+    }
+
+    // Messages, which are sent, but not received yet, as (sender, receiver, data)
+    pub(crate) fn pending_messages(&self) -> Vec<(Id, Id, Data)> {
+        let state = self.lock();
+        let mut messages: Vec<_> = state
+            .mailboxes
+            .iter()
+            .map(|(&(from, to), &data)| (from, to, data))
+            .collect();
+        messages.sort_unstable();
+        messages
+    }
+
+    // Messages, which are received, but their senders haven't gone on yet, as (sender, receiver)
+    pub(crate) fn delivered_messages(&self) -> Vec<(Id, Id)> {
+        let mut delivered: Vec<_> = self.lock().delivered.iter().copied().collect();
+        delivered.sort_unstable();
+        delivered
+    }
+
+    // Fills the new runtime with the state of a paused tree. The root isn't kept by the runtime,
+    // it is registered again, when the execution is resumed.
+    pub(crate) fn restore(
+        &self,
+        last_id: Id,
+        steps: u64,
+        threads: Vec<ThreadInfo>,
+        bytecodes: Vec<ByteCode>,
+        messages: Vec<(Id, Id, Data)>,
+        delivered: Vec<(Id, Id)>,
+    ) {
+        self.last_id.store(last_id, Ordering::Relaxed);
+        let mut state = self.lock();
+        state.steps = steps;
+        for info in threads {
+            match &info.state {
+                ThreadState::Running => {
+                    state.live += 1;
+                    state.children.entry(info.id).or_default();
+                    if let Some(parent) = info.parent {
+                        *state.children.entry(parent).or_default() += 1;
+                    }
+                }
+                ThreadState::Failed(_) => {
+                    if let Some(parent) = info.parent {
+                        state.failed_children.entry(parent).or_insert(info.id);
+                    }
+                }
+                ThreadState::Finished(_) => {}
+            }
+            state.threads.insert(info.id, info);
+        }
+        // Failures of children of finished threads aren't delivered to anybody
+        let State {
+            failed_children,
+            children,
+            ..
+        } = &mut *state;
+        failed_children.retain(|parent, _| children.contains_key(parent));
+        state.messages += messages.len();
+        state.mailboxes.extend(
+            messages
+                .into_iter()
+                .map(|(from, to, data)| ((from, to), data)),
+        );
+        state.delivered.extend(delivered);
+        // Waiting bytecodes find out again, what they are waiting for
+        state.run_queue.extend(bytecodes);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    // Executes the bytecode and all its descendants on the pool of workers and returns the
+    // bytecode, when all of them have finished or `max_steps` instructions have been executed.
+    // In the latter case the rest threads stay in the runtime, so the next run resumes them.
+    pub(crate) fn run(&self, root: ByteCode, workers: usize, max_steps: Option<u64>) -> ByteCode {
+        self.lock().budget = max_steps;
+        self.start(root);
+        thread::scope(|scope| {
+            for i in 1..workers {
+                // The current thread is a worker too, so we just go on with fewer workers, if
+                // the system doesn't give us more threads
+                let _ = thread::Builder::new()
+                    .name(format!("worker {}", i))
+                    .spawn_scoped(scope, || self.work());
+            }
+            self.work();
+        });
+        let mut state = self.lock();
+        state.budget = None;
+        state.take_root()
+    }
+
+    // Executes the bytecode and all its descendants on the current thread. At every scheduling
+    // point `choose` picks the next thread from the runnable ones. Returns the bytecode and the
+    // reason, if the execution has been interrupted before all threads have finished.
+    pub(crate) fn run_deterministic(
+        &self,
+        root: ByteCode,
+        choose: &mut dyn FnMut(&[Id]) -> Result<Id, Error>,
+        max_steps: Option<u64>,
+    ) -> (ByteCode, Result<(), Error>) {
+        self.start(root);
+        let mut steps = 0;
+        let interrupted = loop {
+            let mut state = self.lock();
+            if state.run_queue.is_empty() {
+                self.fail_waiting(&mut state, Error::Deadlock);
+                break Ok(());
+            }
+            let quantum = match max_steps {
+                Some(max) if steps >= max => {
+                    break Err(format!("Limit of steps is exceeded (maximum is {})", max).into())
+                }
+                Some(max) => (max - steps) as usize,
+                None => usize::MAX,
+            };
+            let mut runnable: Vec<_> = state.run_queue.iter().map(|b| b.id).collect();
+            runnable.sort_unstable();
+            drop(state);
+            let id = match choose(&runnable) {
+                Ok(id) => id,
+                Err(e) => break Err(e),
+            };
+            let mut state = self.lock();
+            let index = state.run_queue.iter().position(|b| b.id == id);
+            let Some(mut bytecode) = index.and_then(|index| state.run_queue.remove(index)) else {
+                break Err("Chosen thread isn't runnable".into());
+            };
+            drop(state);
+            let before = bytecode.steps;
+            let result = bytecode.run_slice(quantum, false);
+            steps += bytecode.steps - before;
+            self.schedule(&mut self.lock(), bytecode, result);
+        };
+        (self.lock().take_root(), interrupted)
+    }
+
+    // Executes the next runnable bytecode on the current thread for at most `quantum`
+    // instructions. Returns the count of executed instructions and whether the bytecode has to
+    // wait, or `None`, if all threads have finished or wait for each other.
+    pub(crate) fn run_next(&self, quantum: usize) -> Option<(u64, bool)> {
+        let mut state = self.lock();
+        let Some(mut bytecode) = state.run_queue.pop_front() else {
+            self.fail_waiting(&mut state, Error::Deadlock);
+            return None;
+        };
+        drop(state);
+        let before = bytecode.steps;
+        let result = bytecode.run_slice(quantum, true);
+        let slice = (bytecode.steps - before, bytecode.waiting.is_some());
+        self.schedule(&mut self.lock(), bytecode, result);
+        Some(slice)
+    }
+
+    // The root of a paused or finished tree
+    pub(crate) fn take_root(&self) -> ByteCode {
+        self.lock().take_root()
+    }
+
+    pub(crate) fn recorded_events(&self) -> Option<Vec<Event>> {
+        self.lock().events.clone()
+    }
+
+    pub(crate) fn events(&self, start: usize) -> Vec<Event> {
+        let state = self.lock();
+        let events = state.events.as_deref().unwrap_or_default();
+        events.get(start..).unwrap_or_default().to_vec()
+    }
+
+    pub(crate) fn start(&self, root: ByteCode) {
+        let mut state = self.lock();
+        if root.config.record && state.events.is_none() {
+            state.events = Some(Vec::new());
+        }
+        state.register(root.id, root.parent);
+        state.run_queue.push_back(root);
+    }
+
+    pub(crate) fn spawn(
+        &self,
+        children: [ByteCode; 2],
+        max_threads: Option<usize>,
+    ) -> Result<(), Error> {
+        let mut state = self.lock();
+        if let Some(max) = max_threads {
+            if state.live + children.len() > max {
+                return Err(Error::Exceeded {
+                    resource: Resource::LiveThreads,
+                    maximum: max,
+                });
+            }
+        }
+        let [a, b] = &children;
+        state.record(Event::Spawn {
+            parent: a.parent.unwrap_or_default(),
+            children: [a.id, b.id],
+        });
+        for child in children {
+            state.register(child.id, child.parent);
+            state.run_queue.push_back(child);
+        }
+        self.wakeup.notify_all();
+        Ok(())
+    }
+
+    // Returns true, when the receiver has taken the message, otherwise the sender has to wait and
+    // send again, when it's woken
+    pub(crate) fn send(
+        &self,
+        from: Id,
+        to: Id,
+        data: Data,
+        max_messages: Option<usize>,
+    ) -> Result<bool, Error> {
+        let mut state = self.lock();
+        if state.delivered.remove(&(from, to)) {
+            return Ok(true);
+        }
+        if state.mailboxes.contains_key(&(from, to)) {
+            return Ok(false);
+        }
+        match state.threads.get(&to) {
+            None => return Err(format!("Sender {} doesn't exist", to).into()),
+            Some(ThreadInfo {
+                state: ThreadState::Running,
+                ..
+            }) => {}
+            Some(_) => return Err(format!("Thread {} has already finished", to).into()),
+        }
+        match max_messages {
+            Some(max) if state.messages >= max => {
+                return Err(Error::Exceeded {
+                    resource: Resource::MessagesInFlight,
+                    maximum: max,
+                })
+            }
+            _ => state.messages += 1,
+        }
+        state.mailboxes.insert((from, to), data);
+        state.record(Event::Send { from, to, data });
+        if state.wake(to, Wait::Recv(from)) {
+            self.wakeup.notify_one();
+        }
+        Ok(false)
+    }
+
+    // Returns `None`, if the sender is alive, but hasn't sent anything yet
+    pub(crate) fn try_recv(&self, from: Id, to: Id) -> Result<Option<Data>, Error> {
+        let mut state = self.lock();
+        if let Some(data) = state.mailboxes.remove(&(from, to)) {
+            state.messages -= 1;
+            state.delivered.insert((from, to));
+            state.record(Event::Recv { from, to, data });
+            if state.wake(from, Wait::Send(to)) {
+                self.wakeup.notify_one();
+            }
+            return Ok(Some(data));
+        }
+        match state.threads.get(&from).map(|info| &info.state) {
+            None => Err(format!("Receiver {} doesn't exist", from).into()),
+            Some(ThreadState::Running) => Ok(None),
+            Some(ThreadState::Finished(_)) => {
+                Err(format!("Thread {} has finished without sending", from).into())
+            }
+            Some(ThreadState::Failed(e)) => Err(Error::Child {
+                id: from,
+                source: Box::new(e.clone()),
+            }),
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.lock();
+        loop {
+            // Threads, which have returned, are finished without steps, so a sender, which
+            // executes the last step, doesn't leave its parent unfinished
+            if state.budget == Some(0) && state.run_queue.front().is_none_or(|b| b.ret.is_none()) {
+                // The tree is paused, the rest workers stop after their slices
+                self.wakeup.notify_all();
+                return;
+            }
+            let Some(mut bytecode) = state.run_queue.pop_front() else {
+                if state.running > 0 {
+                    state = self.wakeup.wait(state).unwrap();
+                    continue;
+                }
+                // Nothing is executed and nothing can be executed, so the rest threads would
+                // wait forever
+                self.fail_waiting(&mut state, Error::Deadlock);
+                self.wakeup.notify_all();
+                return;
+            };
+            let quantum = state
+                .budget
+                .map_or(QUANTUM as u64, |left| left.min(QUANTUM as u64));
+            if let Some(left) = &mut state.budget {
+                *left -= quantum;
+            }
+            state.running += 1;
+            drop(state);
+            let before = bytecode.steps;
+            let result = bytecode.run_slice(quantum as usize, true);
+            state = self.lock();
+            state.running -= 1;
+            if let Some(left) = &mut state.budget {
+                *left += quantum - (bytecode.steps - before);
+            }
+            self.schedule(&mut state, bytecode, result);
+            self.wakeup.notify_all();
+        }
+    }
+
+    fn schedule(&self, state: &mut State, mut bytecode: ByteCode, result: Result<(), Error>) {
+        if let Err(e) = result {
+            return self.finish(state, bytecode, Err(e));
+        }
+        if bytecode.ret.is_some() {
+            if state.children[&bytecode.id] > 0 {
+                bytecode.waiting = Some(Wait::Children);
+                state.waiting.insert(bytecode.id, bytecode);
+                return;
+            }
+            let result = match state.child_failure(bytecode.id) {
+                Some(e) => Err(e),
+                None => Ok(()),
+            };
+            return self.finish(state, bytecode, result);
+        }
+        if self.is_aborted() {
+            return self.finish(state, bytecode, Err(Error::Aborted));
+        }
+        // A bytecode, which is going to wait, is parked right now, so it isn't chosen in vain
+        match bytecode.waiting.or_else(|| bytecode.next_wait()) {
+            Some(Wait::Recv(from)) if !state.can_receive(from, bytecode.id) => {
+                bytecode.waiting = Some(Wait::Recv(from));
+                state.receivers.entry(from).or_default().push(bytecode.id);
+                state.waiting.insert(bytecode.id, bytecode);
+            }
+            Some(Wait::Send(to)) if state.mailboxes.contains_key(&(bytecode.id, to)) => {
+                state.waiting.insert(bytecode.id, bytecode);
+            }
+            _ => state.run_queue.push_back(bytecode),
+        }
+    }
+
+    fn finish(&self, state: &mut State, bytecode: ByteCode, result: Result<(), Error>) {
+        let id = bytecode.id;
+        let mut aborting = false;
+        if let Err(e) = &result {
+            if bytecode.config.failure_policy == FailurePolicy::AbortTree
+                && !e.is_aborted()
+                && state.failure.is_none()
+            {
+                state.failure = Some((id, e.clone()));
+                self.aborted.store(true, Ordering::SeqCst);
+                aborting = true;
+            }
+        }
+        let failed = result.is_err();
+        state.record(match result {
+            Ok(()) => Event::Return {
+                id,
+                data: bytecode.ret.unwrap_or_default(),
+            },
+            Err(_) => Event::Fail { id },
+        });
+        state.threads.get_mut(&id).unwrap().state = match result {
+            Ok(()) => ThreadState::Finished(bytecode.ret.unwrap_or_default()),
+            Err(e) => ThreadState::Failed(e),
+        };
+        state.live -= 1;
+        state.steps += bytecode.steps;
+        self.resize_heap(bytecode.usage.total, 0);
+        state.children.remove(&id);
+        state.failed_children.remove(&id);
+        if let Some(parent) = bytecode.parent {
+            // The parent may have already failed
+            if let Some(children) = state.children.get_mut(&parent) {
+                *children -= 1;
+                if *children == 0 {
+                    state.wake(parent, Wait::Children);
+                }
+                if failed {
+                    state.failed_children.entry(parent).or_insert(id);
+                }
+            }
+        } else {
+            state.root = Some(bytecode);
+        }
+        for receiver in state.receivers.remove(&id).unwrap_or_default() {
+            state.wake(receiver, Wait::Recv(id));
+        }
+        // Senders to the thread find out, that it has finished without receiving
+        let senders: Vec<_> = state
+            .mailboxes
+            .keys()
+            .filter(|(_, to)| *to == id)
+            .map(|(from, _)| *from)
+            .collect();
+        for sender in senders {
+            state.mailboxes.remove(&(sender, id));
+            state.messages -= 1;
+            state.wake(sender, Wait::Send(id));
+        }
+        state.delivered.retain(|(from, _)| *from != id);
+        if aborting {
+            self.fail_waiting(state, Error::Aborted);
+        }
+        // Waiting threads don't execute instructions, so they don't notice the interruption
+        // themselves
+        if let ThreadState::Failed(e @ (Error::Cancelled | Error::TimedOut)) =
+            &state.threads[&id].state
+        {
+            let e = e.clone();
+            self.fail_waiting(state, e);
+        }
+    }
+
+    fn fail_waiting(&self, state: &mut State, error: Error) {
+        let mut waiting: Vec<_> = state.waiting.drain().map(|(_, b)| b).collect();
+        waiting.sort_unstable_by_key(|bytecode| bytecode.id);
+        for bytecode in waiting {
+            self.finish(state, bytecode, Err(error.clone()));
+        }
+    }
+}
+
+// Handle for querying threads of a tree, also while it is being interpreted
+#[derive(Debug, Clone)]
+pub struct Registry(pub(crate) Arc<Runtime>);
+
+impl Registry {
+    pub fn threads(&self) -> Vec<ThreadInfo> {
+        self.0.lock().threads.values().cloned().collect()
+    }
+
+    pub fn thread(&self, id: Id) -> Option<ThreadInfo> {
+        self.0.thread(id)
+    }
+
+    pub fn live_threads(&self) -> usize {
+        self.0.lock().live
+    }
+
+    // Count of instructions, which finished threads have executed
+    pub fn steps(&self) -> u64 {
+        self.0.steps()
+    }
+}
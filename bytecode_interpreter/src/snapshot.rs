@@ -0,0 +1,731 @@
+use std::{
+    fmt::{self, Write},
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{
+    instructions::{IndexedInstruction, Instruction, IteratorWrapper},
+    ByteCode, Config, Data, Error, FailurePolicy, Id, Memory, Overflow, Resource, Runtime, Stack,
+    ThreadInfo, ThreadState,
+};
+
+const HEADER: &str = "bytecode-snapshot 1";
+// The text of `Instruction::Unk`, which programs can't contain, but snapshots can
+const UNKNOWN: &str = "UNKNOWN";
+
+// The text format of a snapshot consists of lines, every one starts with its kind:
+//
+// policy propagate | abort-tree
+// overflow checked | wrapping | saturating
+// gas <count>
+// max-threads <count>
+// max-stack <count>
+// max-vars <count>
+// max-heap <bytes>
+// max-spawn-depth <count>
+// max-messages <count>
+// last-id <id>
+// steps <count>
+// file <name>
+// instruction <line> <instruction>
+// thread <id> <parent | -> running | finished <data> | failed <error>
+// message <sender> <receiver> <data>
+// delivered <sender> <receiver>
+// bytecode <id> <parent | -> <position> <steps> <ret | ->
+// stack <data>...
+// var <ident> <data>
+//
+// `file` line sets the file of the following instructions, it's empty for instructions without
+// a file. `stack` and `var` lines belong to the preceding bytecode, the first bytecode is the root.
+// Errors of failed threads are written as:
+//
+// message <text> | line <line> <error> | file-line <file> <line> <error> | child <id> <error> |
+// aborted | deadlock | cancelled | timed-out | exceeded <resource> <maximum>
+//
+// where the resource is named as its limit without `max-`. `%`, line breaks and spaces of files and
+// variables are escaped as `%25`, `%0A`, `%0D` and `%20`. Unknown instructions are written as
+// `UNKNOWN`. Host functions, the cancellation token and the deadline aren't kept, they have to be
+// set again.
+impl ByteCode {
+    // State of the paused tree, which can be restored in another process
+    pub fn snapshot(&self) -> String {
+        let mut output = String::new();
+        self.write_snapshot(&mut output)
+            .expect("Writing to a string failed");
+        output
+    }
+
+    pub fn restore(input: impl AsRef<str>) -> Result<Self, String> {
+        let mut lines = input.as_ref().lines();
+        if lines.next() != Some(HEADER) {
+            return Err("Unsupported format of snapshot".into());
+        }
+        let mut snapshot = Snapshot::default();
+        // Lines are counted from 1 with the header
+        for (i, line) in lines.enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            snapshot
+                .parse_line(line)
+                .map_err(|e| format!("Line: {}, error: {}", i + 2, e))?;
+        }
+        snapshot.build()
+    }
+
+    fn write_snapshot(&self, f: &mut String) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        let policy = match self.config.failure_policy {
+            FailurePolicy::Propagate => "propagate",
+            FailurePolicy::AbortTree => "abort-tree",
+        };
+        writeln!(f, "policy {}", policy)?;
+        match self.config.overflow {
+            Overflow::Checked => {}
+            Overflow::Wrapping => writeln!(f, "overflow wrapping")?,
+            Overflow::Saturating => writeln!(f, "overflow saturating")?,
+        }
+        if let Some(gas) = self.config.limits.gas {
+            writeln!(f, "gas {}", gas)?;
+        }
+        let limits = &self.config.limits;
+        for (name, max) in [
+            ("max-threads", limits.max_threads),
+            ("max-stack", limits.max_stack),
+            ("max-vars", limits.max_vars),
+            ("max-heap", limits.max_heap),
+            ("max-spawn-depth", limits.max_spawn_depth),
+            ("max-messages", limits.max_messages),
+        ] {
+            if let Some(max) = max {
+                writeln!(f, "{} {}", name, max)?;
+            }
+        }
+        writeln!(f, "last-id {}", self.runtime.last_id())?;
+        writeln!(f, "steps {}", self.runtime.steps())?;
+        let mut file = None;
+        for instruction in self.instructions.iter() {
+            if instruction.file() != file {
+                file = instruction.file();
+                match file {
+                    Some(file) => writeln!(f, "file {}", escape(file, true))?,
+                    None => writeln!(f, "file")?,
+                }
+            }
+            writeln!(
+                f,
+                "instruction {} {}",
+                instruction.index(),
+                instruction.instruction()
+            )?;
+        }
+        for info in self.registry().threads() {
+            write!(f, "thread {} {} ", info.id, OptionalId(info.parent))?;
+            match info.state {
+                ThreadState::Running => writeln!(f, "running")?,
+                ThreadState::Finished(data) => writeln!(f, "finished {}", data)?,
+                ThreadState::Failed(e) => {
+                    write!(f, "failed ")?;
+                    write_error(f, &e)?;
+                    writeln!(f)?;
+                }
+            }
+        }
+        for (from, to, data) in self.runtime.pending_messages() {
+            writeln!(f, "message {} {} {}", from, to, data)?;
+        }
+        for (from, to) in self.runtime.delivered_messages() {
+            writeln!(f, "delivered {} {}", from, to)?;
+        }
+        let mut result = write_bytecode(f, self);
+        self.runtime.for_each_bytecode(&mut |bytecode| {
+            if result.is_ok() {
+                result = write_bytecode(f, bytecode);
+            }
+        });
+        result
+    }
+}
+
+fn write_bytecode(f: &mut String, bytecode: &ByteCode) -> fmt::Result {
+    write!(
+        f,
+        "bytecode {} {} {} {} ",
+        bytecode.id,
+        OptionalId(bytecode.parent),
+        bytecode.position,
+        bytecode.steps
+    )?;
+    match bytecode.ret {
+        Some(ret) => writeln!(f, "{}", ret)?,
+        None => writeln!(f, "-")?,
+    }
+    write!(f, "stack")?;
+    for data in &bytecode.stack {
+        write!(f, " {}", data)?;
+    }
+    writeln!(f)?;
+    let mut memory: Vec<_> = bytecode.memory.iter().collect();
+    memory.sort_unstable();
+    for (ident, data) in memory {
+        writeln!(f, "var {} {}", escape(ident, true), data)?;
+    }
+    Ok(())
+}
+
+struct OptionalId(Option<Id>);
+
+impl fmt::Display for OptionalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(id) => write!(f, "{}", id),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+impl FromStr for OptionalId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "-" => Ok(Self(None)),
+            s => Ok(Self(Some(parse(s)?))),
+        }
+    }
+}
+
+struct Bytecode {
+    id: Id,
+    parent: Option<Id>,
+    position: Data,
+    steps: u64,
+    ret: Option<Data>,
+    stack: Stack,
+    memory: Memory,
+}
+
+#[derive(Default)]
+struct Snapshot {
+    config: Config,
+    last_id: Id,
+    steps: u64,
+    // File of the following instructions
+    file: Option<Arc<str>>,
+    instructions: Vec<IndexedInstruction>,
+    threads: Vec<ThreadInfo>,
+    messages: Vec<(Id, Id, Data)>,
+    // Received messages, whose senders haven't gone on yet
+    delivered: Vec<(Id, Id)>,
+    bytecodes: Vec<Bytecode>,
+}
+
+impl Snapshot {
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let mut words = rest.split_ascii_whitespace();
+        let mut word = || {
+            words
+                .next()
+                .ok_or(format!("Not enough operands of `{}`", kind))
+        };
+        match kind {
+            "policy" => {
+                self.config.failure_policy = match word()? {
+                    "propagate" => FailurePolicy::Propagate,
+                    "abort-tree" => FailurePolicy::AbortTree,
+                    policy => return Err(format!("Unknown failure policy `{}`", policy)),
+                }
+            }
+            "overflow" => {
+                self.config.overflow = match word()? {
+                    "checked" => Overflow::Checked,
+                    "wrapping" => Overflow::Wrapping,
+                    "saturating" => Overflow::Saturating,
+                    overflow => return Err(format!("Unknown overflow handling `{}`", overflow)),
+                }
+            }
+            "gas" => self.config.limits.gas = Some(parse(word()?)?),
+            "max-threads" => self.config.limits.max_threads = Some(parse(word()?)?),
+            "max-stack" => self.config.limits.max_stack = Some(parse(word()?)?),
+            "max-vars" => self.config.limits.max_vars = Some(parse(word()?)?),
+            "max-heap" => self.config.limits.max_heap = Some(parse(word()?)?),
+            "max-spawn-depth" => self.config.limits.max_spawn_depth = Some(parse(word()?)?),
+            "max-messages" => self.config.limits.max_messages = Some(parse(word()?)?),
+            "last-id" => self.last_id = parse(word()?)?,
+            "steps" => self.steps = parse(word()?)?,
+            "file" => self.file = (!rest.is_empty()).then(|| unescape(rest).into()),
+            "instruction" => {
+                let index = parse(word()?)?;
+                let instruction = match rest.split_once(' ') {
+                    // A tree may be built from bytes, which aren't instructions
+                    Some((_, UNKNOWN)) => Instruction::Unk,
+                    _ => {
+                        Instruction::try_from(IteratorWrapper(words)).map_err(|e| e.to_string())?
+                    }
+                };
+                self.instructions
+                    .push(IndexedInstruction::new(index, instruction).with_file(self.file.clone()));
+            }
+            "thread" => {
+                let id = parse(word()?)?;
+                let parent = word()?.parse::<OptionalId>()?.0;
+                let state = match word()? {
+                    "running" => ThreadState::Running,
+                    "finished" => ThreadState::Finished(parse(word()?)?),
+                    "failed" => {
+                        let (_, error) = rest.split_once(" failed ").unwrap_or_default();
+                        ThreadState::Failed(parse_error(error)?)
+                    }
+                    state => return Err(format!("Unknown state of thread `{}`", state)),
+                };
+                self.threads.push(ThreadInfo { id, parent, state });
+            }
+            "message" => {
+                let message = (parse(word()?)?, parse(word()?)?, parse(word()?)?);
+                self.messages.push(message);
+            }
+            "delivered" => self.delivered.push((parse(word()?)?, parse(word()?)?)),
+            "bytecode" => {
+                let bytecode = Bytecode {
+                    id: parse(word()?)?,
+                    parent: word()?.parse::<OptionalId>()?.0,
+                    position: parse(word()?)?,
+                    steps: parse(word()?)?,
+                    ret: match word()? {
+                        "-" => None,
+                        ret => Some(parse(ret)?),
+                    },
+                    stack: Stack::new(),
+                    memory: Memory::new(),
+                };
+                self.bytecodes.push(bytecode);
+            }
+            "stack" => {
+                let bytecode = self.bytecodes.last_mut().ok_or("Stack without bytecode")?;
+                bytecode.stack = words.map(parse).collect::<Result<_, _>>()?;
+            }
+            "var" => {
+                let ident = unescape(word()?);
+                let data = parse(word()?)?;
+                let bytecode = self
+                    .bytecodes
+                    .last_mut()
+                    .ok_or("Variable without bytecode")?;
+                bytecode.memory.insert(ident, data);
+            }
+            _ => return Err(format!("Unknown kind of line `{}`", kind)),
+        }
+        Ok(())
+    }
+
+    fn build(self) -> Result<ByteCode, String> {
+        let runtime = Arc::new(Runtime::default());
+        let instructions: Arc<[IndexedInstruction]> = self.instructions.into();
+        let config = Arc::new(self.config);
+        let bytecodes = self.bytecodes.into_iter().map(|bytecode| ByteCode {
+            id: bytecode.id,
+            parent: bytecode.parent,
+            instructions: instructions.clone(),
+            stack: bytecode.stack,
+            memory: bytecode.memory,
+            position: bytecode.position,
+            runtime: runtime.clone(),
+            config: config.clone(),
+            waiting: None,
+            steps: bytecode.steps,
+            ret: bytecode.ret,
+            depth: depth(&self.threads, bytecode.parent),
+            usage: Default::default(),
+        });
+        let mut bytecodes = bytecodes.collect::<Vec<_>>().into_iter();
+        let root = bytecodes.next().ok_or("Snapshot doesn't have bytecodes")?;
+        if root.parent.is_some() {
+            return Err("The first bytecode isn't the root".into());
+        }
+        runtime.restore(
+            self.last_id,
+            self.steps,
+            self.threads,
+            bytecodes.collect(),
+            self.messages,
+            self.delivered,
+        );
+        Ok(root)
+    }
+}
+
+// Count of ancestors of a thread with the parent
+fn depth(threads: &[ThreadInfo], mut parent: Option<Id>) -> usize {
+    let mut depth = 0;
+    while let Some(id) = parent {
+        depth += 1;
+        parent = threads
+            .iter()
+            .find(|info| info.id == id)
+            .and_then(|info| info.parent);
+        if depth > threads.len() {
+            break;
+        }
+    }
+    depth
+}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("Invalid number `{}`", s))
+}
+
+const RESOURCES: [(Resource, &str); 6] = [
+    (Resource::LiveThreads, "threads"),
+    (Resource::StackDepth, "stack"),
+    (Resource::Variables, "vars"),
+    (Resource::HeapBytes, "heap"),
+    (Resource::SpawnDepth, "spawn-depth"),
+    (Resource::MessagesInFlight, "messages"),
+];
+
+fn write_error(f: &mut String, e: &Error) -> fmt::Result {
+    match e {
+        Error::Message(message) => write!(f, "message {}", escape(message, false)),
+        Error::Line {
+            file: None,
+            line,
+            source,
+        } => {
+            write!(f, "line {} ", line)?;
+            write_error(f, source)
+        }
+        Error::Line {
+            file: Some(file),
+            line,
+            source,
+        } => {
+            write!(f, "file-line {} {} ", escape(file, true), line)?;
+            write_error(f, source)
+        }
+        Error::Child { id, source } => {
+            write!(f, "child {} ", id)?;
+            write_error(f, source)
+        }
+        Error::Aborted => write!(f, "aborted"),
+        Error::Deadlock => write!(f, "deadlock"),
+        Error::Cancelled => write!(f, "cancelled"),
+        Error::TimedOut => write!(f, "timed-out"),
+        Error::Exceeded { resource, maximum } => {
+            let (_, name) = RESOURCES.iter().find(|(r, _)| r == resource).unwrap();
+            write!(f, "exceeded {} {}", name, maximum)
+        }
+    }
+}
+
+fn parse_error(s: &str) -> Result<Error, String> {
+    let (kind, rest) = s.split_once(' ').unwrap_or((s, ""));
+    let operand = |rest: &mut &str| {
+        let (word, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+        *rest = tail;
+        match word {
+            "" => Err(format!("Not enough operands of `{}` error", kind)),
+            word => Ok(word.to_string()),
+        }
+    };
+    let mut rest = rest;
+    let error = match kind {
+        "message" => Error::Message(unescape(rest)),
+        "line" => Error::Line {
+            file: None,
+            line: parse(&operand(&mut rest)?)?,
+            source: Box::new(parse_error(rest)?),
+        },
+        "file-line" => Error::Line {
+            file: Some(unescape(&operand(&mut rest)?).into()),
+            line: parse(&operand(&mut rest)?)?,
+            source: Box::new(parse_error(rest)?),
+        },
+        "child" => Error::Child {
+            id: parse(&operand(&mut rest)?)?,
+            source: Box::new(parse_error(rest)?),
+        },
+        "aborted" => Error::Aborted,
+        "deadlock" => Error::Deadlock,
+        "cancelled" => Error::Cancelled,
+        "timed-out" => Error::TimedOut,
+        "exceeded" => {
+            let name = operand(&mut rest)?;
+            let (resource, _) = RESOURCES
+                .iter()
+                .find(|(_, n)| *n == name)
+                .ok_or(format!("Unknown resource `{}`", name))?;
+            Error::Exceeded {
+                resource: *resource,
+                maximum: parse(&operand(&mut rest)?)?,
+            }
+        }
+        kind => return Err(format!("Unknown kind of error `{}`", kind)),
+    };
+    Ok(error)
+}
+
+// Keeps a value on one line, spaces are escaped only in words
+fn escape(s: &str, word: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\n' => escaped.push_str("%0A"),
+            '\r' => escaped.push_str("%0D"),
+            ' ' if word => escaped.push_str("%20"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("%20", " ")
+        .replace("%0D", "\r")
+        .replace("%0A", "\n")
+        .replace("%25", "%")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::{parse_error, write_error};
+    use crate::{ByteCode, Error, IndexedInstruction, Instruction, Resource, Status, ThreadState};
+
+    const POW: &str = r#"
+LOAD_VAL 12
+WRITE_VAR base
+LOAD_VAL 15
+WRITE_VAR exponent
+LOAD_VAL 1
+WRITE_VAR result
+
+READ_VAR exponent
+LOAD_VAL 0
+LOAD_VAL 12
+JUMP_GREATER_THAN
+READ_VAR result
+RETURN_VALUE
+
+READ_VAR result
+READ_VAR base
+MULTIPLY
+WRITE_VAR result
+READ_VAR exponent
+LOAD_VAL 1
+SUB
+WRITE_VAR exponent
+LOAD_VAL 6
+JUMP
+"#;
+
+    const SEND_RECV: &str = r#"
+LOAD_VAL 0
+LOAD_VAL 13
+LOAD_VAL 0
+LOAD_VAL 13
+SPAWN_IDS
+RECV_CHANNEL
+WRITE_VAR b
+RECV_CHANNEL
+LOAD_VAL 10
+MULTIPLY
+READ_VAR b
+ADD
+RETURN_VALUE
+SELF_ID
+PARENT_ID
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+    #[test]
+    fn pow_resumed_from_snapshot() {
+        let mut bytecode = ByteCode::from_bytecode_text(POW).unwrap();
+        assert_eq!(bytecode.interpret_steps(50), Ok(Status::Paused));
+        assert_eq!(bytecode.ret(), None);
+        let snapshot = bytecode.snapshot();
+        assert!(snapshot.contains("\nbytecode 0 - "));
+        assert!(snapshot.contains("\nvar exponent "));
+
+        let mut restored = ByteCode::restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.instructions(), bytecode.instructions());
+        restored.interpret().unwrap();
+        assert_eq!(*restored.ret().unwrap(), 15_407_021_574_586_368);
+
+        // The snapshot doesn't depend on the original bytecode
+        bytecode.interpret().unwrap();
+        assert_eq!(bytecode.ret(), restored.ret());
+    }
+
+    #[test]
+    fn pow_paused_many_times() {
+        let mut bytecode = ByteCode::from_bytecode_text(POW).unwrap();
+        let mut pauses = 0;
+        while bytecode.interpret_steps(7).unwrap() == Status::Paused {
+            bytecode = ByteCode::restore(bytecode.snapshot()).unwrap();
+            pauses += 1;
+        }
+        assert_eq!(pauses, 31);
+        assert_eq!(*bytecode.ret().unwrap(), 15_407_021_574_586_368);
+    }
+
+    #[test]
+    fn threads_and_messages() {
+        let mut bytecode = ByteCode::from_bytecode_text(SEND_RECV).unwrap();
+        bytecode.set_workers(1);
+        // The root receives both messages, the children are paused on sending, before they
+        // have seen that their messages are delivered
+        assert_eq!(bytecode.interpret_steps(12), Ok(Status::Paused));
+        let snapshot = bytecode.snapshot();
+        assert!(snapshot.contains("\nlast-id 2\n"));
+        assert!(snapshot.contains("\nthread 1 0 running\n"));
+        assert!(snapshot.contains("\ndelivered 1 0\ndelivered 2 0\n"));
+        assert!(snapshot.contains("\nbytecode 0 - 8 8 -\nstack 1\nvar b 2\n"));
+        assert!(snapshot.contains("\nbytecode 2 0 15 2 -\nstack 2 0\n"));
+
+        let mut restored = ByteCode::restore(&snapshot).unwrap();
+        assert_eq!(restored.registry().live_threads(), 3);
+        restored.interpret().unwrap();
+        assert_eq!(restored.ret(), Some(&12));
+        assert_eq!(restored.registry().live_threads(), 0);
+    }
+
+    #[test]
+    fn included_files_and_failures() {
+        let dir = env::temp_dir().join(format!("bytecode-snapshot-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("lib/children.bc"),
+            "// fails\nLOAD_VAL 1\nLOAD_VAL 2\nSUB\n// returns\nLOAD_VAL 0\nLOAD_VAL 0\nRETURN_VALUE\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.bc"),
+            "LOAD_VAL 0\nLOAD_VAL 7\nLOAD_VAL 0\nLOAD_VAL 10\nSPAWN\nLOAD_VAL 0\nRETURN_VALUE\n.include \"lib/children.bc\"\n",
+        )
+        .unwrap();
+        let mut bytecode = ByteCode::from_bytecode_file(dir.join("main.bc")).unwrap();
+        bytecode.set_workers(1);
+        // The first child has failed and the second one is paused
+        assert_eq!(bytecode.interpret_steps(10), Ok(Status::Paused));
+        let snapshot = bytecode.snapshot();
+        let child = dir.join("lib").join("children.bc").display().to_string();
+        assert!(snapshot.contains(&format!("\nfile {}\ninstruction 1 LOAD_VAL 1\n", child)));
+        assert!(snapshot.contains(&format!(
+            "\nthread 1 0 failed file-line {} 3 message Substraction overflow occurred (1 - 2)\n",
+            child
+        )));
+
+        let mut restored = ByteCode::restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.instructions(), bytecode.instructions());
+        let failure = restored.registry().thread(1).unwrap().state;
+        assert_eq!(failure, bytecode.registry().thread(1).unwrap().state);
+        assert!(matches!(
+            failure,
+            ThreadState::Failed(Error::Line { file: Some(_), .. })
+        ));
+        let error = restored.interpret().unwrap_err();
+        assert_eq!(error, bytecode.interpret().unwrap_err());
+        assert!(error
+            .to_string()
+            .starts_with(&format!("Thread 1 failed: File: {}", child)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let errors = [
+            Error::Child {
+                id: 3,
+                source: Box::new(Error::Line {
+                    file: Some("lib/my file%.bc".into()),
+                    line: 2,
+                    source: Box::new("Host  function\nfailed: 100%".into()),
+                }),
+            },
+            Error::Line {
+                file: None,
+                line: 7,
+                source: Box::new(Error::Exceeded {
+                    resource: Resource::SpawnDepth,
+                    maximum: 4,
+                }),
+            },
+            Error::Aborted,
+            Error::Deadlock,
+            Error::Cancelled,
+            Error::TimedOut,
+        ];
+        for error in errors {
+            let mut output = String::new();
+            write_error(&mut output, &error).unwrap();
+            assert!(!output.contains('\n'), "{}", output);
+            assert_eq!(parse_error(&output), Ok(error));
+        }
+        assert_eq!(
+            parse_error("exceeded disk 1"),
+            Err("Unknown resource `disk`".into())
+        );
+        assert_eq!(
+            parse_error("child 1"),
+            Err("Unknown kind of error ``".into())
+        );
+    }
+
+    #[test]
+    fn unknown_instruction() {
+        let mut bytecode = ByteCode::new(vec![
+            IndexedInstruction::new(0, Instruction::LoadVal(1)),
+            IndexedInstruction::new(1, Instruction::Unk),
+        ]);
+        assert_eq!(bytecode.interpret_steps(1), Ok(Status::Paused));
+        let snapshot = bytecode.snapshot();
+        assert!(snapshot.contains("\ninstruction 1 UNKNOWN\n"));
+
+        let mut restored = ByteCode::restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.instructions(), bytecode.instructions());
+        let error = restored.interpret().unwrap_err();
+        assert_eq!(error.to_string(), "Line: 2, error: Unknown instruction");
+        assert_eq!(error, bytecode.interpret().unwrap_err());
+    }
+
+    #[test]
+    fn escaped_variables() {
+        let mut bytecode = ByteCode::from_bytecode_text("READ_VAR x\nRETURN_VALUE").unwrap();
+        let ident = "a b%20\nc";
+        bytecode.memory.insert(ident.into(), 7);
+        bytecode.memory.insert("x".into(), 3);
+        let snapshot = bytecode.snapshot();
+        assert!(snapshot.contains("\nvar a%20b%2520%0Ac 7\n"));
+
+        let mut restored = ByteCode::restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.memory, bytecode.memory);
+        restored.interpret().unwrap();
+        assert_eq!(restored.ret(), Some(&3));
+    }
+
+    #[test]
+    fn invalid_snapshot() {
+        assert_eq!(
+            ByteCode::restore("bytecode-snapshot 2\n").unwrap_err(),
+            "Unsupported format of snapshot"
+        );
+        assert_eq!(
+            ByteCode::restore("bytecode-snapshot 1\ninstruction 1 PUSH 1\n").unwrap_err(),
+            "Line: 2, error: Unknown instruction `PUSH`"
+        );
+        assert_eq!(
+            ByteCode::restore("bytecode-snapshot 1\nlast-id 0\n").unwrap_err(),
+            "Snapshot doesn't have bytecodes"
+        );
+    }
+}
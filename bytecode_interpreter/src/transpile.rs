@@ -0,0 +1,396 @@
+// Translation of single-threaded programs to a Rust function, which is compiled instead of
+// interpreted. The stack and the checks of the interpreter are kept, but blocks of instructions
+// are executed one after another, so only jumps dispatch on the position. Variables are locals,
+// which are `None` until they are written. The function returns the same errors as the
+// interpreter, only limits, cancelling and deadlines of the config aren't translated.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+};
+
+use crate::{
+    error::Location, Cfg, Data, EdgeKind, Error, IndexedInstruction, Instruction, Overflow,
+    Successor,
+};
+
+impl crate::ByteCode {
+    // Source of `pub fn <function>(vars: &[(&str, u128)]) -> Result<u128, String>`, where `vars`
+    // are set before the execution
+    pub fn to_rust(&self, function: &str) -> Result<String, Error> {
+        let valid = function.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && function
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("Invalid name of the function `{}`", function).into());
+        }
+        let mut transpiler = Transpiler {
+            output: String::new(),
+            overflow: self.config.overflow,
+            vars: BTreeMap::new(),
+            id: self.id as Data,
+            parent: self.parent.map(|parent| parent as Data),
+        };
+        transpiler.function(function, &self.instructions)?;
+        Ok(transpiler.output)
+    }
+}
+
+struct Transpiler {
+    output: String,
+    overflow: Overflow,
+    // Locals of variables by their names
+    vars: BTreeMap<String, usize>,
+    id: Data,
+    parent: Option<Data>,
+}
+
+// Rust literal of the text
+struct Literal<'a>(&'a str);
+
+impl fmt::Display for Literal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl Transpiler {
+    fn function(&mut self, name: &str, instructions: &[IndexedInstruction]) -> Result<(), Error> {
+        for instruction in instructions {
+            let unsupported = match instruction.instruction() {
+                Instruction::Spawn
+                | Instruction::SpawnIds
+                | Instruction::SendChannel
+                | Instruction::RecvChannel => "only single-threaded programs are supported",
+                Instruction::CallHost(_) => "host functions are registered in the interpreter",
+                Instruction::ReadVar(ident) | Instruction::WriteVar(ident) => {
+                    let next = self.vars.len();
+                    self.vars.entry(ident.clone()).or_insert(next);
+                    continue;
+                }
+                _ => continue,
+            };
+            return Err(Error::Line {
+                file: instruction.file().cloned(),
+                line: instruction.index(),
+                source: Box::new(
+                    format!(
+                        "{} can't be transpiled, {}",
+                        instruction.instruction().mnemonic(),
+                        unsupported
+                    )
+                    .into(),
+                ),
+            });
+        }
+
+        let cfg = Cfg::new(instructions);
+        // A computed jump can land anywhere, so then every instruction starts a block
+        let computed = cfg
+            .edges
+            .iter()
+            .any(|edge| edge.kind != EdgeKind::Spawn && edge.to == Successor::Unknown);
+        let blocks: Vec<_> = match computed {
+            true => (0..instructions.len()).map(|i| (i, i + 1)).collect(),
+            false => cfg.blocks.iter().map(|b| (b.start, b.end)).collect(),
+        };
+
+        let output = &mut self.output;
+        let _ = writeln!(
+            output,
+            "#[allow(unused_mut, unused_variables, unreachable_code)]"
+        );
+        let _ = writeln!(
+            output,
+            "pub fn {}(vars: &[(&str, u128)]) -> Result<u128, String> {{",
+            name
+        );
+        let _ = writeln!(output, "    let mut stack: Vec<u128> = Vec::new();");
+        let mut vars: Vec<_> = self.vars.iter().collect();
+        vars.sort_by_key(|(_, local)| **local);
+        for (ident, local) in &vars {
+            let _ = writeln!(output, "    // {}", ident);
+            let _ = writeln!(output, "    let mut var{}: Option<u128> = None;", local);
+        }
+        let _ = writeln!(output, "    for &(name, value) in vars {{");
+        let _ = writeln!(output, "        match name {{");
+        for (ident, local) in &vars {
+            let _ = writeln!(
+                output,
+                "            {} => var{} = Some(value),",
+                Literal(ident),
+                local
+            );
+        }
+        let _ = writeln!(output, "            _ => {{}}");
+        let _ = writeln!(output, "        }}");
+        let _ = writeln!(output, "    }}");
+        let _ = writeln!(output, "    let mut position: u128 = 0;");
+        let _ = writeln!(output, "    loop {{");
+        let _ = writeln!(output, "        match position {{");
+        for (start, end) in blocks {
+            let _ = writeln!(self.output, "            {} => {{", start);
+            for (position, instruction) in instructions[start..end].iter().enumerate() {
+                self.instruction(start + position, instruction);
+            }
+            if !instructions[end - 1].instruction().is_jump()
+                && *instructions[end - 1].instruction() != Instruction::RetVal
+            {
+                let _ = writeln!(self.output, "                position = {};", end);
+            }
+            let _ = writeln!(self.output, "            }}");
+        }
+        let output = &mut self.output;
+        let _ = writeln!(output, "            _ => {{");
+        let _ = writeln!(
+            output,
+            "                return Err(format!(\"Instruction doesn't exist at {{}} position\", position))"
+        );
+        let _ = writeln!(output, "            }}");
+        let _ = writeln!(output, "        }}");
+        let _ = writeln!(output, "    }}");
+        let _ = writeln!(output, "}}");
+        Ok(())
+    }
+
+    fn instruction(&mut self, position: usize, instruction: &IndexedInstruction) {
+        let prefix = format!(
+            "{}, error: ",
+            Location(instruction.file().map(|f| &**f), instruction.index())
+        );
+        let fail = |message: &str| Literal(&format!("{}{}", prefix, message)).to_string();
+        let pop = |name: &str| {
+            format!(
+                "let {} = stack.pop().ok_or({})?;",
+                name,
+                fail("Stack is empty")
+            )
+        };
+        let arithmetic = |(method, sign, name): (&str, &str, &str), overflow| {
+            let result = match overflow {
+                Overflow::Checked => format!(
+                    "match lhs.checked_{}(rhs) {{ Some(value) => value, None => return \
+                     Err(format!({}, lhs, rhs)) }}",
+                    method,
+                    fail(&format!("{} overflow occurred ({{}} {} {{}})", name, sign)),
+                ),
+                Overflow::Wrapping => format!("lhs.wrapping_{}(rhs)", method),
+                Overflow::Saturating => format!("lhs.saturating_{}(rhs)", method),
+            };
+            vec![pop("rhs"), pop("lhs"), format!("stack.push({});", result)]
+        };
+        let carry = |(method, _, _): (&str, &str, &str)| {
+            vec![
+                pop("rhs"),
+                pop("lhs"),
+                format!("let (value, overflowed) = lhs.overflowing_{}(rhs);", method),
+                "stack.push(value);".into(),
+                "stack.push(u128::from(overflowed));".into(),
+            ]
+        };
+        let comparison = |operator: &str| {
+            vec![
+                pop("target"),
+                pop("rhs"),
+                pop("lhs"),
+                format!(
+                    "let target = if lhs {} rhs {{ target }} else {{ {} }};",
+                    operator,
+                    position + 1
+                ),
+            ]
+        };
+        // Comparisons and logical operations push 1 or 0
+        let condition = |condition: &str| {
+            vec![
+                pop("rhs"),
+                pop("lhs"),
+                format!("stack.push(u128::from({}));", condition),
+            ]
+        };
+        let jump_if = |operator: &str| {
+            vec![
+                pop("target"),
+                pop("condition"),
+                format!(
+                    "let target = if condition {} 0 {{ target }} else {{ {} }};",
+                    operator,
+                    position + 1
+                ),
+            ]
+        };
+        const ADD: (&str, &str, &str) = ("add", "+", "Addition");
+        const SUB: (&str, &str, &str) = ("sub", "-", "Substraction");
+        const MUL: (&str, &str, &str) = ("mul", "*", "Multiplication");
+        let mut lines = match instruction.instruction() {
+            Instruction::LoadVal(value) => vec![format!("stack.push({});", value)],
+            Instruction::WriteVar(ident) => vec![
+                pop("value"),
+                format!("var{} = Some(value);", self.vars[ident]),
+            ],
+            Instruction::ReadVar(ident) => vec![format!(
+                "stack.push(var{}.ok_or({})?);",
+                self.vars[ident],
+                fail(&format!("Variable `{}` doesn't exist", ident))
+            )],
+            Instruction::Add => arithmetic(ADD, self.overflow),
+            Instruction::Sub => arithmetic(SUB, self.overflow),
+            Instruction::Mul => arithmetic(MUL, self.overflow),
+            Instruction::AddWrap => arithmetic(ADD, Overflow::Wrapping),
+            Instruction::SubWrap => arithmetic(SUB, Overflow::Wrapping),
+            Instruction::MulWrap => arithmetic(MUL, Overflow::Wrapping),
+            Instruction::AddSat => arithmetic(ADD, Overflow::Saturating),
+            Instruction::SubSat => arithmetic(SUB, Overflow::Saturating),
+            Instruction::MulSat => arithmetic(MUL, Overflow::Saturating),
+            Instruction::AddCarry => carry(ADD),
+            Instruction::SubCarry => carry(SUB),
+            Instruction::MulCarry => carry(MUL),
+            Instruction::LessThan
+            | Instruction::GreaterThan
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::LessEqual
+            | Instruction::GreaterEqual => {
+                let comparison = instruction.instruction().comparison();
+                let operator = comparison.expect("Instruction is a comparison").operator();
+                condition(&format!("lhs {} rhs", operator))
+            }
+            Instruction::LogicalAnd => condition("lhs != 0 && rhs != 0"),
+            Instruction::LogicalOr => condition("lhs != 0 || rhs != 0"),
+            Instruction::LogicalNot => {
+                vec![pop("value"), "stack.push(u128::from(value == 0));".into()]
+            }
+            Instruction::RetVal => vec![pop("value"), "return Ok(value);".into()],
+            Instruction::Jump => vec![pop("target")],
+            Instruction::JumpLessThan => comparison("<"),
+            Instruction::JumpGreaterThan => comparison(">"),
+            Instruction::JumpEqual => comparison("=="),
+            Instruction::JumpIf => jump_if("!="),
+            Instruction::JumpIfNot => jump_if("=="),
+            Instruction::SelfId => vec![format!("stack.push({});", self.id)],
+            Instruction::ParentId => match self.parent {
+                Some(parent) => vec![format!("stack.push({});", parent)],
+                None => vec![format!(
+                    "return Err(String::from({}));",
+                    fail("Thread doesn't have a parent")
+                )],
+            },
+            Instruction::Log => vec![
+                pop("value"),
+                "println!(\"\\x1b[31mLOG: {}\\x1b[0m\", value);".into(),
+            ],
+            Instruction::Unk => vec![format!(
+                "return Err(String::from({}));",
+                fail("Unknown instruction")
+            )],
+            // They are rejected before
+            Instruction::Spawn
+            | Instruction::SpawnIds
+            | Instruction::SendChannel
+            | Instruction::RecvChannel
+            | Instruction::CallHost(_) => unreachable!(),
+        };
+        if instruction.instruction().is_jump() {
+            lines.push("position = target;".into());
+        }
+
+        let _ = writeln!(
+            self.output,
+            "                // {}: {}",
+            instruction.index() + 1,
+            instruction.instruction()
+        );
+        if let [line] = lines.as_slice() {
+            let _ = writeln!(self.output, "                {}", line);
+            return;
+        }
+        // Every instruction has its own scope, so its operands don't leak into the next one
+        let _ = writeln!(self.output, "                {{");
+        for line in lines {
+            let _ = writeln!(self.output, "                    {}", line);
+        }
+        let _ = writeln!(self.output, "                }}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ByteCode, Error};
+
+    #[test]
+    fn blocks() {
+        let bytecode = ByteCode::from_bytecode_text(
+            "READ_VAR x\nLOAD_VAL 4\nLOAD_VAL 5\nJUMP_LESS_THAN\nLOAD_VAL 1\nRETURN_VALUE",
+        )
+        .unwrap();
+        let rust = bytecode.to_rust("example").unwrap();
+        assert_eq!(
+            rust,
+            r#"#[allow(unused_mut, unused_variables, unreachable_code)]
+pub fn example(vars: &[(&str, u128)]) -> Result<u128, String> {
+    let mut stack: Vec<u128> = Vec::new();
+    // x
+    let mut var0: Option<u128> = None;
+    for &(name, value) in vars {
+        match name {
+            "x" => var0 = Some(value),
+            _ => {}
+        }
+    }
+    let mut position: u128 = 0;
+    loop {
+        match position {
+            0 => {
+                // 1: READ_VAR x
+                stack.push(var0.ok_or("Line: 1, error: Variable `x` doesn't exist")?);
+                // 2: LOAD_VAL 4
+                stack.push(4);
+                // 3: LOAD_VAL 5
+                stack.push(5);
+                // 4: JUMP_LESS_THAN
+                {
+                    let target = stack.pop().ok_or("Line: 4, error: Stack is empty")?;
+                    let rhs = stack.pop().ok_or("Line: 4, error: Stack is empty")?;
+                    let lhs = stack.pop().ok_or("Line: 4, error: Stack is empty")?;
+                    let target = if lhs < rhs { target } else { 4 };
+                    position = target;
+                }
+            }
+            4 => {
+                // 5: LOAD_VAL 1
+                stack.push(1);
+                position = 5;
+            }
+            5 => {
+                // 6: RETURN_VALUE
+                {
+                    let value = stack.pop().ok_or("Line: 6, error: Stack is empty")?;
+                    return Ok(value);
+                }
+            }
+            _ => {
+                return Err(format!("Instruction doesn't exist at {} position", position))
+            }
+        }
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let bytecode = ByteCode::from_bytecode_text("LOAD_VAL 1\nSELF_ID\nSEND_CHANNEL").unwrap();
+        assert_eq!(
+            bytecode.to_rust("f").unwrap_err().to_string(),
+            "Line: 3, error: SEND_CHANNEL can't be transpiled, only single-threaded programs are \
+             supported"
+        );
+        let bytecode = ByteCode::from_bytecode_text("LOAD_VAL 1\nRETURN_VALUE").unwrap();
+        assert_eq!(
+            bytecode.to_rust("1f"),
+            Err(Error::from("Invalid name of the function `1f`"))
+        );
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    ByteCode, CancellationToken, Coverage, CoverageRecorder, Data, Error, FailurePolicy, Id,
+    Limits, Memory, Observer, Overflow, Profile, Profiler, Recording, Schedule, Step, ThreadInfo,
+};
+
+// Order, in which threads of the tree are executed
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum Scheduling {
+    // On the pool of workers
+    #[default]
+    Parallel,
+    // On the current thread, at every scheduling point the runnable thread with the least id
+    // goes on
+    Sequential,
+    // On the current thread in the order of the schedule
+    Schedule(Schedule),
+    // On the current thread in the order of the recorded interactions
+    Replay(Recording),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub ret: Data,
+    // Variables of the root thread
+    pub memory: Memory,
+    // Logged data with the threads, which have logged it
+    pub logs: Vec<(Id, Data)>,
+    // Count of instructions, which all threads have executed
+    pub steps: u64,
+    pub threads: Vec<ThreadInfo>,
+    // Counts and times of executed instructions, if the profiling is enabled
+    pub profile: Option<Profile>,
+    // Executed positions and conditional jumps, if the coverage is enabled
+    pub coverage: Option<Coverage>,
+}
+
+pub struct VmBuilder {
+    bytecode: ByteCode,
+    observer: Option<Arc<dyn Observer>>,
+    scheduling: Scheduling,
+    profile: bool,
+    coverage: bool,
+    timeout: Option<Duration>,
+}
+
+impl VmBuilder {
+    pub fn new(bytecode: ByteCode) -> Self {
+        Self {
+            bytecode,
+            observer: None,
+            scheduling: Scheduling::default(),
+            profile: false,
+            coverage: false,
+            timeout: None,
+        }
+    }
+
+    pub fn from_bytecode_text(input: impl AsRef<str>) -> Result<Self, Vec<String>> {
+        ByteCode::from_bytecode_text(input).map(Self::new)
+    }
+
+    pub fn var(mut self, name: impl Into<String>, value: Data) -> Self {
+        self.bytecode.memory.insert(name.into(), value);
+        self
+    }
+
+    // Arguments are pushed in the order of calls, so the last one is on the top of the stack
+    pub fn arg(mut self, value: Data) -> Self {
+        self.bytecode.stack.push(value);
+        self
+    }
+
+    pub fn args(mut self, values: impl IntoIterator<Item = Data>) -> Self {
+        self.bytecode.stack.extend(values);
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.bytecode.set_limits(limits);
+        self
+    }
+
+    pub fn failure_policy(mut self, policy: FailurePolicy) -> Self {
+        self.bytecode.set_failure_policy(policy);
+        self
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.bytecode.set_overflow(overflow);
+        self
+    }
+
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.bytecode.set_workers(workers);
+        self
+    }
+
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
+
+    // Instructions are timed, which slows down the execution
+    pub fn profile(mut self) -> Self {
+        self.profile = true;
+        self
+    }
+
+    pub fn coverage(mut self) -> Self {
+        self.coverage = true;
+        self
+    }
+
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.bytecode.set_cancellation(token);
+        self
+    }
+
+    // The time is measured from the start of the run
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn host_function(
+        mut self,
+        name: impl Into<String>,
+        arity: usize,
+        function: impl Fn(&[Data]) -> Result<Data, Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.bytecode.register_host_function(name, arity, function);
+        self
+    }
+
+    pub fn run(self) -> Result<Outcome, Error> {
+        let Self {
+            mut bytecode,
+            observer,
+            scheduling,
+            profile,
+            coverage,
+            timeout,
+        } = self;
+        let collector = Arc::new(Collector {
+            logs: Mutex::default(),
+            profiler: profile.then(Profiler::new),
+            coverage: coverage.then(CoverageRecorder::new),
+            observer,
+        });
+        bytecode.set_observer(collector.clone());
+        if let Some(timeout) = timeout {
+            bytecode.set_deadline(Instant::now() + timeout);
+        }
+        match &scheduling {
+            Scheduling::Parallel => bytecode.interpret(),
+            Scheduling::Sequential => bytecode.interpret_deterministic(&mut |ids| Ok(ids[0]), None),
+            Scheduling::Schedule(schedule) => bytecode.interpret_schedule(schedule),
+            Scheduling::Replay(recording) => bytecode.replay(recording),
+        }?;
+        let registry = bytecode.registry();
+        let logs = std::mem::take(&mut *collector.logs.lock().unwrap());
+        Ok(Outcome {
+            ret: bytecode.ret.ok_or("Root thread hasn't returned")?,
+            memory: bytecode.memory,
+            logs,
+            steps: registry.steps(),
+            threads: registry.threads(),
+            profile: collector.profiler.as_ref().map(Profiler::profile),
+            coverage: collector.coverage.as_ref().map(CoverageRecorder::coverage),
+        })
+    }
+}
+
+// Keeps logs, the profile and the coverage for the outcome and passes everything to the observer
+// of the embedder
+struct Collector {
+    logs: Mutex<Vec<(Id, Data)>>,
+    profiler: Option<Profiler>,
+    coverage: Option<CoverageRecorder>,
+    observer: Option<Arc<dyn Observer>>,
+}
+
+impl Observer for Collector {
+    fn step(&self, step: &Step) {
+        if let Some(profiler) = &self.profiler {
+            profiler.step(step);
+        }
+        if let Some(coverage) = &self.coverage {
+            coverage.step(step);
+        }
+        if let Some(observer) = &self.observer {
+            observer.step(step);
+        }
+    }
+
+    fn flush(&self, thread: Id) {
+        if let Some(profiler) = &self.profiler {
+            profiler.flush(thread);
+        }
+        if let Some(coverage) = &self.coverage {
+            coverage.flush(thread);
+        }
+        if let Some(observer) = &self.observer {
+            observer.flush(thread);
+        }
+    }
+
+    fn timed(&self) -> bool {
+        self.profiler.is_some() || self.observer.as_ref().is_some_and(|o| o.timed())
+    }
+
+    fn log(&self, thread: Id, data: Data) {
+        self.logs.lock().unwrap().push((thread, data));
+        if let Some(observer) = &self.observer {
+            observer.log(thread, data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        ByteCode, CancellationToken, Error, Limits, Observer, Scheduling, Step, ThreadInfo,
+        ThreadState, VmBuilder,
+    };
+
+    // return x * y + z, where z is the only argument
+    const LINEAR: &str = r#"
+WRITE_VAR z
+READ_VAR x
+READ_VAR y
+MULTIPLY
+READ_VAR z
+ADD
+WRITE_VAR result
+READ_VAR result
+LOG
+READ_VAR result
+RETURN_VALUE
+"#;
+
+    // spawn two children, which log and send their arguments to the parent
+    const CHILDREN: &str = r#"
+LOAD_VAL 3
+LOAD_VAL 4
+LOAD_VAL 1
+LOAD_VAL 13
+LOAD_VAL 1
+LOAD_VAL 13
+SPAWN_IDS
+RECV_CHANNEL
+WRITE_VAR b
+RECV_CHANNEL
+READ_VAR b
+ADD
+RETURN_VALUE
+WRITE_VAR a
+READ_VAR a
+LOG
+READ_VAR a
+PARENT_ID
+SEND_CHANNEL
+LOAD_VAL 0
+RETURN_VALUE
+"#;
+
+    #[derive(Default)]
+    struct Counter(AtomicU64);
+
+    impl Observer for Counter {
+        fn step(&self, _step: &Step) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn variables_and_arguments() {
+        let outcome = VmBuilder::from_bytecode_text(LINEAR)
+            .unwrap()
+            .var("x", 6)
+            .var("y", 7)
+            .arg(100)
+            .run()
+            .unwrap();
+        assert_eq!(outcome.ret, 142);
+        assert_eq!(outcome.memory["result"], 142);
+        assert_eq!(outcome.memory.len(), 4);
+        assert_eq!(outcome.logs, vec![(0, 142)]);
+        assert_eq!(outcome.steps, 11);
+        assert_eq!(
+            outcome.threads,
+            vec![ThreadInfo {
+                id: 0,
+                parent: None,
+                state: ThreadState::Finished(142)
+            }]
+        );
+    }
+
+    #[test]
+    fn threads_and_logs() {
+        let counter = Arc::new(Counter::default());
+        for scheduling in [Scheduling::Parallel, Scheduling::Sequential] {
+            let outcome = VmBuilder::from_bytecode_text(CHILDREN)
+                .unwrap()
+                .scheduling(scheduling)
+                .observer(counter.clone())
+                .run()
+                .unwrap();
+            assert_eq!(outcome.ret, 7);
+            let mut logs = outcome.logs;
+            logs.sort_unstable();
+            assert_eq!(logs, vec![(1, 3), (2, 4)]);
+            assert_eq!(outcome.steps, 13 + 2 * 8);
+            assert_eq!(
+                outcome.threads[1].state,
+                ThreadState::Finished(0),
+                "{:?}",
+                outcome.threads
+            );
+        }
+        assert_eq!(counter.0.load(Ordering::Relaxed), 2 * (13 + 2 * 8));
+    }
+
+    #[test]
+    fn profile() {
+        let outcome = VmBuilder::from_bytecode_text(CHILDREN)
+            .unwrap()
+            .profile()
+            .run()
+            .unwrap();
+        let profile = outcome.profile.unwrap();
+        assert_eq!(profile.total().count, outcome.steps);
+        assert_eq!(profile.threads.len(), 3);
+        assert_eq!(profile.threads[&1].total().count, 8);
+        assert_eq!(profile.opcodes().len(), 10);
+        assert!(profile.loops().is_empty());
+        assert!(VmBuilder::from_bytecode_text(LINEAR)
+            .unwrap()
+            .arg(0)
+            .var("x", 0)
+            .var("y", 0)
+            .run()
+            .unwrap()
+            .profile
+            .is_none());
+    }
+
+    #[test]
+    fn limits() {
+        let run = |limits| {
+            VmBuilder::from_bytecode_text(CHILDREN)
+                .unwrap()
+                .limits(limits)
+                .run()
+                .map(|outcome| outcome.ret)
+        };
+        assert_eq!(
+            run(Limits {
+                gas: Some(20),
+                ..Default::default()
+            })
+            .unwrap_err()
+            .to_string(),
+            "Limit of steps is exceeded (maximum is 20)"
+        );
+        assert_eq!(
+            run(Limits {
+                gas: Some(29),
+                ..Default::default()
+            }),
+            Ok(7)
+        );
+        assert_eq!(
+            run(Limits {
+                max_threads: Some(2),
+                ..Default::default()
+            })
+            .unwrap_err()
+            .to_string(),
+            "Line: 8, error: Limit of live threads is exceeded (maximum is 2)"
+        );
+        let exceeded = |limits| run(limits).unwrap_err().to_string();
+        assert_eq!(
+            exceeded(Limits {
+                max_stack: Some(5),
+                ..Default::default()
+            }),
+            "Line: 7, error: Limit of stack depth is exceeded (maximum is 5)"
+        );
+        assert_eq!(
+            exceeded(Limits {
+                max_heap: Some(80),
+                ..Default::default()
+            }),
+            "Line: 7, error: Limit of heap bytes is exceeded (maximum is 80)"
+        );
+        assert_eq!(
+            exceeded(Limits {
+                max_spawn_depth: Some(0),
+                ..Default::default()
+            }),
+            "Line: 8, error: Limit of spawn depth is exceeded (maximum is 0)"
+        );
+        // Children inherit limits
+        assert_eq!(
+            exceeded(Limits {
+                max_vars: Some(0),
+                ..Default::default()
+            }),
+            "Line: 9, error: Thread 2 failed: Line: 15, error: \
+             Limit of variables is exceeded (maximum is 0)"
+        );
+        assert_eq!(
+            exceeded(Limits {
+                max_messages: Some(0),
+                ..Default::default()
+            }),
+            "Line: 9, error: Thread 2 failed: Line: 20, error: \
+             Limit of messages in flight is exceeded (maximum is 0)"
+        );
+        assert_eq!(
+            run(Limits {
+                max_stack: Some(6),
+                max_vars: Some(1),
+                max_heap: Some(200),
+                max_spawn_depth: Some(1),
+                max_messages: Some(2),
+                ..Default::default()
+            }),
+            Ok(7)
+        );
+    }
+
+    #[test]
+    fn cancellation_and_timeout() {
+        // the root waits for children, which loop forever
+        const ENDLESS: &str = "LOAD_VAL 0\nLOAD_VAL 7\nLOAD_VAL 0\nLOAD_VAL 7\nSPAWN_IDS\n\
+                               RECV_CHANNEL\nRETURN_VALUE\nLOAD_VAL 7\nJUMP\n";
+        let token = CancellationToken::new();
+        let mut bytecode = ByteCode::from_bytecode_text(ENDLESS).unwrap();
+        bytecode.set_workers(2);
+        bytecode.set_cancellation(token.clone());
+        let cancel = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        assert_eq!(bytecode.interpret(), Err(Error::Cancelled));
+        cancel.join().unwrap();
+        let registry = bytecode.registry();
+        assert_eq!(registry.live_threads(), 0);
+        assert!(registry
+            .threads()
+            .iter()
+            .all(|info| info.state == ThreadState::Failed(Error::Cancelled)));
+
+        for scheduling in [Scheduling::Parallel, Scheduling::Sequential] {
+            let result = VmBuilder::from_bytecode_text(ENDLESS)
+                .unwrap()
+                .scheduling(scheduling)
+                .timeout(Duration::from_millis(20))
+                .run();
+            assert_eq!(result, Err(Error::TimedOut));
+        }
+    }
+
+    #[test]
+    fn host_functions() {
+        let outcome = VmBuilder::from_bytecode_text("CALL_HOST answer\nRETURN_VALUE\n")
+            .unwrap()
+            .host_function("answer", 0, |_| Ok(42))
+            .run()
+            .unwrap();
+        assert_eq!(outcome.ret, 42);
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    env, fs,
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+const POW: &str = r#"
+// return base ^ exponent
+LOAD_VAL 1
+WRITE_VAR result
+READ_VAR exponent
+LOAD_VAL 0
+LOAD_VAL 8
+JUMP_GREATER_THAN
+READ_VAR result
+RETURN_VALUE
+READ_VAR result
+READ_VAR base
+MULTIPLY
+WRITE_VAR result
+READ_VAR exponent
+LOAD_VAL 1
+SUB
+WRITE_VAR exponent
+LOAD_VAL 2
+JUMP
+"#;
+
+fn bytecode(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bytecode"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn run_from_stdin() {
+    let output = bytecode(&["run", "--var", "base=3", "--var", "exponent=4"], POW);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "81\n");
+}
+
+#[test]
+fn run_file() {
+    let path = std::env::temp_dir().join(format!("bytecode-cli-{}.bc", std::process::id()));
+    std::fs::write(&path, "LOAD_VAL 5\nLOG\nLOAD_VAL 7\nRETURN_VALUE\n").unwrap();
+    let output = bytecode(&["run", path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "7\n");
+    assert_eq!(stderr(&output), "LOG [0]: 5\n");
+}
+
+#[test]
+fn trace_and_gas() {
+    let output = bytecode(&["run", "--trace", "-"], "LOAD_VAL 2\nRETURN_VALUE\n");
+    assert_eq!(stdout(&output), "2\n");
+    assert_eq!(
+        stderr(&output),
+        "[0]    0: LOAD_VAL 2               [2]\n[0]    1: RETURN_VALUE             []\n"
+    );
+
+    let args = [
+        "run",
+        "--gas",
+        "10",
+        "--var",
+        "base=3",
+        "--var",
+        "exponent=4",
+    ];
+    let output = bytecode(&args, POW);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "Error: Limit of steps is exceeded (maximum is 10)\n"
+    );
+}
+
+#[test]
+fn overflow() {
+    let args = [
+        "run",
+        "--overflow",
+        "wrapping",
+        "--var",
+        "base=2",
+        "--var",
+        "exponent=130",
+    ];
+    let output = bytecode(&args, POW);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "0\n");
+
+    let output = bytecode(&["run", "--overflow", "modular"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("Invalid overflow `modular`\n"));
+}
+
+#[test]
+fn runtime_error() {
+    let output = bytecode(&["run"], POW);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "Error: Line: 5, error: Variable `exponent` doesn't exist\n"
+    );
+}
+
+#[test]
+fn check_and_disasm() {
+    let output = bytecode(&["check"], POW);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "OK, 18 instructions\n");
+
+    let output = bytecode(&["check"], "LOAD_VAL\nCALL_HOST f\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        "<input>:1:10: error: Empty operand for LOAD_VAL\n  |\n1 | LOAD_VAL\n  |          ^\n"
+    );
+
+    let output = bytecode(&["check"], "CALL_HOST f\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        "Line: 1, error: Host function `f` isn't registered\n"
+    );
+
+    let output = bytecode(&["disasm"], "\nLOAD_VAL 1\n\nRETURN_VALUE\n");
+    assert_eq!(
+        stdout(&output),
+        "position     line  instruction\n       0        2  LOAD_VAL 1\n       1        4  RETURN_VALUE\n"
+    );
+}
+
+#[test]
+fn repl() {
+    let output = bytecode(
+        &["repl"],
+        "LOAD_VAL 2\nLOAD_VAL 3\nADD\nSUB\n:undo\nWRITE_VAR x\n:memory\n:quit\nLOAD_VAL 1\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "[2]\n[2, 3]\n[5]\nError: Line: 4, error: Stack is empty\n[2, 3]\n[2]\nx = 3\n"
+    );
+}
+
+#[test]
+fn usage_errors() {
+    assert_eq!(bytecode(&[], "").status.code(), Some(2));
+    assert_eq!(bytecode(&["build"], "").status.code(), Some(2));
+    assert_eq!(bytecode(&["run", "--gas"], "").status.code(), Some(2));
+    assert_eq!(bytecode(&["disasm", "--trace"], "").status.code(), Some(2));
+}
+
+#[test]
+fn profile() {
+    let folded = env::temp_dir().join(format!("bytecode-folded-{}.txt", std::process::id()));
+    let output = bytecode(
+        &[
+            "run",
+            "--var",
+            "base=3",
+            "--var",
+            "exponent=4",
+            "--profile",
+            "--folded",
+            folded.to_str().unwrap(),
+        ],
+        POW,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "81\n");
+    let report = stderr(&output);
+    assert!(report.starts_with("Profile: 64 steps, "), "{}", report);
+    assert!(report.contains("\nLoops:\n       count  back-edge\n           4  <input>:20 -> <input>:5 (position 17 -> 2)\n"), "{}", report);
+    let folded = fs::read_to_string(&folded).unwrap();
+    assert_eq!(folded.lines().count(), 18);
+    assert!(
+        folded.starts_with("thread 0;<input>:3;LOAD_VAL "),
+        "{}",
+        folded
+    );
+}
+
+#[test]
+fn coverage() {
+    let lcov = env::temp_dir().join(format!("bytecode-lcov-{}.info", std::process::id()));
+    let args = ["run", "--var", "base=3", "--var", "exponent=0"];
+    let output = bytecode(
+        &[&args[..], &["--coverage", lcov.to_str().unwrap()]].concat(),
+        POW,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let lcov = fs::read_to_string(&lcov).unwrap();
+    assert!(
+        lcov.starts_with("TN:\nSF:<input>\nBRDA:8,5,0,0\nBRDA:8,5,1,1\nBRF:2\nBRH:1\nDA:3,1\n"),
+        "{}",
+        lcov
+    );
+    assert!(
+        lcov.ends_with("DA:20,0\nLF:18\nLH:8\nend_of_record\n"),
+        "{}",
+        lcov
+    );
+}
+
+#[test]
+fn json() {
+    let output = bytecode(&["json"], POW);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let json = stdout(&output);
+    assert!(
+        json.contains(r#"{"opcode": "MULTIPLY", "line": 13}"#),
+        "{}",
+        json
+    );
+
+    let path = env::temp_dir().join(format!("bytecode-cli-{}.json", std::process::id()));
+    fs::write(&path, &json).unwrap();
+    let output = bytecode(
+        &[
+            "run",
+            "--var",
+            "base=2",
+            "--var",
+            "exponent=5",
+            path.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "32\n");
+
+    fs::write(&path, json.replace("MULTIPLY", "MULTIPLYY")).unwrap();
+    let output = bytecode(&["check", path.to_str().unwrap()], "");
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        format!(
+            "{}: $.instructions[10].opcode: Unknown instruction `MULTIPLYY`, did you mean `MULTIPLY`?\n",
+            path.display()
+        )
+    );
+
+    let output = bytecode(&["disasm"], &json);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn rust() {
+    let output = bytecode(&["rust"], POW);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output)
+        .starts_with("#[allow(unused_mut, unused_variables, unreachable_code)]\npub fn program("));
+
+    let output = bytecode(&["rust"], "LOAD_VAL 1\nRECV_CHANNEL\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        "Line: 2, error: RECV_CHANNEL can't be transpiled, only single-threaded programs are \
+         supported\n"
+    );
+}
+
+#[test]
+fn registers() {
+    let output = bytecode(&["registers"], POW);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let registers = stdout(&output);
+    assert!(registers.starts_with("// r0: result\n// r1: exponent\n// r2: base\n"));
+    assert!(registers.contains("  r0 = r3 * r4\n"), "{}", registers);
+}
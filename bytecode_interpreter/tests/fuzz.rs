@@ -0,0 +1,24 @@
+use std::{fs, path::Path, time::Duration};
+
+use bytecode_interpreter::fuzz;
+
+// Inputs, which have made targets panic or hang, and their neighbours are checked on every run
+#[test]
+fn corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    for (target, function) in fuzz::TARGETS {
+        let mut inputs = 0;
+        for entry in fs::read_dir(corpus.join(target)).unwrap() {
+            let path = entry.unwrap().path();
+            let input = fs::read(&path).unwrap();
+            assert_eq!(
+                fuzz::check(function, &input, Duration::from_secs(30)),
+                Ok(()),
+                "{}",
+                path.display()
+            );
+            inputs += 1;
+        }
+        assert!(inputs > 0, "Corpus of `{}` target is empty", target);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{env, fs, process::Command};
+
+use bytecode_interpreter::{ByteCode, Overflow, VmBuilder};
+
+// Name, source, variables and the handling of overflows
+type Program = (
+    &'static str,
+    &'static str,
+    &'static [(&'static str, u128)],
+    Overflow,
+);
+
+// Copies of programs of the unit and CLI tests in `tests/programs`
+macro_rules! program {
+    ($name:literal) => {
+        include_str!(concat!("programs/", $name, ".bc"))
+    };
+}
+
+const POW: &str = program!("pow_of_vars");
+const MAX: &str = program!("max_countdown");
+
+// Programs of other tests with their variables, they are interpreted and transpiled, and both
+// results have to be the same
+const PROGRAMS: &[Program] = &[
+    (
+        "pow",
+        POW,
+        &[("base", 3), ("exponent", 4)],
+        Overflow::Checked,
+    ),
+    (
+        "pow_overflow",
+        POW,
+        &[("base", 2), ("exponent", 200)],
+        Overflow::Checked,
+    ),
+    (
+        "pow_wrapping",
+        POW,
+        &[("base", 3), ("exponent", 100)],
+        Overflow::Wrapping,
+    ),
+    (
+        "pow_saturating",
+        POW,
+        &[("base", 3), ("exponent", 100)],
+        Overflow::Saturating,
+    ),
+    ("pow_missing", POW, &[("base", 3)], Overflow::Checked),
+    ("max", MAX, &[("a", 5), ("b", 9)], Overflow::Checked),
+    ("countdown", program!("countdown"), &[], Overflow::Checked),
+    (
+        "parse_and_interpret",
+        program!("parse_and_interpret"),
+        &[],
+        Overflow::Checked,
+    ),
+    ("jump_ret_x", program!("jump_ret_x"), &[], Overflow::Checked),
+    (
+        "jump_ret_sum_of_xyw",
+        program!("jump_ret_sum_of_xyw"),
+        &[],
+        Overflow::Checked,
+    ),
+    (
+        "jump_and_jump",
+        program!("jump_and_jump"),
+        &[],
+        Overflow::Checked,
+    ),
+    (
+        "jump_less_than_0",
+        program!("jump_less_than_0"),
+        &[],
+        Overflow::Checked,
+    ),
+    (
+        "jump_less_than_1",
+        program!("jump_less_than_1"),
+        &[],
+        Overflow::Checked,
+    ),
+    (
+        "jump_greater_than_0",
+        program!("jump_greater_than_0"),
+        &[],
+        Overflow::Checked,
+    ),
+    (
+        "jump_greater_than_1",
+        program!("jump_greater_than_1"),
+        &[],
+        Overflow::Checked,
+    ),
+    ("jump_equal", program!("jump_equal"), &[], Overflow::Checked),
+    ("pow_of_constants", program!("pow"), &[], Overflow::Checked),
+    (
+        "fibonacci",
+        program!("fibonacci_space_optimized"),
+        &[],
+        Overflow::Checked,
+    ),
+    (
+        "overflow",
+        include_str!("corpus/text/overflow.bc"),
+        &[],
+        Overflow::Checked,
+    ),
+    ("arithmetic", ARITHMETIC, &[], Overflow::Checked),
+    (
+        "conditions",
+        CONDITIONS,
+        &[("n", 0), ("done", 0)],
+        Overflow::Checked,
+    ),
+    (
+        "conditions_done",
+        CONDITIONS,
+        &[("n", 5), ("done", 2)],
+        Overflow::Checked,
+    ),
+    (
+        "conditions_not_done",
+        CONDITIONS,
+        &[("n", 5), ("done", 0)],
+        Overflow::Checked,
+    ),
+    ("computed", COMPUTED, &[("x", 3)], Overflow::Checked),
+    ("computed_middle", COMPUTED, &[("x", 4)], Overflow::Checked),
+    ("computed_beyond", COMPUTED, &[("x", 7)], Overflow::Checked),
+    (
+        "ids",
+        "SELF_ID\nPARENT_ID\nADD\nRETURN_VALUE",
+        &[],
+        Overflow::Checked,
+    ),
+    (
+        "empty_stack",
+        "LOAD_VAL 1\nADD\nRETURN_VALUE",
+        &[],
+        Overflow::Checked,
+    ),
+    ("end", "LOAD_VAL 1\nWRITE_VAR x", &[], Overflow::Checked),
+    ("empty", "", &[], Overflow::Checked),
+];
+
+const ARITHMETIC: &str = r#"
+LOAD_VAL 340282366920938463463374607431768211455
+LOAD_VAL 2
+ADD_CARRY
+LOG
+LOG
+LOAD_VAL 1
+LOAD_VAL 2
+SUB_WRAP
+LOG
+LOAD_VAL 1
+LOAD_VAL 2
+SUB_SAT
+LOG
+LOAD_VAL 340282366920938463463374607431768211455
+LOAD_VAL 3
+MULTIPLY_SAT
+LOAD_VAL 3
+MULTIPLY_CARRY
+ADD
+RETURN_VALUE
+"#;
+
+// return (n <= 1 || done ? 10 : 20) + !n, comparisons are logged
+const CONDITIONS: &str = r#"
+READ_VAR n
+LOAD_VAL 1
+LE
+READ_VAR done
+LOGICAL_OR
+LOAD_VAL 10
+JUMP_IF
+LOAD_VAL 20
+LOAD_VAL 11
+JUMP
+LOAD_VAL 10
+READ_VAR n
+LOGICAL_NOT
+ADD
+READ_VAR n
+LOAD_VAL 3
+LT
+READ_VAR done
+LOAD_VAL 2
+GE
+LOGICAL_AND
+LOG
+READ_VAR n
+READ_VAR done
+NE
+LOAD_VAL 27
+JUMP_IF_NOT
+RETURN_VALUE
+"#;
+
+// The target of the jump is read from the variable, so it can land in the middle of a block
+const COMPUTED: &str = r#"
+LOAD_VAL 10
+READ_VAR x
+JUMP
+LOAD_VAL 5
+ADD
+RETURN_VALUE
+"#;
+
+#[test]
+fn transpiled_programs_match_interpreter() {
+    let mut source = String::new();
+    let mut main = String::from("fn main() {\n");
+    let mut expected = String::new();
+    for (name, program, vars, overflow) in PROGRAMS {
+        let mut bytecode = ByteCode::from_bytecode_text(program).unwrap();
+        bytecode.set_overflow(*overflow);
+        source.push_str(&bytecode.to_rust(name).unwrap());
+
+        let mut vm = VmBuilder::new(bytecode).overflow(*overflow);
+        for (var, value) in *vars {
+            vm = vm.var(*var, *value);
+        }
+        let result = match vm.run() {
+            Ok(outcome) => {
+                for (_, data) in outcome.logs {
+                    expected.push_str(&format!("\x1b[31mLOG: {}\x1b[0m\n", data));
+                }
+                Ok(outcome.ret)
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        expected.push_str(&format!("{}: {:?}\n", name, result));
+        main.push_str(&format!(
+            "    println!(\"{{}}: {{:?}}\", {:?}, {}(&{:?}));\n",
+            name, name, vars
+        ));
+    }
+    main.push_str("}\n");
+    source.push_str(&main);
+
+    let dir = env::temp_dir().join(format!("bytecode-transpile-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("programs.rs");
+    fs::write(&file, source).unwrap();
+    let binary = dir.join("programs");
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let output = Command::new(rustc)
+        .args(["--edition", "2021", "-o"])
+        .arg(&binary)
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = Command::new(&binary).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}